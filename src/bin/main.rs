@@ -1,7 +1,9 @@
 use clap::clap_app;
+use hyper::{body, Body, Client};
+use hyper_tls::HttpsConnector;
 use log::{error, info, LevelFilter};
-use std::{error, process::exit};
-use tokio::{fs::OpenOptions};
+use std::{convert::TryFrom, error, path::Path, process::exit, str, time::Duration};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
 
 use maguro;
 
@@ -28,6 +30,56 @@ mod maglog {
 
 static LOGGER: maglog::MagnetLogger = maglog::MagnetLogger;
 
+/// Parses a timestamp of the form `[[HH:]MM:]SS[.fff]`.
+fn parse_timestamp(s: &str) -> Option<Duration> {
+    let mut secs = 0.0;
+    for part in s.split(':') {
+        secs = secs * 60.0
+            + part
+                .parse::<f64>()
+                .ok()
+                .filter(|p| p.is_finite() && *p >= 0.0)?;
+    }
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// The path to write the video `id` to, given as `output`. Any `{id}` in it
+/// is replaced by the ID; failing that, when `several` videos are written,
+/// the ID is added ahead of the extension so that each gets a file of its
+/// own.
+fn output_path(output: &str, id: &str, several: bool) -> String {
+    if output.contains("{id}") {
+        return output.replace("{id}", id);
+    }
+    if !several {
+        return output.to_string();
+    }
+    let p = Path::new(output);
+    match (p.file_stem(), p.extension()) {
+        (Some(stem), Some(ext)) => p
+            .with_file_name(format!(
+                "{}.{}.{}",
+                stem.to_string_lossy(),
+                id,
+                ext.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{}.{}", output, id),
+    }
+}
+
+/// Parses a section of the form `START-END`.
+fn parse_section(s: &str) -> Option<(Duration, Duration)> {
+    let (start, end) = s.split_once('-')?;
+    let (start, end) = (parse_timestamp(start)?, parse_timestamp(end)?);
+    if start < end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<(dyn error::Error + 'static)>> {
     let matches = clap_app!(maguro =>
@@ -36,9 +88,19 @@ async fn main() -> Result<(), Box<(dyn error::Error + 'static)>> {
         (about: "A fast YouTube downloader.")
         (@arg verbose: -v ... "Increases program verbosity")
         (@arg show_formats: -F --formats "Display formats available for download and exit")
-        (@arg format: -f +takes_value "Downloads a specific format by `itag`. Defaults to highest quality.")
-        (@arg output: -o --output +takes_value "Outputs the selected stream to the given file")
-        (@arg VIDEOS: +required "Video to download or introspect on")
+        (@arg format: -f +takes_value "Downloads a specific format by `itag`, or merges a `VIDEO+AUDIO` pair. Defaults to highest quality.")
+        (@arg output: -o --output +takes_value "Outputs the selected stream to the given file. Any `{id}` in it is replaced by the video's ID; otherwise, each of several videos is written with its ID ahead of the extension")
+        (@arg embed_metadata: --("embed-metadata") "Embeds the title, author, date, description and thumbnail into the output. WebM output gets no thumbnail.")
+        (@arg section: --section +takes_value "Downloads only the segments covering a time range, such as `1:00-1:30`")
+        (@arg dash_manifest: --("dash-manifest") "Writes a DASH manifest of the video's adaptive formats to the output, or stdout, and exits")
+        (@arg rotate_minutes: --("rotate-minutes") +takes_value "Splits the recording of a live stream into numbered files of this many minutes each")
+        (@arg rotate_size: --("rotate-size") +takes_value "Splits the recording of a live stream into numbered files of at most this many megabytes each")
+        (@arg live_from_start: --("live-from-start") requires[format] "Records the live stream format chosen with -f from the beginning of the broadcast, rather than from now")
+        (@arg wait_for_video: --("wait-for-video") "Waits for upcoming premieres and scheduled live streams to start, then downloads them")
+        (@arg remux: --remux "Rewrites fragmented MP4 or MPEG-TS output as a progressive MP4 (or M4A) with faststart, including each file of a live recording")
+        (@arg manifest: --manifest +takes_value "Lists the representations of the DASH manifest at the given URL and exits. With -f, downloads the representation with that ID instead, joining it across periods")
+        (@arg period: --period +takes_value requires[manifest] "Downloads a manifest representation from only the period at the given index")
+        (@arg VIDEOS: required_unless[manifest] "Video, playlist or channel to download or introspect on, by ID or URL")
     )
     .get_matches();
 
@@ -53,73 +115,487 @@ async fn main() -> Result<(), Box<(dyn error::Error + 'static)>> {
         panic!("Failed to initialize logger! {}", e)
     }
 
+    // Outputs the contents of a DASH manifest, or downloads from it, then
+    // exits.
+    if let Some(url) = matches.value_of("manifest") {
+        let manifest = fetch_manifest(url).await?;
+        let id = match matches.value_of("format") {
+            Some(id) => id,
+            None => {
+                show_manifest(&manifest);
+                exit(0)
+            }
+        };
+        let path = matches.value_of("output").unwrap_or_else(|| {
+            println!("Please specify an output file.");
+            exit(1)
+        });
+        println!("Starting download of representation {}...", id);
+        if let Err(e) = download_manifest(&manifest, id, matches.value_of("period"), path).await {
+            error!("{}", e);
+            exit(1)
+        }
+        println!("Completed download of representation {}.", id);
+        exit(0)
+    }
+
     // Get video and output ID information.
-    let mut ids: Vec<&str> = matches
+    let mut ids: Vec<String> = Vec::new();
+    for video in matches
         .values_of("VIDEOS")
         .unwrap_or_else(|| panic!("A list of video IDs is required!"))
-        .collect();
+    {
+        match video.parse::<maguro::query::Target>() {
+            Ok(maguro::query::Target::Video(id)) => ids.push(id),
+            Ok(maguro::query::Target::Playlist(id)) => {
+                let playlist = match maguro::query::Playlist::from_id(&id).await {
+                    Ok(playlist) => playlist,
+                    Err(e) => {
+                        error!("{}", e);
+                        exit(1)
+                    }
+                };
+                println!(
+                    "Expanded playlist {} into {} videos.",
+                    playlist.title(),
+                    playlist.entries().len()
+                );
+                ids.extend(playlist.entries().iter().map(|e| e.id()));
+            }
+            Ok(maguro::query::Target::Channel(channel)) => {
+                let tabs = maguro::query::Tab::ALL;
+                let channel = match maguro::query::Channel::from_ref(&channel, &tabs).await {
+                    Ok(channel) => channel,
+                    Err(e) => {
+                        error!("{}", e);
+                        exit(1)
+                    }
+                };
+                println!(
+                    "Expanded channel {} into {} videos.",
+                    channel.title(),
+                    channel.entries().len()
+                );
+                ids.extend(channel.entries().iter().map(|e| e.id()));
+            }
+            Err(e) => {
+                error!("{}", e);
+                exit(1)
+            }
+        }
+    }
 
-    let mut info: Vec<maguro::InfoResponse> = Vec::new();
-    while let Some(id) = ids.pop() {
+    let section = matches.value_of("section").map(|s| {
+        parse_section(s).unwrap_or_else(|| {
+            error!(
+                "Invalid section {}; expected START-END, such as 1:00-1:30.",
+                s
+            );
+            exit(1)
+        })
+    });
+
+    // Videos are handled in order, each fetched only once the previous one is
+    // done. When there are several, each is written to its own file, and
+    // those that cannot be fetched, such as private or deleted videos, are
+    // skipped.
+    let several = ids.len() > 1;
+    let mut skipped = 0;
+    for id in &ids {
         info!("Collecting data for {}", id);
-        let vid_info = maguro::get_video_info(id).await?;
-        info.push(vid_info);
-    }
+        let resp = if matches.is_present("wait_for_video") {
+            println!("Waiting for {} to become available...", id);
+            maguro::wait_for_video(id).await
+        } else {
+            maguro::get_video_info(id).await
+        };
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(e) if several => {
+                error!("Skipping video {}: {}", id, e);
+                skipped += 1;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
 
-    // Outputs available formats then exits.
-    if matches.is_present("show_formats") {
-        for resp in info {
+        // Outputs available formats.
+        if matches.is_present("show_formats") {
             println!(
                 "Displaying available streaming formats for video ID {}:",
-                resp.details().id()
+                id
             );
-
             for format in &resp.all_formats() {
                 println!("{}", format);
             }
+            continue;
+        }
+
+        // Outputs a DASH manifest.
+        if matches.is_present("dash_manifest") {
+            let xml = resp.to_dash_manifest().to_xml();
+            match matches.value_of("output") {
+                Some(output) => {
+                    let path = output_path(output, id, several);
+                    tokio::fs::write(&path, xml).await?;
+                    println!("Wrote DASH manifest to {}.", path);
+                }
+                None => print!("{}", xml),
+            }
+            continue;
+        }
+
+        // Otherwise, download the video.
+        let path = output_path(
+            matches.value_of("output").unwrap_or_else(|| {
+                println!("Please specify an output file.");
+                exit(1)
+            }),
+            id,
+            several,
+        );
+        println!("Starting download of {}...", id);
+
+        // Only recordings of live streams from now on are split.
+        let rotates = matches.is_present("rotate_minutes") || matches.is_present("rotate_size");
+        let reject_rotation = || {
+            error!(
+                "Video {}: --rotate-minutes and --rotate-size only apply when recording a live stream from now on.",
+                id
+            );
+            exit(1)
+        };
+
+        // Live streams with DVR can be recorded from their beginning.
+        if matches.is_present("live_from_start") {
+            let url = resp.dash_manifest_url().unwrap_or_else(|| {
+                error!("Video {}: no DVR manifest available for this video.", id);
+                exit(1)
+            });
+            if rotates {
+                reject_rotation()
+            }
+            println!("Recording live stream from the beginning...");
+            let format = matches.value_of("format").unwrap_or_default();
+            if let Err(e) = record_from_start(&url, format, &path).await {
+                error!("{}", e);
+                exit(1)
+            }
+            if matches.is_present("remux") {
+                if let Err(e) = remux_file(&path).await {
+                    error!("{}", e);
+                    exit(1)
+                }
+            }
+            println!("Completed recording of video {}.", id);
+            continue;
         }
-        exit(0)
-    }
 
-    // Otherwise, download videos.
-    for resp in info {
-        println!("Starting download of {}...", resp.details().id());
+        // Videos that are live now are recorded until they end.
+        if let Some(url) = resp.hls_manifest_url() {
+            // The recorder follows the best variant of the whole stream.
+            if ["format", "section", "embed_metadata"]
+                .iter()
+                .any(|&arg| matches.is_present(arg))
+            {
+                error!(
+                    "Video {} is live; -f, --section and --embed-metadata cannot be used when recording a live stream.",
+                    id
+                );
+                exit(1)
+            }
+            println!("Recording live stream...");
+            let rotation = (
+                matches.value_of("rotate_minutes"),
+                matches.value_of("rotate_size"),
+            );
+            match record_live(&url, rotation, &path).await {
+                Ok(recording) => {
+                    if !recording.gaps().is_empty() {
+                        println!(
+                            "Recording is missing {} ranges of segments.",
+                            recording.gaps().len()
+                        );
+                    }
+                    // Each file starts with its own initialization section,
+                    // so each can be remuxed on its own.
+                    if matches.is_present("remux") {
+                        for file in recording.files() {
+                            if let Err(e) = remux_file(&file).await {
+                                error!("{}", e);
+                                exit(1)
+                            }
+                        }
+                    }
+                    println!(
+                        "Completed recording of video {} into {}.",
+                        id,
+                        recording.files().join(", ")
+                    );
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    exit(1)
+                }
+            }
+            continue;
+        }
 
+        if rotates {
+            reject_rotation()
+        }
         let mut dest = OpenOptions::new()
-            .read(false)
+            .read(true)
             .write(true)
             .create(true)
-            .open(format!(
-                "{}",
-                &matches.value_of("output").unwrap_or_else(|| {
-                    println!("Please specify an output file.");
-                    exit(1)
-                })
-            ))
+            .truncate(true)
+            .open(&path)
             .await?;
 
         let formats = resp.all_formats();
-        let chosen = match matches.value_of("format") {
-            Some(fmt) => formats.iter().find(|&f| f.itag().to_string() == fmt),
-            None => formats.last(),
-        };
+        let find = |itag: &str| formats.iter().find(|&f| f.itag().to_string() == itag);
 
-        match chosen {
-            Some(f) => {
-                println!("Downloading...");
-                if let Err(e) = f.download(&mut dest).await {
-                    error!("{}", e);
+        // A `VIDEO+AUDIO` pair of itags is downloaded and merged.
+        if let Some((video, audio)) = matches.value_of("format").and_then(|f| f.split_once('+')) {
+            match (find(video), find(audio)) {
+                (Some(v), Some(a)) => {
+                    println!("Downloading and merging...");
+                    let result = match section {
+                        Some((start, end)) => {
+                            download_merged_range(v, a, start, end, &mut dest).await
+                        }
+                        None => maguro::mux::download(v, a, &mut dest).await,
+                    };
+                    if let Err(e) = result {
+                        error!("{}", e);
+                        exit(1)
+                    }
+                }
+                _ => {
+                    error!("Failed to find selected itags!");
                     exit(1)
                 }
+            };
+        } else {
+            let chosen = match matches.value_of("format") {
+                Some(fmt) => find(fmt),
+                None => formats.last(),
+            };
+
+            match chosen {
+                Some(f) => {
+                    println!("Downloading...");
+                    let result = match section {
+                        Some((start, end)) => f.download_range(start, end, &mut dest).await,
+                        None => f.download(&mut dest).await,
+                    };
+                    if let Err(e) = result {
+                        error!("{}", e);
+                        exit(1)
+                    }
+                }
+                None => {
+                    error!("Failed to find selected itag!");
+                    exit(1)
+                }
+            };
+        }
+
+        if matches.is_present("remux") {
+            if let Err(e) = maguro::mux::remux(&mut dest).await {
+                error!("{}", e);
+                exit(1)
             }
-            None => {
-                error!("Failed to find selected itag!");
+        }
+
+        if matches.is_present("embed_metadata") {
+            if let Err(e) = maguro::mux::tag(&mut dest, &resp).await {
+                error!("{}", e);
                 exit(1)
             }
-        };
+        }
+
+        println!("Completed download of video {}.", id);
+    }
+
+    if skipped > 0 {
+        error!("Skipped {} of {} videos.", skipped, ids.len());
+        exit(1)
+    }
+    Ok(())
+}
+
+/// Remuxes the file at `path` in place; see [maguro::mux::remux].
+async fn remux_file(path: &str) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path).await?;
+    maguro::mux::remux(&mut file).await
+}
+
+/// Fetches and parses the DASH manifest at `url`.
+async fn fetch_manifest(url: &str) -> Result<maguro::dash::Manifest, Box<dyn error::Error>> {
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    let mut res = client.get(url.parse()?).await?;
+    let body = body::to_bytes(res.body_mut()).await?;
 
-        println!("Completed download of video {}.", resp.details().id());
+    let mut manifest = maguro::dash::Manifest::try_from(str::from_utf8(&body)?)?;
+    manifest.set_location(url)?;
+    Ok(manifest)
+}
+
+/// Downloads the representation `id` of a manifest into `path`, from the
+/// period at index `period` if given, and otherwise joined across them all.
+async fn download_manifest(
+    manifest: &maguro::dash::Manifest,
+    id: &str,
+    period: Option<&str>,
+    path: &str,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let mut dest = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .await?;
+    let downloader = maguro::dash::Downloader::new();
+
+    let representations = match period {
+        Some(index) => {
+            let period = index
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| manifest.periods().nth(i))
+                .ok_or_else(|| format!("The manifest has no period {}", index))?;
+            let representation = period
+                .representation(id)
+                .ok_or_else(|| format!("Period {} has no representation {}", index, id))?;
+            vec![representation]
+        }
+        None => manifest.stitch(id)?,
+    };
+    match representations.as_slice() {
+        [representation] => downloader.download(representation, &mut dest).await?,
+        _ => {
+            downloader
+                .download_periods(&representations, &mut dest)
+                .await?
+        }
+    };
+    Ok(())
+}
+
+/// Records the highest-bandwidth variant of the live HLS playlist at `url`
+/// into `path`, rotated after the given number of minutes or megabytes.
+async fn record_live(
+    url: &str,
+    (minutes, megabytes): (Option<&str>, Option<&str>),
+    path: &str,
+) -> Result<maguro::hls::Recording, Box<dyn error::Error + Send + Sync>> {
+    let media = match maguro::hls::Playlist::from_url(&url).await? {
+        maguro::hls::Playlist::Master(master) => master
+            .highest_bandwidth()
+            .ok_or("The live stream has no variants")?
+            .uri(),
+        maguro::hls::Playlist::Media(_) => url.to_string(),
+    };
+
+    let mut recorder = maguro::hls::Recorder::new(&media);
+    if let Some(minutes) = minutes {
+        let minutes: u64 = minutes
+            .parse()
+            .map_err(|_| format!("Invalid number of minutes {}", minutes))?;
+        recorder = recorder.rotate_every(Duration::from_secs(minutes * 60));
+    }
+    if let Some(megabytes) = megabytes {
+        let megabytes: u64 = megabytes
+            .parse()
+            .map_err(|_| format!("Invalid number of megabytes {}", megabytes))?;
+        recorder = recorder.rotate_at(megabytes * 1_000_000);
+    }
+    recorder.record(path).await
+}
+
+/// Records the representation `id` of the live DASH manifest at `url` into
+/// `path`, from the beginning of the broadcast until it ends.
+async fn record_from_start(
+    url: &str,
+    id: &str,
+    path: &str,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let session = maguro::dash::LiveSession::new(url).await?;
+    if !session.manifest().representations().any(|r| r.id() == id) {
+        return Err(format!("The live stream has no format {}", id).into());
+    }
+    let mut dest = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .await?;
+    session.record_from_start(id, &mut dest).await
+}
+
+/// Formats a duration as `[H:]MM:SS`.
+fn timestamp(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        h => format!("{}:{:02}:{:02}", h, secs / 60 % 60, secs % 60),
     }
+}
+
+/// Lists the periods, adaptation sets and representations of a manifest.
+fn show_manifest(manifest: &maguro::dash::Manifest) {
+    for (i, period) in manifest.periods().enumerate() {
+        let mut label = format!("Period {}", i);
+        if let Some(id) = period.id() {
+            label.push_str(&format!(" ({})", id));
+        }
+        if let Some(start) = period.start() {
+            label.push_str(&format!(" | Start: {}", timestamp(start)));
+        }
+        if let Some(duration) = period.duration() {
+            label.push_str(&format!(" | Duration: {}", timestamp(duration)));
+        }
+        println!("{}:", label);
+
+        for set in period.adaptation_sets() {
+            let mut label = set
+                .mime_type()
+                .map(|m| m.to_string())
+                .or_else(|| set.content_type())
+                .unwrap_or_else(|| "unknown".to_string());
+            if let Some(lang) = set.lang() {
+                label.push_str(&format!(" | Language: {}", lang));
+            }
+            for role in set.roles() {
+                label.push_str(&format!(" | Role: {}", role.value()));
+            }
+            if !set.content_protections().is_empty() {
+                label.push_str(" | Protected");
+            }
+            println!("  Adaptation set: {}", label);
+
+            for rep in set.representations() {
+                println!("    {}", rep);
+            }
+        }
+    }
+}
 
+/// Downloads the same section of a video and an audio format, and merges
+/// them into `dest`.
+async fn download_merged_range(
+    video: &maguro::Format,
+    audio: &maguro::Format,
+    start: Duration,
+    end: Duration,
+    dest: &mut tokio::fs::File,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let (video_data, audio_data) = tokio::try_join!(
+        video.range_to_vec(start, end),
+        audio.range_to_vec(start, end)
+    )?;
+    let merged = maguro::mux::merge(video, &video_data, audio, &audio_data)?;
+    dest.write_all(&merged).await?;
     Ok(())
 }
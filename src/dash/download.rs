@@ -0,0 +1,659 @@
+//! Downloading a [Representation] segment by segment into a single file.
+//!
+//! Segments are fetched several at a time but written strictly in order, so
+//! the output is always a prefix of the finished stream. A [Progress]
+//! records how much of that prefix exists, and lets an interrupted download
+//! pick up again from the last whole segment.
+//!
+//! When a manifest offers several BaseURLs, a segment that keeps failing on
+//! one is fetched from the next, and later segments start from whichever
+//! last worked.
+//!
+//! [Downloader::download_periods] joins the representations of several
+//! periods into one MP4 stream, moving the decode times of each period's
+//! fragments to where that period begins in the presentation.
+
+use hyper::{body, client::HttpConnector, header::RANGE, Body, Client, Request, StatusCode};
+use hyper_tls::HttpsConnector;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    error,
+    future::Future,
+    io::SeekFrom,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+
+use super::{Error, Representation};
+use crate::{mux::mp4, ByteRange};
+
+pub(crate) type HttpsClient = Client<HttpsConnector<HttpConnector>>;
+
+/// The contents of a segment, once fetched.
+pub(crate) type Fetch =
+    Pin<Box<dyn Future<Output = Result<Vec<u8>, Box<dyn error::Error + Send + Sync>>> + Send>>;
+
+/// A segment waiting to be fetched.
+struct Job {
+    urls: Vec<String>,
+    range: Option<ByteRange>,
+
+    // Decode time shift for each track, by track ID, for segments moved to
+    // another point in the presentation.
+    shifts: Option<Vec<(u32, i64)>>,
+
+    // Contents already fetched, if any.
+    data: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// How much of a download, of a [Representation] or of an HLS
+/// [MediaPlaylist](crate::hls::MediaPlaylist), has been written.
+pub struct Progress {
+    segments: usize,
+    bytes: u64,
+}
+
+impl Progress {
+    /// Progress after writing `segments` segments, counting the
+    /// initialization segment, totalling `bytes` bytes.
+    pub fn new(segments: usize, bytes: u64) -> Self {
+        Self { segments, bytes }
+    }
+
+    /// Number of segments written, counting the initialization segment.
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    /// Number of bytes written.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Fetches the segments of a [Representation] and writes them to a `File`.
+pub struct Downloader {
+    concurrency: usize,
+    retries: u32,
+    timeout: Duration,
+    resume: Progress,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            retries: 3,
+            timeout: Duration::from_secs(30),
+            resume: Progress::default(),
+        }
+    }
+}
+
+impl Downloader {
+    /// A [Downloader] fetching four segments at a time, giving each request
+    /// 30 seconds, and retrying each segment up to three times.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of segments fetched at once.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets the number of times a failed segment is retried before giving
+    /// up.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets how long a single request may take before it is abandoned and
+    /// retried, possibly from another BaseURL.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Continues an earlier download from the [Progress] it last reported.
+    /// Anything in the `File` past that point is discarded.
+    pub fn resume(mut self, progress: Progress) -> Self {
+        self.resume = progress;
+        self
+    }
+
+    /// Downloads the initialization segment and every media segment of
+    /// `representation` into `dest`. Fails with
+    /// [Protected](crate::drm::Protected) before fetching anything if the
+    /// representation is encrypted.
+    pub async fn download(
+        &self,
+        representation: &Representation,
+        dest: &mut File,
+    ) -> Result<Progress, Box<dyn error::Error + Send + Sync>> {
+        self.download_callback(representation, dest, |_| Ok(()))
+            .await
+    }
+
+    /// Downloads `representation` into `dest`, calling the closure with
+    /// the [Progress] made after each segment is written. Saving it allows
+    /// the download to be [resumed](Downloader::resume).
+    pub async fn download_callback<T>(
+        &self,
+        representation: &Representation,
+        dest: &mut File,
+        on_segment: T,
+    ) -> Result<Progress, Box<dyn error::Error + Send + Sync>>
+    where
+        T: Fn(Progress) -> Result<(), Box<dyn error::Error + Send + Sync>>,
+    {
+        representation.check_unprotected()?;
+
+        let mut jobs = Vec::new();
+        for segment in representation
+            .initialization()?
+            .into_iter()
+            .chain(representation.segments()?)
+        {
+            jobs.push(Job {
+                urls: representation.locate(&segment)?,
+                range: segment.range(),
+                shifts: None,
+                data: None,
+            });
+        }
+
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+        self.run(
+            client,
+            Arc::new(AtomicUsize::new(0)),
+            jobs,
+            dest,
+            on_segment,
+        )
+        .await
+    }
+
+    /// Downloads `representations`, one for each [Period](super::Period) as
+    /// chosen by [Manifest::stitch](super::Manifest::stitch), into `dest` as
+    /// a single continuous MP4 stream.
+    ///
+    /// Only the initialization segment of the first is written, so all of
+    /// them must share a codec configuration; representations that do not
+    /// are rejected before anything is written. The decode times of the
+    /// fragments of each are corrected to the start of their period.
+    pub async fn download_periods(
+        &self,
+        representations: &[&Representation],
+        dest: &mut File,
+    ) -> Result<Progress, Box<dyn error::Error + Send + Sync>> {
+        self.download_periods_callback(representations, dest, |_| Ok(()))
+            .await
+    }
+
+    /// Downloads `representations` into `dest` as with
+    /// [Downloader::download_periods], calling the closure with the
+    /// [Progress] made after each segment is written.
+    pub async fn download_periods_callback<T>(
+        &self,
+        representations: &[&Representation],
+        dest: &mut File,
+        on_segment: T,
+    ) -> Result<Progress, Box<dyn error::Error + Send + Sync>>
+    where
+        T: Fn(Progress) -> Result<(), Box<dyn error::Error + Send + Sync>>,
+    {
+        let first = representations
+            .first()
+            .ok_or_else(|| Error::Unsupported("no representations to download".into()))?;
+        for representation in representations {
+            representation.check_unprotected()?;
+            if representation
+                .mime_type()
+                .is_none_or(|m| m.subtype().as_str() != "mp4")
+            {
+                return Err(Error::Unsupported(format!(
+                    "cannot stitch representation {}; only MP4 is supported",
+                    representation.id()
+                ))
+                .into());
+            }
+        }
+
+        // The timescale of each track is needed to correct decode times.
+        let init = first.initialization()?.ok_or_else(|| {
+            Error::Unsupported(format!(
+                "representation {} has no initialization segment",
+                first.id()
+            ))
+        })?;
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+        let preferred = Arc::new(AtomicUsize::new(0));
+        let header = fetch(
+            client.clone(),
+            first.locate(&init)?,
+            init.range(),
+            self.clone(),
+            preferred.clone(),
+        )
+        .await?;
+        let timescales = mp4::timescales(&header)?;
+
+        // Fragments can only follow the header if the tracks they belong to
+        // are described the same way, which ad breaks often are not.
+        let mut checked = vec![(first.locate(&init)?, init.range())];
+        for representation in &representations[1..] {
+            let init = representation.initialization()?.ok_or_else(|| {
+                Error::Unsupported(format!(
+                    "representation {} has no initialization segment",
+                    representation.id()
+                ))
+            })?;
+            let key = (representation.locate(&init)?, init.range());
+            if checked.contains(&key) {
+                continue;
+            }
+            let other = fetch(
+                client.clone(),
+                key.0.clone(),
+                key.1.clone(),
+                self.clone(),
+                preferred.clone(),
+            )
+            .await?;
+            if !mp4::compatible(&header, &other)? {
+                return Err(Error::Unsupported(format!(
+                    "representation {} is encoded differently from {}, and cannot be joined to it",
+                    representation.id(),
+                    first.id()
+                ))
+                .into());
+            }
+            checked.push(key);
+        }
+
+        let mut jobs = vec![Job {
+            urls: Vec::new(),
+            range: None,
+            shifts: None,
+            data: Some(header),
+        }];
+        for representation in representations {
+            let start = representation.period_start.unwrap_or_default();
+            let offset = representation.presentation_time_offset();
+            let shifts: Vec<_> = timescales
+                .iter()
+                .map(|&(track, timescale)| (track, shift(start, offset, timescale)))
+                .collect();
+            for segment in representation.segments()? {
+                jobs.push(Job {
+                    urls: representation.locate(&segment)?,
+                    range: segment.range(),
+                    shifts: Some(shifts.clone()),
+                    data: None,
+                });
+            }
+        }
+
+        self.run(client, preferred, jobs, dest, on_segment).await
+    }
+
+    /// Appends the segments at `urls`, each given as the URLs it can be
+    /// fetched from in order of preference, to the end of `dest`.
+    pub(super) async fn append(
+        &self,
+        client: HttpsClient,
+        preferred: Arc<AtomicUsize>,
+        urls: Vec<Vec<String>>,
+        dest: &mut File,
+    ) -> Result<Progress, Box<dyn error::Error + Send + Sync>> {
+        let jobs = urls
+            .into_iter()
+            .map(|urls| Job {
+                urls,
+                range: None,
+                shifts: None,
+                data: None,
+            })
+            .collect();
+        let end = Progress::new(0, dest.metadata().await?.len());
+        self.clone()
+            .resume(end)
+            .run(client, preferred, jobs, dest, |_| Ok(()))
+            .await
+    }
+
+    /// Fetches `jobs` several at a time, writing them to `dest` in order,
+    /// with the decode times of any moved in the presentation shifted.
+    async fn run<T>(
+        &self,
+        client: HttpsClient,
+        preferred: Arc<AtomicUsize>,
+        jobs: Vec<Job>,
+        dest: &mut File,
+        on_segment: T,
+    ) -> Result<Progress, Box<dyn error::Error + Send + Sync>>
+    where
+        T: Fn(Progress) -> Result<(), Box<dyn error::Error + Send + Sync>>,
+    {
+        let fetches = jobs
+            .into_iter()
+            .map(|job| {
+                let (client, options, preferred) =
+                    (client.clone(), self.clone(), preferred.clone());
+                Box::pin(async move {
+                    let data = match job.data {
+                        Some(data) => data,
+                        None => fetch(client, job.urls, job.range, options, preferred).await?,
+                    };
+                    match job.shifts {
+                        Some(shifts) => Ok(mp4::restamp(&data, &shifts)?),
+                        None => Ok(data),
+                    }
+                }) as Fetch
+            })
+            .collect();
+        self.run_fetches(fetches, dest, on_segment).await
+    }
+
+    /// Runs `fetches` several at a time, writing what each yields to `dest`
+    /// in order, and skipping those already written according to the
+    /// [Progress] to resume from.
+    pub(crate) async fn run_fetches<T>(
+        &self,
+        fetches: Vec<Fetch>,
+        dest: &mut File,
+        on_segment: T,
+    ) -> Result<Progress, Box<dyn error::Error + Send + Sync>>
+    where
+        T: Fn(Progress) -> Result<(), Box<dyn error::Error + Send + Sync>>,
+    {
+        let mut progress = self.resume;
+        if dest.metadata().await?.len() < progress.bytes {
+            return Err("Cannot resume: the file is shorter than the recorded progress".into());
+        }
+        dest.set_len(progress.bytes).await?;
+        dest.seek(SeekFrom::Start(progress.bytes)).await?;
+        info!(
+            "Downloading {} segments, skipping {}",
+            fetches.len(),
+            progress.segments
+        );
+
+        let mut queue = fetches.into_iter().skip(progress.segments);
+        let mut pending = VecDeque::with_capacity(self.concurrency);
+        loop {
+            while pending.len() < self.concurrency {
+                match queue.next() {
+                    Some(fetch) => pending.push_back(tokio::spawn(fetch)),
+                    None => break,
+                }
+            }
+
+            let data = match pending.pop_front() {
+                Some(handle) => handle.await??,
+                None => break,
+            };
+            dest.write_all(&data).await?;
+            progress.segments += 1;
+            progress.bytes += data.len() as u64;
+            on_segment(progress)?;
+        }
+
+        Ok(progress)
+    }
+}
+
+/// Ticks of `timescale` by which to move decode times so that media timed
+/// from `offset` begins at `start`.
+fn shift(start: Duration, offset: Duration, timescale: u32) -> i64 {
+    let nanos = start.as_nanos() as i128 - offset.as_nanos() as i128;
+    (nanos * i128::from(timescale) / 1_000_000_000) as i64
+}
+
+/// Fetches a segment from any of `urls`, starting with the `preferred` one.
+/// Each round tries every URL once, and rounds are retried with exponential
+/// backoff.
+pub(crate) async fn fetch(
+    client: HttpsClient,
+    urls: Vec<String>,
+    range: Option<ByteRange>,
+    options: Downloader,
+    preferred: Arc<AtomicUsize>,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let mut attempt = 0;
+    loop {
+        let first = preferred.load(Ordering::Relaxed);
+        let mut last_error = None;
+        for i in (0..urls.len()).map(|k| (first + k) % urls.len()) {
+            let result =
+                tokio::time::timeout(options.timeout, fetch_once(&client, &urls[i], &range))
+                    .await
+                    .unwrap_or_else(|_| Err("Segment request timed out".into()));
+            match result {
+                Ok(data) => {
+                    preferred.store(i, Ordering::Relaxed);
+                    return Ok(data);
+                }
+                Err(e) => {
+                    warn!("Failed to fetch {}: {}", urls[i], e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        let e = last_error.unwrap_or_else(|| "No URL to fetch segment from".into());
+        if attempt >= options.retries || urls.is_empty() {
+            return Err(e);
+        }
+        tokio::time::sleep(Duration::from_millis(500 << attempt)).await;
+        attempt += 1;
+    }
+}
+
+/// Whether the resource at any of `urls` exists, retried as in [fetch].
+/// Only a 404 or 410 from every URL counts as missing; any other failure is
+/// returned once the retries run out.
+pub(crate) async fn exists(
+    client: HttpsClient,
+    urls: Vec<String>,
+    options: Downloader,
+) -> Result<bool, Box<dyn error::Error + Send + Sync>> {
+    let mut attempt = 0;
+    loop {
+        let mut last_error = None;
+        for url in &urls {
+            let result = tokio::time::timeout(options.timeout, exists_once(&client, url))
+                .await
+                .unwrap_or_else(|_| Err("Segment request timed out".into()));
+            match result {
+                Ok(true) => return Ok(true),
+                Ok(false) => (),
+                Err(e) => {
+                    warn!("Failed to check {}: {}", url, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        let e = match last_error {
+            Some(e) => e,
+            None => return Ok(false),
+        };
+        if attempt >= options.retries {
+            return Err(e);
+        }
+        tokio::time::sleep(Duration::from_millis(500 << attempt)).await;
+        attempt += 1;
+    }
+}
+
+async fn exists_once(
+    client: &HttpsClient,
+    url: &str,
+) -> Result<bool, Box<dyn error::Error + Send + Sync>> {
+    let req = Request::get(url).header(RANGE, "bytes=0-0");
+    let res = client.request(req.body(Body::empty())?).await?;
+    match res.status() {
+        status if status.is_success() => Ok(true),
+        StatusCode::NOT_FOUND | StatusCode::GONE => Ok(false),
+        status => Err(format!("Segment request failed with {}", status).into()),
+    }
+}
+
+async fn fetch_once(
+    client: &HttpsClient,
+    url: &str,
+    range: &Option<ByteRange>,
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let mut req = Request::get(url);
+    match range {
+        Some(range) if range.end() == u64::MAX => {
+            req = req.header(RANGE, format!("bytes={}-", range.start()))
+        }
+        Some(range) => req = req.header(RANGE, format!("bytes={}-{}", range.start(), range.end())),
+        None => (),
+    }
+    let mut res = client.request(req.body(Body::empty())?).await?;
+    if !res.status().is_success() {
+        return Err(format!("Segment request failed with {}", res.status()).into());
+    }
+    Ok(body::to_bytes(res.body_mut()).await?.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{
+        header::CONTENT_RANGE,
+        service::{make_service_fn, service_fn},
+        Response, Server,
+    };
+    use std::{convert::Infallible, convert::TryFrom, net::SocketAddr};
+
+    use crate::dash::Manifest;
+
+    /// Serves `data` at every path, honouring single byte ranges, and
+    /// returns the address it listens on.
+    fn serve(data: &'static [u8]) -> SocketAddr {
+        let service = make_service_fn(move |_| async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+                let range = req
+                    .headers()
+                    .get(RANGE)
+                    .and_then(|r| r.to_str().ok())
+                    .and_then(|r| r.strip_prefix("bytes="))
+                    .and_then(|r| r.split_once('-'))
+                    .map(|(start, end)| {
+                        let start: usize = start.parse().unwrap();
+                        let end = end.parse::<usize>().map_or(data.len(), |e| e + 1);
+                        (start, end.min(data.len()))
+                    });
+                Ok::<_, Infallible>(match range {
+                    Some((start, end)) => Response::builder()
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .header(
+                            CONTENT_RANGE,
+                            format!("bytes {}-{}/{}", start, end - 1, data.len()),
+                        )
+                        .body(Body::from(&data[start..end]))
+                        .unwrap(),
+                    None => Response::new(Body::from(data)),
+                })
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn tells_missing_from_failing() {
+        // Responds to `/<status>` with that status.
+        let service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let status = req.uri().path()[1..].parse::<u16>().unwrap();
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .status(status)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client: HttpsClient = Client::builder().build::<_, Body>(HttpsConnector::new());
+        let options = Downloader::new().retries(1);
+        let check = |statuses: &[u16]| {
+            let urls = statuses
+                .iter()
+                .map(|s| format!("http://{}/{}", addr, s))
+                .collect();
+            exists(client.clone(), urls, options.clone())
+        };
+        assert!(check(&[206]).await.unwrap());
+        assert!(check(&[503, 200]).await.unwrap());
+        assert!(!check(&[404]).await.unwrap());
+        assert!(!check(&[404, 410]).await.unwrap());
+        assert!(check(&[404, 503]).await.is_err());
+        assert!(check(&[500]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn writes_single_resource_once() {
+        static RESOURCE: [u8; 1200] = [7; 1200];
+        let addr = serve(&RESOURCE);
+        let manifest = Manifest::try_from(
+            format!(
+                r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT9S">
+  <Period>
+    <AdaptationSet mimeType="audio/mp4">
+      <Representation id="audio" bandwidth="128000">
+        <BaseURL>http://{}/audio.mp4</BaseURL>
+        <SegmentBase indexRange="700-999">
+          <Initialization range="0-699"/>
+        </SegmentBase>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+                addr
+            )
+            .as_str(),
+        )
+        .unwrap();
+        let representation = manifest.representations().next().unwrap();
+
+        let path = std::env::temp_dir().join(format!("maguro-segment-base-{}.mp4", addr.port()));
+        let mut dest = File::create(&path).await.unwrap();
+        let progress = Downloader::new()
+            .download(representation, &mut dest)
+            .await
+            .unwrap();
+        let written = tokio::fs::metadata(&path).await.unwrap().len();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        // The initialization segment is written once, followed by the rest
+        // of the file.
+        assert_eq!(progress, Progress::new(2, RESOURCE.len() as u64));
+        assert_eq!(written, RESOURCE.len() as u64);
+    }
+}
@@ -0,0 +1,539 @@
+//! Recording live (dynamic) DASH presentations.
+//!
+//! A dynamic [Manifest] lists only what has been published so far, and is
+//! fetched again periodically as the presentation goes on. A [LiveSession]
+//! keeps it up to date, follows the server's clock as advertised by the
+//! manifest's UTCTiming, and emits each segment once, as soon as it becomes
+//! available.
+//!
+//! YouTube keeps the segments of a live stream with DVR available for far
+//! longer than its manifest lists them, each addressed by the `sq` sequence
+//! number in its URL. [LiveSession::record_from_start] walks back through
+//! them to capture a stream from its beginning rather than its live edge.
+
+use chrono::{DateTime, Utc};
+use hyper::{body, header::DATE, Body, Client, Method, Request};
+use hyper_tls::HttpsConnector;
+use log::{info, warn};
+use std::{
+    collections::HashMap,
+    error,
+    ops::Range,
+    sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
+};
+use tokio::{fs::File, io::AsyncWriteExt, sync::mpsc};
+
+use super::{
+    download::{self, HttpsClient},
+    Downloader, Error, Manifest, Segment, UTCTiming,
+};
+use crate::serde::date_time;
+
+#[derive(Debug, Clone)]
+/// A segment of a live [Representation](super::Representation) that has
+/// become available.
+pub struct LiveSegment {
+    segment: Segment,
+    urls: Vec<String>,
+    initialization: bool,
+}
+
+impl LiveSegment {
+    /// The segment itself.
+    pub fn segment(&self) -> Segment {
+        self.segment.clone()
+    }
+
+    /// Every absolute URL the segment can be fetched from, in order of
+    /// preference.
+    pub fn urls(&self) -> Vec<String> {
+        self.urls.clone()
+    }
+
+    /// Whether this is an initialization segment. One is emitted before the
+    /// first media segment, and again whenever it changes, such as at the
+    /// start of a new period.
+    pub fn is_initialization(&self) -> bool {
+        self.initialization
+    }
+
+    /// The YouTube sequence number of the segment, if its URL carries one.
+    pub fn sequence_number(&self) -> Option<u64> {
+        let url = self.urls.first()?;
+        url[sequence_span(url)?].parse().ok()
+    }
+}
+
+/// Segments already emitted by a [LiveSession].
+#[derive(Debug, Default)]
+struct Seen {
+    initialization: Option<Vec<String>>,
+
+    // Start of the latest segment emitted from each period still listed,
+    // by period ID or index.
+    latest: HashMap<String, Duration>,
+}
+
+#[derive(Debug, Clone)]
+/// A live presentation followed through its [Manifest].
+pub struct LiveSession {
+    url: String,
+    manifest: Manifest,
+
+    // Difference between the server's clock and ours.
+    clock: chrono::Duration,
+}
+
+impl LiveSession {
+    /// Fetches the [Manifest] at `url`, and synchronizes with the clock it
+    /// advertises.
+    pub async fn new(url: &str) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        let mut session = Self {
+            url: url.to_string(),
+            manifest: Manifest::from_url(&url).await?,
+            clock: chrono::Duration::zero(),
+        };
+        session.synchronize().await;
+        Ok(session)
+    }
+
+    /// The most recently fetched [Manifest].
+    pub fn manifest(&self) -> Manifest {
+        self.manifest.clone()
+    }
+
+    /// The current time according to the server.
+    pub fn now(&self) -> DateTime<Utc> {
+        Utc::now() + self.clock
+    }
+
+    /// Emits the segments of the representation with the given `id` as
+    /// they become available, starting from the earliest still within the
+    /// time-shift buffer. The stream ends once the presentation does, or
+    /// with a [Protected](crate::drm::Protected) error if the
+    /// representation is encrypted.
+    pub fn segments(
+        self,
+        id: &str,
+    ) -> mpsc::Receiver<Result<LiveSegment, Box<dyn error::Error + Send + Sync>>> {
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(self.run(id.to_string(), tx));
+        rx
+    }
+
+    /// Records the representation with the given `id` into `dest` until
+    /// the presentation ends.
+    pub async fn record(
+        self,
+        id: &str,
+        dest: &mut File,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        self.capture(id, dest, false).await
+    }
+
+    /// Records the representation with the given `id` into `dest` from the
+    /// start of a YouTube live stream, rather than from the earliest segment
+    /// its manifest lists, then follows it until it ends.
+    ///
+    /// The earliest segment that can still be fetched is found by walking
+    /// back through sequence numbers, and everything from it on is fetched
+    /// before the segments the manifest lists. Streams without sequence
+    /// numbers in their URLs are recorded as with [LiveSession::record].
+    pub async fn record_from_start(
+        self,
+        id: &str,
+        dest: &mut File,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        self.capture(id, dest, true).await
+    }
+
+    /// Records the representation `id` into `dest`, first filling in the
+    /// segments from the start of the stream if `from_start`.
+    async fn capture(
+        self,
+        id: &str,
+        dest: &mut File,
+        from_start: bool,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let client: HttpsClient = Client::builder().build::<_, Body>(HttpsConnector::new());
+        let preferred = Arc::new(AtomicUsize::new(0));
+        let downloader = Downloader::new();
+
+        let mut backfill = from_start;
+        let mut segments = self.segments(id);
+        while let Some(segment) = segments.recv().await {
+            let segment = segment?;
+            if backfill && !segment.initialization {
+                backfill = false;
+                match segment.sequence_number() {
+                    Some(sq) => {
+                        let first =
+                            earliest_available(&client, &downloader, &segment.urls, sq).await?;
+                        if first < sq {
+                            info!(
+                                "Fetching segments {} to {} from before the manifest",
+                                first,
+                                sq - 1
+                            );
+                            let urls = (first..sq)
+                                .map(|n| {
+                                    segment
+                                        .urls
+                                        .iter()
+                                        .filter_map(|u| with_sequence_number(u, n))
+                                        .collect()
+                                })
+                                .collect();
+                            downloader
+                                .append(client.clone(), preferred.clone(), urls, dest)
+                                .await?;
+                        }
+                    }
+                    None => warn!(
+                        "Segments have no sequence numbers; recording from the earliest listed"
+                    ),
+                }
+            }
+
+            let data = download::fetch(
+                client.clone(),
+                segment.urls,
+                segment.segment.range(),
+                downloader.clone(),
+                preferred.clone(),
+            )
+            .await?;
+            dest.write_all(&data).await?;
+        }
+        Ok(())
+    }
+
+    async fn run(
+        mut self,
+        id: String,
+        tx: mpsc::Sender<Result<LiveSegment, Box<dyn error::Error + Send + Sync>>>,
+    ) {
+        if let Some(rep) = self.manifest.representations().find(|r| r.id == id) {
+            if let Err(e) = rep.check_unprotected() {
+                let _ = tx.send(Err(e.into())).await;
+                return;
+            }
+        }
+
+        let mut seen = Seen::default();
+        loop {
+            match self.pending(&id, &mut seen) {
+                Ok(segments) => {
+                    for segment in segments {
+                        if tx.send(Ok(segment)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e.into())).await;
+                    return;
+                }
+            }
+            if !self.manifest.is_dynamic() {
+                info!("Presentation has ended");
+                return;
+            }
+
+            let update = self.manifest.minimum_update_period;
+            tokio::time::sleep(
+                update
+                    .unwrap_or(Duration::from_secs(2))
+                    .max(Duration::from_secs(1)),
+            )
+            .await;
+            if update.is_some() {
+                if let Err(e) = self.refresh().await {
+                    warn!("Failed to refresh manifest: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Fetches the [Manifest] again.
+    async fn refresh(&mut self) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        self.manifest = Manifest::from_url(&self.url).await?;
+        Ok(())
+    }
+
+    /// Adopts the clock of the first UTCTiming source that responds.
+    async fn synchronize(&mut self) {
+        for timing in &self.manifest.utc_timings {
+            match server_time(timing).await {
+                Ok(Some(time)) => {
+                    self.clock = time - Utc::now();
+                    info!("Server clock is {} ahead", self.clock);
+                    return;
+                }
+                Ok(None) => (),
+                Err(e) => warn!("Failed to read time from {}: {}", timing.value, e),
+            }
+        }
+    }
+
+    /// Segments of the representation `id` that are available now and have
+    /// not been emitted yet.
+    fn pending(&self, id: &str, seen: &mut Seen) -> Result<Vec<LiveSegment>, Error> {
+        let manifest = &self.manifest;
+        let available_since = manifest.availability_start_time();
+        let depth = manifest.time_shift_buffer_depth;
+
+        let mut out = Vec::new();
+        let mut keys = Vec::new();
+        for (i, period) in manifest.periods.iter().enumerate() {
+            let key = period.id.clone().unwrap_or_else(|| i.to_string());
+            keys.push(key.clone());
+            let latest = seen.latest.get(&key).copied();
+
+            let rep = match period
+                .adaptation_sets
+                .iter()
+                .flat_map(|s| s.representations.iter())
+                .find(|r| r.id == id)
+            {
+                Some(rep) => rep,
+                None => continue,
+            };
+
+            let segments = if manifest.is_dynamic() {
+                let since = available_since.ok_or(Error::Malformed(
+                    "dynamic manifest without an availabilityStartTime",
+                ))?;
+                let elapsed = match (self.now() - since)
+                    .to_std()
+                    .ok()
+                    .and_then(|e| e.checked_sub(rep.period_start.unwrap_or_default()))
+                {
+                    Some(elapsed) => elapsed,
+                    // The period has yet to begin.
+                    None => continue,
+                };
+                // Only segments still within the time-shift buffer, and
+                // from the latest emitted on, need listing.
+                let window = depth
+                    .and_then(|depth| elapsed.checked_sub(depth))
+                    .unwrap_or_default();
+                rep.available_segments(latest.map_or(window, |l| l.max(window)), elapsed)?
+            } else {
+                rep.segments()?
+            };
+
+            let mut fresh = Vec::new();
+            for segment in segments {
+                if latest.is_none_or(|l| segment.start > l) {
+                    fresh.push(LiveSegment {
+                        urls: rep.locate(&segment)?,
+                        segment,
+                        initialization: false,
+                    });
+                }
+            }
+            let last = match fresh.last() {
+                Some(last) => last.segment.start,
+                None => continue,
+            };
+            seen.latest.insert(key, last);
+
+            if let Some(init) = rep.initialization()? {
+                let urls = rep.locate(&init)?;
+                if seen.initialization.as_ref() != Some(&urls) {
+                    seen.initialization = Some(urls.clone());
+                    out.push(LiveSegment {
+                        segment: init,
+                        urls,
+                        initialization: true,
+                    });
+                }
+            }
+            out.extend(fresh);
+        }
+        // Periods no longer listed will not be again.
+        seen.latest.retain(|key, _| keys.contains(key));
+        Ok(out)
+    }
+}
+
+/// Reads the time from a UTCTiming source, or [None] if its scheme is not
+/// supported.
+async fn server_time(
+    timing: &UTCTiming,
+) -> Result<Option<DateTime<Utc>>, Box<dyn error::Error + Send + Sync>> {
+    // Several sources may be given, separated by spaces.
+    let source = timing.value.split_whitespace().next().unwrap_or_default();
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+
+    match timing.scheme_id_uri.as_str() {
+        "urn:mpeg:dash:utc:direct:2014" => Ok(date_time::parse(source)),
+        "urn:mpeg:dash:utc:http-xsdate:2014" | "urn:mpeg:dash:utc:http-iso:2014" => {
+            let mut res = client.get(source.parse()?).await?;
+            let text = body::to_bytes(res.body_mut()).await?;
+            Ok(date_time::parse(std::str::from_utf8(&text)?))
+        }
+        "urn:mpeg:dash:utc:http-head:2014" => {
+            let req = Request::builder()
+                .method(Method::HEAD)
+                .uri(source)
+                .body(Body::empty())?;
+            let res = client.request(req).await?;
+            Ok(res
+                .headers()
+                .get(DATE)
+                .and_then(|d| d.to_str().ok())
+                .and_then(|d| DateTime::parse_from_rfc2822(d).ok())
+                .map(|d| d.with_timezone(&Utc)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Where the digits of the `sq` sequence number are in a YouTube segment
+/// URL, given either as an `sq/N` path component or an `sq=N` parameter.
+fn sequence_span(url: &str) -> Option<Range<usize>> {
+    ["/sq/", "?sq=", "&sq="].iter().find_map(|pattern| {
+        let start = url.find(pattern)? + pattern.len();
+        let digits = url[start..].bytes().take_while(u8::is_ascii_digit).count();
+        Some(start..start + digits).filter(|_| digits > 0)
+    })
+}
+
+/// `url` with its sequence number replaced by `sq`.
+fn with_sequence_number(url: &str, sq: u64) -> Option<String> {
+    let span = sequence_span(url)?;
+    Some(format!("{}{}{}", &url[..span.start], sq, &url[span.end..]))
+}
+
+/// The lowest sequence number of a segment that can still be fetched, given
+/// the `urls` of segment `known`, which can be. Segments are dropped from
+/// the start of a stream, so those available are always contiguous.
+async fn earliest_available(
+    client: &HttpsClient,
+    downloader: &Downloader,
+    urls: &[String],
+    known: u64,
+) -> Result<u64, Box<dyn error::Error + Send + Sync>> {
+    if is_available(client, downloader, urls, 0).await? {
+        return Ok(0);
+    }
+    let (mut missing, mut available) = (0, known);
+    while available - missing > 1 {
+        let mid = missing + (available - missing) / 2;
+        if is_available(client, downloader, urls, mid).await? {
+            available = mid;
+        } else {
+            missing = mid;
+        }
+    }
+    Ok(available)
+}
+
+/// Whether the segment `sq` can be fetched from any of `urls`, as given for
+/// another segment. Fails if the server cannot say.
+async fn is_available(
+    client: &HttpsClient,
+    downloader: &Downloader,
+    urls: &[String],
+    sq: u64,
+) -> Result<bool, Box<dyn error::Error + Send + Sync>> {
+    let urls = urls
+        .iter()
+        .filter_map(|u| with_sequence_number(u, sq))
+        .collect();
+    download::exists(client.clone(), urls, downloader.clone()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn emits_available_segments_once() {
+        let manifest = Manifest::try_from(
+            r#"<MPD type="dynamic" availabilityStartTime="2021-05-01T12:00:00Z"
+    timeShiftBufferDepth="PT5S" minimumUpdatePeriod="PT2S">
+  <BaseURL>https://live.example.com/</BaseURL>
+  <Period id="p0" start="PT0S">
+    <AdaptationSet>
+      <SegmentTemplate timescale="1" duration="2" initialization="init.mp4" media="$Number$.m4s"/>
+      <Representation id="v" bandwidth="1"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+        )
+        .unwrap();
+        let since = date_time::parse("2021-05-01T12:00:00Z").unwrap();
+        let mut session = LiveSession {
+            url: String::new(),
+            manifest,
+            clock: since + chrono::Duration::milliseconds(11_500) - Utc::now(),
+        };
+
+        // Segments 4 and 5 ended within the last five seconds.
+        let mut seen = Seen::default();
+        let urls: Vec<_> = session
+            .pending("v", &mut seen)
+            .unwrap()
+            .iter()
+            .map(|s| s.urls[0].clone())
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://live.example.com/init.mp4",
+                "https://live.example.com/4.m4s",
+                "https://live.example.com/5.m4s",
+            ]
+        );
+
+        session.clock = session.clock + chrono::Duration::seconds(2);
+        let next = session.pending("v", &mut seen).unwrap();
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].segment().number(), Some(6));
+        assert!(session.pending("v", &mut seen).unwrap().is_empty());
+
+        // Only the latest segment of each period listed is remembered.
+        assert_eq!(seen.latest.len(), 1);
+        session.manifest.periods[0].id = Some("p1".into());
+        assert_eq!(session.pending("v", &mut seen).unwrap().len(), 2);
+        assert_eq!(seen.latest.keys().collect::<Vec<_>>(), vec!["p1"]);
+    }
+
+    #[test]
+    fn rewrites_sequence_numbers() {
+        let path = "https://r1.googlevideo.com/videoplayback/id/abc.1/itag/137/sq/4821/lmt/1620/";
+        let query = "https://r1.googlevideo.com/videoplayback?id=abc.1&itag=140&sq=17&sig=AB%2C";
+        let segment = |url: &str| LiveSegment {
+            segment: Segment {
+                url: url.to_string(),
+                range: None,
+                number: None,
+                start: Duration::default(),
+                duration: None,
+            },
+            urls: vec![url.to_string()],
+            initialization: false,
+        };
+        assert_eq!(segment(path).sequence_number(), Some(4821));
+        assert_eq!(segment(query).sequence_number(), Some(17));
+        assert_eq!(
+            segment("https://live.example.com/4.m4s").sequence_number(),
+            None
+        );
+
+        assert_eq!(
+            with_sequence_number(path, 0).unwrap(),
+            "https://r1.googlevideo.com/videoplayback/id/abc.1/itag/137/sq/0/lmt/1620/"
+        );
+        assert_eq!(
+            with_sequence_number(query, 123).unwrap(),
+            "https://r1.googlevideo.com/videoplayback?id=abc.1&itag=140&sq=123&sig=AB%2C"
+        );
+        assert!(with_sequence_number("https://example.com/sq/x", 1).is_none());
+    }
+}
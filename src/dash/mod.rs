@@ -5,10 +5,36 @@
 //! function are translated. In the future, this process should ideally be
 //! automated.
 
-use hyper::{self, body};
-use hyper_tls;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::{convert::TryFrom, error, iter::FromIterator, str};
+use std::{
+    convert::TryFrom,
+    error,
+    fmt::{self, Display},
+    str::{self, FromStr},
+    time::Duration,
+};
+
+use url::Url;
+
+use crate::{
+    drm,
+    serde::{date_time, duration},
+    ByteRange,
+};
+
+#[cfg(feature = "client")]
+mod download;
+#[cfg(feature = "client")]
+mod live;
+mod xml;
+
+#[cfg(feature = "client")]
+pub(crate) use download::{fetch, Fetch, HttpsClient};
+#[cfg(feature = "client")]
+pub use download::{Downloader, Progress};
+#[cfg(feature = "client")]
+pub use live::{LiveSegment, LiveSession};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename = "MPD")]
@@ -19,6 +45,60 @@ pub struct Manifest {
 
     #[serde(rename = "type")]
     mpd_type: String,
+
+    profiles: Option<String>,
+
+    #[serde(
+        default,
+        rename = "minBufferTime",
+        deserialize_with = "duration::from_iso8601_option",
+        serialize_with = "duration::to_iso8601_option"
+    )]
+    min_buffer_time: Option<Duration>,
+
+    #[serde(
+        default,
+        rename = "mediaPresentationDuration",
+        deserialize_with = "duration::from_iso8601_option",
+        serialize_with = "duration::to_iso8601_option"
+    )]
+    media_presentation_duration: Option<Duration>,
+
+    #[serde(default, rename = "BaseURL")]
+    base_urls: Vec<BaseURL>,
+
+    // Live presentations only.
+    #[serde(
+        default,
+        rename = "availabilityStartTime",
+        deserialize_with = "date_time::from_str_option",
+        serialize_with = "date_time::to_str_option"
+    )]
+    availability_start_time: Option<DateTime<Utc>>,
+
+    #[serde(
+        default,
+        rename = "minimumUpdatePeriod",
+        deserialize_with = "duration::from_iso8601_option",
+        serialize_with = "duration::to_iso8601_option"
+    )]
+    minimum_update_period: Option<Duration>,
+
+    #[serde(
+        default,
+        rename = "timeShiftBufferDepth",
+        deserialize_with = "duration::from_iso8601_option",
+        serialize_with = "duration::to_iso8601_option"
+    )]
+    time_shift_buffer_depth: Option<Duration>,
+
+    #[serde(default, rename = "UTCTiming")]
+    utc_timings: Vec<UTCTiming>,
+
+    // Where the manifest itself was fetched from, against which relative
+    // BaseURLs are resolved.
+    #[serde(skip)]
+    location: Option<Url>,
 }
 
 impl Manifest {
@@ -31,32 +111,387 @@ impl Manifest {
         let client = hyper::Client::builder().build::<_, hyper::Body>(https);
 
         let mut res = client.get(url.to_string().parse().unwrap()).await?;
-        let body = String::from_iter(
-            body::to_bytes(res.body_mut())
-                .await?
-                .to_vec()
-                .iter()
-                .map(|e| char::from(e.clone()))
-                .filter(|e| e.is_ascii()),
-        );
+        let body = String::from_utf8(hyper::body::to_bytes(res.body_mut()).await?.to_vec())?;
+
+        let mut manifest = Self::try_from(body.as_str())?;
+        manifest.set_location(&url.to_string())?;
+        Ok(manifest)
+    }
+
+    /// Sets the URL the [Manifest] was fetched from, which relative
+    /// BaseURLs and segment URLs are resolved against. [Manifest::from_url]
+    /// sets it automatically.
+    pub fn set_location(&mut self, location: &str) -> Result<(), Error> {
+        self.location =
+            Some(Url::parse(location).map_err(|_| Error::Malformed("invalid manifest URL"))?);
+        self.resolve_base_urls();
+        Ok(())
+    }
+}
+
+impl Manifest {
+    /// Whether the [Manifest] describes a live presentation, whose segments
+    /// are published over time. See [LiveSession].
+    pub fn is_dynamic(&self) -> bool {
+        self.mpd_type == "dynamic"
+    }
 
-        Ok(Self::try_from(body.as_str())?)
+    /// Length of the whole presentation, if known.
+    pub fn media_presentation_duration(&self) -> Option<Duration> {
+        self.media_presentation_duration
+    }
+
+    /// Iterator over the [Periods](Period) of the presentation.
+    pub fn periods(&self) -> impl Iterator<Item = &Period> {
+        self.periods.iter()
+    }
+
+    /// Iterator over the [AdaptationSets](AdaptationSet) of every [Period].
+    pub fn adaptation_sets(&self) -> impl Iterator<Item = &AdaptationSet> {
+        self.periods.iter().flat_map(Period::adaptation_sets)
+    }
+
+    /// Iterator over the [Representations](Representation) of every
+    /// [Period].
+    pub fn representations(&self) -> impl Iterator<Item = &Representation> {
+        self.adaptation_sets()
+            .flat_map(AdaptationSet::representations)
+    }
+
+    /// The [Representation] to play in each [Period] to follow the one with
+    /// the given `id` through the whole presentation, for downloading with
+    /// [Downloader::download_periods].
+    ///
+    /// Periods such as ad breaks often use different IDs. Those without a
+    /// [Representation] of the same `id` contribute the one closest in
+    /// bandwidth from an [AdaptationSet] of the same content type and
+    /// language. These may be encoded differently, in which case
+    /// [Downloader::download_periods] refuses to join them.
+    pub fn stitch(&self, id: &str) -> Result<Vec<&Representation>, Error> {
+        let (reference, wanted) = self
+            .adaptation_sets()
+            .find_map(|set| {
+                set.representations
+                    .iter()
+                    .find(|r| r.id == id)
+                    .map(|r| (set, r))
+            })
+            .ok_or_else(|| Error::Unsupported(format!("no representation {}", id)))?;
+
+        self.periods
+            .iter()
+            .enumerate()
+            .map(|(i, period)| {
+                period
+                    .representation(id)
+                    .or_else(|| {
+                        period
+                            .adaptation_sets
+                            .iter()
+                            .filter(|set| {
+                                set.content_type() == reference.content_type()
+                                    && set.lang == reference.lang
+                            })
+                            .flat_map(AdaptationSet::representations)
+                            .min_by_key(|r| {
+                                (i64::from(r.bandwidth) - i64::from(wanted.bandwidth)).abs()
+                            })
+                    })
+                    .ok_or_else(|| {
+                        Error::Unsupported(format!("period {} has nothing matching {}", i, id))
+                    })
+            })
+            .collect()
+    }
+
+    /// When the first segment of a live presentation became available.
+    pub fn availability_start_time(&self) -> Option<DateTime<Utc>> {
+        self.availability_start_time
     }
 }
 
 impl TryFrom<&str> for Manifest {
     type Error = serde_xml_rs::Error;
 
-    /// Attempt to parse an XML [&str] into a [Manifest].
+    /// Attempt to parse an XML [&str] into a [Manifest]. Elements and
+    /// attributes from namespaces other than DASH's own are ignored.
     fn try_from(s: &str) -> Result<Self, Self::Error> {
-        serde_xml_rs::from_str(s)
+        let s = s.trim_start_matches('\u{feff}');
+        let mut manifest: Self = serde_xml_rs::from_str(&dash_only(s)?)?;
+        manifest.inherit();
+        manifest.resolve_base_urls();
+        Ok(manifest)
+    }
+}
+
+impl Manifest {
+    /// Pass segment information and timing down from each [Period] and
+    /// [AdaptationSet] to the [Representations](Representation) within,
+    /// so that each can list its segments on its own.
+    fn inherit(&mut self) {
+        let total = self.media_presentation_duration;
+
+        // A period without a start begins where the previous one ends.
+        let mut starts = Vec::with_capacity(self.periods.len());
+        let mut next = Some(Duration::from_secs(0));
+        for period in &self.periods {
+            let start = period.start.or(next);
+            starts.push(start);
+            next = start.zip(period.duration).map(|(s, d)| s + d);
+        }
+
+        for (i, period) in self.periods.iter_mut().enumerate() {
+            // Only the last period runs to the end of the presentation.
+            let end = match starts.get(i + 1) {
+                Some(next) => *next,
+                None => total,
+            };
+            let duration = period
+                .duration
+                .or_else(|| end.zip(starts[i]).map(|(e, s)| e.saturating_sub(s)));
+
+            period.start = starts[i];
+            period.duration = duration;
+            for set in period.adaptation_sets.iter_mut() {
+                inherit_addressing(
+                    (
+                        &mut set.segment_base,
+                        &mut set.segment_list,
+                        &mut set.segment_template,
+                    ),
+                    (
+                        &period.segment_base,
+                        &period.segment_list,
+                        &period.segment_template,
+                    ),
+                );
+
+                for rep in set.representations.iter_mut() {
+                    inherit_addressing(
+                        (
+                            &mut rep.segment_base,
+                            &mut rep.segment_list,
+                            &mut rep.segment_template,
+                        ),
+                        (&set.segment_base, &set.segment_list, &set.segment_template),
+                    );
+                    or_parent(&mut rep.mime_type, &set.mime_type);
+                    or_parent(&mut rep.codecs, &set.codecs);
+                    if rep.content_protections.is_empty() {
+                        rep.content_protections = set.content_protections.clone();
+                    }
+                    rep.period_start = starts[i];
+                    rep.period_duration = duration;
+                }
+            }
+        }
+    }
+}
+
+impl Manifest {
+    /// Resolve the BaseURLs at each level of the manifest against those of
+    /// the level above, giving each [Representation] the full list of
+    /// locations its segments may be fetched from.
+    fn resolve_base_urls(&mut self) {
+        let root: Vec<_> = self.location.iter().cloned().collect();
+        let mpd = resolve(&root, &self.base_urls);
+        for period in self.periods.iter_mut() {
+            let period_bases = resolve(&mpd, &period.base_urls);
+            for set in period.adaptation_sets.iter_mut() {
+                let set_bases = resolve(&period_bases, &set.base_urls);
+                for rep in set.representations.iter_mut() {
+                    rep.bases = resolve(&set_bases, &rep.base_urls);
+                }
+            }
+        }
+    }
+}
+
+/// Resolve the alternative `urls` given at one level of a manifest against
+/// each of the alternatives of the level above, as per RFC 3986. A level
+/// without BaseURLs shares its parent's.
+fn resolve(parents: &[Url], urls: &[BaseURL]) -> Vec<Url> {
+    if urls.is_empty() {
+        return parents.to_vec();
+    }
+
+    let mut out: Vec<Url> = Vec::new();
+    for base in urls {
+        let candidates = match Url::parse(base.url.trim()) {
+            Ok(absolute) => vec![absolute],
+            Err(_) => parents
+                .iter()
+                .filter_map(|p| p.join(base.url.trim()).ok())
+                .collect(),
+        };
+        for url in candidates {
+            if !out.contains(&url) {
+                out.push(url);
+            }
+        }
+    }
+    out
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// A location segments may be fetched from. Several at the same level of a
+/// manifest are alternatives, such as the same content on different CDNs.
+struct BaseURL {
+    #[serde(rename = "$value")]
+    pub url: String,
+
+    #[serde(rename = "serviceLocation")]
+    pub service_location: Option<String>,
+}
+
+/// Segment information given at one level of a manifest, whose attributes
+/// are inherited by the levels below it.
+trait Inherit: Clone {
+    /// Fill in whatever `self` leaves unspecified from `parent`.
+    fn inherit(&mut self, parent: &Self);
+}
+
+/// Fill in `child` from `parent`, where either may be absent.
+fn inherit<T: Inherit>(child: &mut Option<T>, parent: &Option<T>) {
+    match (child.as_mut(), parent) {
+        (Some(c), Some(p)) => c.inherit(p),
+        (None, Some(p)) => *child = Some(p.clone()),
+        _ => (),
+    }
+}
+
+/// Fill in the segment information of one level of a manifest from its
+/// parent's. A level that describes its segments one way ignores the ways
+/// its parent describes them.
+fn inherit_addressing(
+    child: (
+        &mut Option<SegmentBase>,
+        &mut Option<SegmentList>,
+        &mut Option<SegmentTemplate>,
+    ),
+    parent: (
+        &Option<SegmentBase>,
+        &Option<SegmentList>,
+        &Option<SegmentTemplate>,
+    ),
+) {
+    let (base, list, template) = child;
+    let own = base.is_some() || list.is_some() || template.is_some();
+    if !own || base.is_some() {
+        inherit(base, parent.0);
+    }
+    if !own || list.is_some() {
+        inherit(list, parent.1);
     }
+    if !own || template.is_some() {
+        inherit(template, parent.2);
+    }
+}
+
+/// Fill in an unspecified attribute from the parent's.
+fn or_parent<T: Clone>(child: &mut Option<T>, parent: &Option<T>) {
+    if child.is_none() {
+        *child = parent.clone();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// A way of synchronizing with the clock a live presentation is timed by.
+struct UTCTiming {
+    #[serde(rename = "schemeIdUri")]
+    pub scheme_id_uri: String,
+    pub value: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct Period {
+/// A span of a presentation, such as a programme or an advertisement, over
+/// which the available [AdaptationSets](AdaptationSet) do not change.
+pub struct Period {
+    id: Option<String>,
+
+    #[serde(
+        default,
+        rename = "start",
+        deserialize_with = "duration::from_iso8601_option",
+        serialize_with = "duration::to_iso8601_option"
+    )]
+    start: Option<Duration>,
+
+    #[serde(
+        default,
+        rename = "duration",
+        deserialize_with = "duration::from_iso8601_option",
+        serialize_with = "duration::to_iso8601_option"
+    )]
+    duration: Option<Duration>,
+
+    #[serde(default, rename = "BaseURL")]
+    base_urls: Vec<BaseURL>,
+
+    #[serde(rename = "SegmentBase")]
+    segment_base: Option<SegmentBase>,
+
+    #[serde(rename = "SegmentList")]
+    segment_list: Option<SegmentList>,
+
+    #[serde(rename = "SegmentTemplate")]
+    segment_template: Option<SegmentTemplate>,
+
     #[serde(default, rename = "AdaptationSet")]
-    pub adaptation_sets: Vec<AdaptationSet>,
+    adaptation_sets: Vec<AdaptationSet>,
+}
+
+impl Period {
+    /// Identifier of the [Period], if it has one.
+    pub fn id(&self) -> Option<String> {
+        self.id.clone()
+    }
+
+    /// Time at which the [Period] starts, relative to the start of the
+    /// presentation. Periods without a `start` begin where the previous one
+    /// ends.
+    pub fn start(&self) -> Option<Duration> {
+        self.start
+    }
+
+    /// Length of the [Period]. When not given, it lasts until the next
+    /// [Period] or the end of the presentation, if either is known.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// The [Representation] with the given `id`, if the [Period] has one.
+    pub fn representation(&self, id: &str) -> Option<&Representation> {
+        self.adaptation_sets
+            .iter()
+            .flat_map(AdaptationSet::representations)
+            .find(|r| r.id == id)
+    }
+
+    /// Iterator over the [AdaptationSets](AdaptationSet) of the [Period].
+    pub fn adaptation_sets(&self) -> impl Iterator<Item = &AdaptationSet> {
+        self.adaptation_sets.iter()
+    }
+
+    /// Iterator over the [AdaptationSets](AdaptationSet) in the given
+    /// language, such as `en`, which also matches regional variants such as
+    /// `en-US`.
+    pub fn by_language<'a>(&'a self, lang: &'a str) -> impl Iterator<Item = &'a AdaptationSet> {
+        self.adaptation_sets.iter().filter(move |s| {
+            s.lang.as_deref().is_some_and(|l| {
+                let primary = l.split('-').next().unwrap_or_default();
+                l.eq_ignore_ascii_case(lang) || primary.eq_ignore_ascii_case(lang)
+            })
+        })
+    }
+
+    /// Iterator over the [AdaptationSets](AdaptationSet) with the given
+    /// [Role] value, such as `main`, `alternate` or `commentary`.
+    pub fn by_role<'a>(&'a self, role: &'a str) -> impl Iterator<Item = &'a AdaptationSet> {
+        self.adaptation_sets
+            .iter()
+            .filter(move |s| s.roles.iter().any(|r| r.value == role))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -71,46 +506,437 @@ pub struct AdaptationSet {
         default,
         rename = "mimeType",
         deserialize_with = "crate::serde::mime::option_from_str",
-        serialize_with = "crate::serde::mime::option_to_str",
+        serialize_with = "crate::serde::mime::option_to_str"
     )]
     mime_type: Option<mime::Mime>,
 
     #[serde(rename = "subsegmentAlignment")]
     subsegment_alignment: Option<bool>,
 
-    #[serde(rename = "Role")]
-    role: Option<Role>,
+    #[serde(rename = "contentType")]
+    content_type: Option<String>,
+
+    lang: Option<String>,
+    codecs: Option<String>,
+
+    #[serde(default, rename = "Role")]
+    roles: Vec<Role>,
+
+    #[serde(default, rename = "ContentProtection")]
+    content_protections: Vec<ContentProtection>,
+
+    #[serde(default, rename = "BaseURL")]
+    base_urls: Vec<BaseURL>,
+
+    #[serde(rename = "SegmentBase")]
+    segment_base: Option<SegmentBase>,
+
+    #[serde(rename = "SegmentList")]
+    segment_list: Option<SegmentList>,
+
+    #[serde(rename = "SegmentTemplate")]
+    segment_template: Option<SegmentTemplate>,
 
     #[serde(rename = "Representation")]
     representations: Vec<Representation>,
 }
 
+impl AdaptationSet {
+    /// Identifier of the [AdaptationSet], if it has one.
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    /// MIME type shared by the [Representations](Representation), if given.
+    pub fn mime_type(&self) -> Option<mime::Mime> {
+        self.mime_type.clone()
+    }
+
+    /// Kind of media carried, such as `video`, `audio` or `text`. Falls
+    /// back to the top-level type of the MIME type.
+    pub fn content_type(&self) -> Option<String> {
+        self.content_type.clone().or_else(|| {
+            self.mime_type
+                .as_ref()
+                .map(|m| m.type_().as_str().to_string())
+        })
+    }
+
+    /// Language of the [AdaptationSet], as a BCP 47 tag, if given.
+    pub fn lang(&self) -> Option<String> {
+        self.lang.clone()
+    }
+
+    /// [Roles](Role) the [AdaptationSet] plays in the presentation.
+    pub fn roles(&self) -> Vec<Role> {
+        self.roles.clone()
+    }
+
+    /// Encryption or DRM applied to every [Representation] of the
+    /// [AdaptationSet].
+    pub fn content_protections(&self) -> Vec<ContentProtection> {
+        self.content_protections.clone()
+    }
+
+    /// Iterator over the [Representations](Representation) of the
+    /// [AdaptationSet].
+    pub fn representations(&self) -> impl Iterator<Item = &Representation> {
+        self.representations.iter()
+    }
+
+    /// The [Representation] with the highest bandwidth.
+    pub fn highest_bandwidth(&self) -> Option<&Representation> {
+        self.representations.iter().max_by_key(|r| r.bandwidth)
+    }
+
+    /// The [Representation] with the lowest bandwidth.
+    pub fn lowest_bandwidth(&self) -> Option<&Representation> {
+        self.representations.iter().min_by_key(|r| r.bandwidth)
+    }
+
+    /// The [Representation] whose resolution is closest to `width` by
+    /// `height` pixels, preferring the higher bandwidth between equals.
+    pub fn closest_resolution(&self, width: u32, height: u32) -> Option<&Representation> {
+        self.representations
+            .iter()
+            .filter(|r| r.width.is_some() || r.height.is_some())
+            .min_by_key(|r| {
+                let dw = i64::from(r.width.unwrap_or(0)) - i64::from(width);
+                let dh = i64::from(r.height.unwrap_or(0)) - i64::from(height);
+                (dw * dw + dh * dh, std::cmp::Reverse(r.bandwidth))
+            })
+    }
+
+    /// Iterator over the [Representations](Representation) using a codec,
+    /// matched by prefix so that `avc1` matches `avc1.4d401f`.
+    pub fn by_codec<'a>(&'a self, codec: &'a str) -> impl Iterator<Item = &'a Representation> {
+        self.representations.iter().filter(move |r| {
+            r.codecs
+                .as_deref()
+                .is_some_and(|c| c.split(',').any(|c| c.trim().starts_with(codec)))
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct Role {
+/// Role of an [AdaptationSet] in a presentation.
+pub struct Role {
     #[serde(rename = "schemeIdUri")]
-    pub scheme_id_uri: String,
-    pub value: String,
+    scheme_id_uri: String,
+    value: String,
+}
+
+impl Role {
+    /// Scheme the value is defined by, usually
+    /// `urn:mpeg:dash:role:2011`.
+    pub fn scheme_id_uri(&self) -> String {
+        self.scheme_id_uri.clone()
+    }
+
+    /// The role itself, such as `main` or `subtitle`.
+    pub fn value(&self) -> String {
+        self.value.clone()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct SegmentURL {
-    pub media: String,
+/// Encryption or DRM applied to the media of an [AdaptationSet] or
+/// [Representation]. See [crate::drm].
+pub struct ContentProtection {
+    #[serde(rename = "schemeIdUri")]
+    scheme_id_uri: String,
+
+    value: Option<String>,
+
+    // Common Encryption key ID, from the `cenc` namespace.
+    #[serde(rename = "default_KID")]
+    default_kid: Option<String>,
+
+    // Base64 `pssh` box carrying the DRM system's initialization data.
+    pssh: Option<String>,
+}
+
+impl ContentProtection {
+    /// Scheme the protection is described by, such as
+    /// `urn:mpeg:dash:mp4protection:2011` or `urn:uuid:<SystemID>`.
+    pub fn scheme_id_uri(&self) -> String {
+        self.scheme_id_uri.clone()
+    }
+
+    /// Value defined by the scheme, such as `cenc`.
+    pub fn value(&self) -> Option<String> {
+        self.value.clone()
+    }
+
+    /// Default key ID of the Common Encryption scheme, if given.
+    pub fn default_kid(&self) -> Option<String> {
+        self.default_kid.clone()
+    }
+
+    /// Base64-encoded `pssh` box for the DRM system, if given.
+    pub fn pssh(&self) -> Option<String> {
+        self.pssh.clone()
+    }
+
+    /// The DRM system the protection names, if any.
+    pub fn system(&self) -> Option<drm::System> {
+        drm::System::from_scheme(&self.scheme_id_uri)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct Initialization {
+/// A single segment in a [SegmentList].
+pub struct SegmentURL {
+    media: Option<String>,
+
+    #[serde(rename = "mediaRange")]
+    media_range: Option<String>,
+}
+
+impl SegmentURL {
+    /// URL of the segment, if it is not the BaseURL itself.
+    pub fn media(&self) -> Option<String> {
+        self.media.clone()
+    }
+
+    /// Bytes of the resource holding the segment, if not all of it.
+    pub fn media_range(&self) -> Option<String> {
+        self.media_range.clone()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Location of an initialization segment or index, as a URL, a byte range,
+/// or both.
+pub struct Initialization {
     #[serde(rename = "sourceURL")]
-    pub source_url: String,
+    source_url: Option<String>,
+
+    range: Option<String>,
+}
+
+impl Initialization {
+    /// URL of the initialization segment, if it is not the BaseURL itself.
+    pub fn source_url(&self) -> Option<String> {
+        self.source_url.clone()
+    }
+
+    /// Bytes of the resource holding the initialization segment, if not all
+    /// of it.
+    pub fn range(&self) -> Option<String> {
+        self.range.clone()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// A single resource holding every segment, optionally indexed by a `sidx`.
+struct SegmentBase {
+    pub timescale: Option<u64>,
+
+    #[serde(rename = "presentationTimeOffset")]
+    pub presentation_time_offset: Option<u64>,
+
+    #[serde(rename = "indexRange")]
+    pub index_range: Option<String>,
+
+    #[serde(rename = "Initialization")]
+    pub initialization: Option<Initialization>,
+}
+
+impl Inherit for SegmentBase {
+    fn inherit(&mut self, parent: &Self) {
+        or_parent(&mut self.timescale, &parent.timescale);
+        or_parent(
+            &mut self.presentation_time_offset,
+            &parent.presentation_time_offset,
+        );
+        or_parent(&mut self.index_range, &parent.index_range);
+        or_parent(&mut self.initialization, &parent.initialization);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 /// The list of segments
-struct SegmentList {
+pub struct SegmentList {
+    timescale: Option<u64>,
+    duration: Option<u64>,
+
+    #[serde(rename = "startNumber")]
+    start_number: Option<u64>,
+
+    #[serde(rename = "Initialization")]
+    initialization: Option<Initialization>,
+
+    #[serde(default, rename = "SegmentURL")]
+    segment_urls: Vec<SegmentURL>,
+}
+
+impl SegmentList {
+    /// The initialization segment, if the list has one.
+    pub fn initialization(&self) -> Option<Initialization> {
+        self.initialization.clone()
+    }
+
+    /// Every segment in the list, in order.
+    pub fn segment_urls(&self) -> Vec<SegmentURL> {
+        self.segment_urls.clone()
+    }
+}
+
+impl Inherit for SegmentList {
+    fn inherit(&mut self, parent: &Self) {
+        or_parent(&mut self.timescale, &parent.timescale);
+        or_parent(&mut self.duration, &parent.duration);
+        or_parent(&mut self.start_number, &parent.start_number);
+        or_parent(&mut self.initialization, &parent.initialization);
+        if self.segment_urls.is_empty() {
+            self.segment_urls = parent.segment_urls.clone();
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Segments addressed by filling in a URL template, either at a fixed
+/// duration or as listed by a [SegmentTimeline].
+struct SegmentTemplate {
+    pub media: Option<String>,
+    pub initialization: Option<String>,
+    pub timescale: Option<u64>,
+    pub duration: Option<u64>,
+
+    #[serde(rename = "startNumber")]
+    pub start_number: Option<u64>,
+
+    #[serde(rename = "presentationTimeOffset")]
+    pub presentation_time_offset: Option<u64>,
+
     #[serde(rename = "Initialization")]
-    pub initialization: Initialization,
+    pub initialization_element: Option<Initialization>,
+
+    #[serde(rename = "SegmentTimeline")]
+    pub segment_timeline: Option<SegmentTimeline>,
+}
+
+impl Inherit for SegmentTemplate {
+    fn inherit(&mut self, parent: &Self) {
+        or_parent(&mut self.media, &parent.media);
+        or_parent(&mut self.initialization, &parent.initialization);
+        or_parent(&mut self.timescale, &parent.timescale);
+        or_parent(&mut self.duration, &parent.duration);
+        or_parent(&mut self.start_number, &parent.start_number);
+        or_parent(
+            &mut self.presentation_time_offset,
+            &parent.presentation_time_offset,
+        );
+        or_parent(
+            &mut self.initialization_element,
+            &parent.initialization_element,
+        );
+        or_parent(&mut self.segment_timeline, &parent.segment_timeline);
+    }
+}
 
-    #[serde(rename = "SegmentURL")]
-    pub segment_urls: Vec<SegmentURL>,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Explicit start times and durations of the segments of a
+/// [SegmentTemplate].
+struct SegmentTimeline {
+    #[serde(default, rename = "S")]
+    pub entries: Vec<TimelineEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// A run of segments of equal duration.
+struct TimelineEntry {
+    /// Start time of the first segment, if it does not directly follow the
+    /// previous run.
+    pub t: Option<u64>,
+
+    /// Duration of each segment.
+    pub d: u64,
+
+    /// Number of times the segment repeats after the first, where `-1`
+    /// repeats it until the next run or the end of the period.
+    #[serde(default)]
+    pub r: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Errors encountered while listing the segments of a [Representation].
+pub enum Error {
+    /// The manifest describes its segments inconsistently.
+    Malformed(&'static str),
+
+    /// The manifest uses a feature maguro does not handle.
+    Unsupported(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed(reason) => write!(f, "Malformed manifest: {}", reason),
+            Error::Unsupported(what) => write!(f, "Unsupported manifest: {}", what),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single segment of a [Representation].
+pub struct Segment {
+    url: String,
+    range: Option<ByteRange>,
+    number: Option<u64>,
+    start: Duration,
+    duration: Option<Duration>,
+}
+
+impl Segment {
+    /// URL of the resource holding the segment, relative to the
+    /// [Representation]'s BaseURLs. An empty URL refers to the BaseURL
+    /// itself.
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Part of the resource holding the segment, if not all of it. A
+    /// segment running to the end of its resource ends at `u64::MAX`.
+    pub fn range(&self) -> Option<ByteRange> {
+        self.range.clone()
+    }
+
+    /// Sequence number of the segment, for segments addressed by number.
+    pub fn number(&self) -> Option<u64> {
+        self.number
+    }
+
+    /// Presentation time at which the segment starts, relative to the start
+    /// of its period.
+    pub fn start(&self) -> Duration {
+        self.start
+    }
+
+    /// Length of the segment, if known.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
+
+impl Display for Representation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let resolution = match (self.width, self.height) {
+            (Some(w), Some(h)) => format!("{}x{}", w, h),
+            _ => String::new(),
+        };
+        write!(
+            f,
+            "id: {:>6} | Bandwidth: {:>9} | Resolution: {:<9} | Codecs: {}",
+            self.id,
+            self.bandwidth,
+            resolution,
+            self.codecs.as_deref().unwrap_or("unknown")
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -122,33 +948,38 @@ pub struct Representation {
     height: Option<u32>,
     sar: Option<String>,
 
+    // Either a whole number or a fraction, such as `30000/1001`.
     #[serde(rename = "frameRate")]
-    frame_rate: Option<u32>,
+    frame_rate: Option<String>,
 
     #[serde(
         default,
         rename = "mimeType",
         deserialize_with = "crate::serde::mime::option_from_str",
-        serialize_with = "crate::serde::mime::option_to_str",
+        serialize_with = "crate::serde::mime::option_to_str"
     )]
     mime_type: Option<mime::Mime>,
 
+    codecs: Option<String>,
+
     // Subelements
-    #[serde(rename = "BaseURL")]
-    base_urls: Option<Vec<String>>,
+    #[serde(default, rename = "ContentProtection")]
+    content_protections: Vec<ContentProtection>,
+
+    #[serde(default, rename = "BaseURL")]
+    base_urls: Vec<BaseURL>,
 
     #[serde(rename = "SubRepresentation")]
     sub_representations: Option<Vec<Representation>>,
 
-    // TODO
     #[serde(rename = "SegmentBase")]
-    segment_base: Option<SegmentURL>,
+    segment_base: Option<SegmentBase>,
 
     #[serde(rename = "SegmentList")]
     segment_list: Option<SegmentList>,
 
     #[serde(rename = "SegmentTemplate")]
-    segment_template: Option<SegmentURL>,
+    segment_template: Option<SegmentTemplate>,
 
     // Attributes
     id: String,
@@ -162,12 +993,903 @@ pub struct Representation {
 
     #[serde(rename = "mediaStreamStructureId")]
     media_stream_structure_id: Option<String>,
+
+    // Timing of the enclosing period, filled in once the whole manifest has
+    // been read.
+    #[serde(skip)]
+    period_start: Option<Duration>,
+
+    #[serde(skip)]
+    period_duration: Option<Duration>,
+
+    // Every location the segments may be fetched from, in order of
+    // preference.
+    #[serde(skip)]
+    bases: Vec<Url>,
+}
+
+impl Representation {
+    /// Identifier of the [Representation], unique within its [Period].
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Bits per second needed to stream the [Representation].
+    pub fn bandwidth(&self) -> u32 {
+        self.bandwidth
+    }
+
+    /// Width of the video in pixels, if any.
+    pub fn width(&self) -> Option<u32> {
+        self.width
+    }
+
+    /// Height of the video in pixels, if any.
+    pub fn height(&self) -> Option<u32> {
+        self.height
+    }
+
+    /// Frames per second of the video, if any.
+    pub fn frame_rate(&self) -> Option<f64> {
+        let rate = self.frame_rate.as_deref()?;
+        match rate.split_once('/') {
+            Some((n, d)) => Some(n.parse::<f64>().ok()? / d.parse::<f64>().ok()?),
+            None => rate.parse().ok(),
+        }
+    }
+
+    /// MIME type of the [Representation], if given here or by its
+    /// [AdaptationSet].
+    pub fn mime_type(&self) -> Option<mime::Mime> {
+        self.mime_type.clone()
+    }
+
+    /// Codecs used, as an RFC 6381 list such as `avc1.4d401f,mp4a.40.2`,
+    /// if given here or by its [AdaptationSet].
+    pub fn codecs(&self) -> Option<String> {
+        self.codecs.clone()
+    }
+
+    /// Relative quality among [Representations](Representation) of the
+    /// same [AdaptationSet], where lower is better.
+    pub fn quality_ranking(&self) -> Option<u32> {
+        self.quality_ranking
+    }
+
+    /// The [SegmentList] describing the [Representation]'s segments, if it
+    /// has one. See [Representation::segments] for any kind of addressing.
+    pub fn segment_list(&self) -> Option<SegmentList> {
+        self.segment_list.clone()
+    }
+
+    /// Every absolute URL one of the [Representation]'s segments can be
+    /// fetched from, in order of preference.
+    pub fn locate(&self, segment: &Segment) -> Result<Vec<String>, Error> {
+        if let Ok(absolute) = Url::parse(&segment.url) {
+            return Ok(vec![absolute.into()]);
+        }
+
+        let urls: Vec<String> = self
+            .bases
+            .iter()
+            .filter_map(|base| base.join(&segment.url).ok())
+            .map(String::from)
+            .collect();
+        if urls.is_empty() {
+            return Err(Error::Unsupported(format!(
+                "segment {} without an absolute BaseURL",
+                segment.url
+            )));
+        }
+        Ok(urls)
+    }
+
+    /// Every [ContentProtection] applying to the [Representation],
+    /// including those of its [AdaptationSet].
+    pub fn content_protections(&self) -> Vec<ContentProtection> {
+        self.content_protections.clone()
+    }
+
+    /// Whether the media of the [Representation] is encrypted.
+    pub fn is_protected(&self) -> bool {
+        !self.content_protections.is_empty()
+    }
+
+    /// The DRM systems the [Representation] may be decrypted with.
+    pub fn drm_systems(&self) -> Vec<drm::System> {
+        let mut systems = Vec::new();
+        for system in self
+            .content_protections
+            .iter()
+            .filter_map(ContentProtection::system)
+        {
+            if !systems.contains(&system) {
+                systems.push(system);
+            }
+        }
+        systems
+    }
+
+    #[cfg(feature = "client")]
+    /// Fails with [drm::Protected] if the [Representation] is encrypted.
+    pub(crate) fn check_unprotected(&self) -> Result<(), drm::Protected> {
+        if self.is_protected() {
+            return Err(drm::Protected::new(self.drm_systems()));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "client")]
+    /// Downloads the initialization segment and every media segment of the
+    /// [Representation] into a `File`. See [Downloader] for control over
+    /// concurrency, retries and resuming.
+    pub async fn download(
+        &self,
+        dest: &mut tokio::fs::File,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        Downloader::new().download(self, dest).await?;
+        Ok(())
+    }
+
+    /// Byte range of the `sidx` index of a [Representation] stored as a
+    /// single resource. See [crate::index::sidx].
+    pub fn index_range(&self) -> Result<Option<ByteRange>, Error> {
+        self.segment_base
+            .as_ref()
+            .and_then(|b| b.index_range.as_deref())
+            .map(parse_range)
+            .transpose()
+    }
+
+    /// The initialization segment of the [Representation], if it has one.
+    pub fn initialization(&self) -> Result<Option<Segment>, Error> {
+        let segment = |init: &Initialization| -> Result<Segment, Error> {
+            Ok(Segment {
+                url: init.source_url.clone().unwrap_or_default(),
+                range: init.range.as_deref().map(parse_range).transpose()?,
+                number: None,
+                start: Duration::from_secs(0),
+                duration: None,
+            })
+        };
+
+        if let Some(template) = &self.segment_template {
+            if let Some(url) = &template.initialization {
+                return Ok(Some(Segment {
+                    url: self.fill(url, None, None)?,
+                    range: None,
+                    number: None,
+                    start: Duration::from_secs(0),
+                    duration: None,
+                }));
+            }
+            if let Some(init) = &template.initialization_element {
+                return segment(init).map(Some);
+            }
+        }
+        if let Some(init) = self
+            .segment_list
+            .as_ref()
+            .and_then(|l| l.initialization.as_ref())
+        {
+            return segment(init).map(Some);
+        }
+        if let Some(init) = self
+            .segment_base
+            .as_ref()
+            .and_then(|b| b.initialization.as_ref())
+        {
+            return segment(init).map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Every media segment of the [Representation], in order.
+    ///
+    /// A [Representation] stored as a single resource yields one segment
+    /// covering everything after its initialization segment; its
+    /// [index](Representation::index_range) can be used to split it
+    /// further.
+    pub fn segments(&self) -> Result<Vec<Segment>, Error> {
+        self.segments_between(Duration::from_secs(0), self.period_duration)
+    }
+
+    /// The media segments of a live [Representation] that have become
+    /// available `elapsed` after the start of its period, in order. Only
+    /// those ending after `since` are listed, so a long presentation can be
+    /// followed without listing everything since it began.
+    pub fn available_segments(
+        &self,
+        since: Duration,
+        elapsed: Duration,
+    ) -> Result<Vec<Segment>, Error> {
+        let until = self.period_duration.map_or(elapsed, |d| d.min(elapsed));
+        Ok(self
+            .segments_between(since, Some(until))?
+            .into_iter()
+            .filter(|s| s.start + s.duration.unwrap_or_default() <= elapsed)
+            .collect())
+    }
+
+    /// Presentation time offset of the media timeline, which begins this
+    /// long before the start of the [Period].
+    pub fn presentation_time_offset(&self) -> Duration {
+        let (offset, timescale) = match (&self.segment_template, &self.segment_base) {
+            (Some(t), _) => (t.presentation_time_offset, t.timescale),
+            (None, Some(b)) => (b.presentation_time_offset, b.timescale),
+            (None, None) => (None, None),
+        };
+        ticks(offset.unwrap_or(0), timescale.unwrap_or(1).max(1))
+    }
+
+    /// Every media segment ending after `from` and starting before `until`,
+    /// if given, or else the end of the [Representation]'s timeline.
+    fn segments_between(
+        &self,
+        from: Duration,
+        until: Option<Duration>,
+    ) -> Result<Vec<Segment>, Error> {
+        if let Some(template) = &self.segment_template {
+            return self.template_segments(template, from, until);
+        }
+        if let Some(list) = &self.segment_list {
+            let timescale = list.timescale.unwrap_or(1).max(1);
+            let start_number = list.start_number.unwrap_or(1);
+            return list
+                .segment_urls
+                .iter()
+                .enumerate()
+                .map(|(i, s)| {
+                    Ok(Segment {
+                        url: s.media.clone().unwrap_or_default(),
+                        range: s.media_range.as_deref().map(parse_range).transpose()?,
+                        number: Some(start_number + i as u64),
+                        start: list
+                            .duration
+                            .map_or(Duration::from_secs(0), |d| ticks(i as u64 * d, timescale)),
+                        duration: list.duration.map(|d| ticks(d, timescale)),
+                    })
+                })
+                .filter(|s| {
+                    s.as_ref()
+                        .map_or(true, |s| s.duration.is_none_or(|d| s.start + d > from))
+                })
+                .collect();
+        }
+        Ok(vec![Segment {
+            url: String::new(),
+            range: self.media_range()?,
+            number: None,
+            start: Duration::from_secs(0),
+            duration: self.period_duration,
+        }])
+    }
+
+    /// The part of a single resource following its initialization segment,
+    /// if that is stored in it, through to its end. The index, if any, is
+    /// left in place.
+    fn media_range(&self) -> Result<Option<ByteRange>, Error> {
+        let init = self
+            .segment_base
+            .as_ref()
+            .and_then(|b| b.initialization.as_ref())
+            .filter(|i| i.source_url.is_none())
+            .and_then(|i| i.range.as_deref())
+            .map(parse_range)
+            .transpose()?;
+        Ok(init.map(|r| ByteRange {
+            start: r.end().saturating_add(1),
+            end: u64::MAX,
+        }))
+    }
+
+    /// Expand a [SegmentTemplate] into the segments ending after `from`,
+    /// following its timeline if it has one.
+    fn template_segments(
+        &self,
+        template: &SegmentTemplate,
+        from: Duration,
+        until: Option<Duration>,
+    ) -> Result<Vec<Segment>, Error> {
+        let media = template
+            .media
+            .as_deref()
+            .ok_or(Error::Malformed("SegmentTemplate without a media URL"))?;
+        let timescale = template.timescale.unwrap_or(1).max(1);
+        let offset = template.presentation_time_offset.unwrap_or(0);
+        let mut number = template.start_number.unwrap_or(1);
+        let media_time =
+            |d: Duration| offset + (d.as_nanos() * u128::from(timescale) / 1_000_000_000) as u64;
+        let begin = media_time(from);
+        let end = until.map(media_time);
+
+        let mut segments = Vec::new();
+        let mut push = |time: u64, duration: u64, number: u64| -> Result<(), Error> {
+            segments.push(Segment {
+                url: self.fill(media, Some(number), Some(time))?,
+                range: None,
+                number: Some(number),
+                start: ticks(time.saturating_sub(offset), timescale),
+                duration: Some(ticks(duration, timescale)),
+            });
+            Ok(())
+        };
+
+        match &template.segment_timeline {
+            Some(timeline) => {
+                let mut time = 0;
+                for (i, entry) in timeline.entries.iter().enumerate() {
+                    if entry.d == 0 {
+                        return Err(Error::Malformed("SegmentTimeline entry without a duration"));
+                    }
+                    time = entry.t.unwrap_or(time);
+                    let count = if entry.r < 0 {
+                        let until = timeline
+                            .entries
+                            .get(i + 1)
+                            .and_then(|next| next.t)
+                            .or(end)
+                            .ok_or_else(|| {
+                                Error::Unsupported("open-ended SegmentTimeline".into())
+                            })?;
+                        until.saturating_sub(time).div_ceil(entry.d)
+                    } else {
+                        entry.r as u64 + 1
+                    };
+                    // Segments ending by `begin` are skipped without being
+                    // expanded.
+                    let skip = (begin.saturating_sub(time) / entry.d).min(count);
+                    time += skip * entry.d;
+                    number += skip;
+                    for _ in skip..count {
+                        push(time, entry.d, number)?;
+                        time += entry.d;
+                        number += 1;
+                    }
+                }
+            }
+            None => {
+                let duration = template
+                    .duration
+                    .filter(|d| *d > 0)
+                    .ok_or(Error::Malformed(
+                        "SegmentTemplate without a duration or SegmentTimeline",
+                    ))?;
+                let end = end.ok_or_else(|| {
+                    Error::Unsupported("numbered segments of a period without a duration".into())
+                })?;
+                let skip = begin.saturating_sub(offset) / duration;
+                let mut time = offset + skip * duration;
+                number += skip;
+                while time < end {
+                    push(time, duration, number)?;
+                    time += duration;
+                    number += 1;
+                }
+            }
+        }
+        Ok(segments)
+    }
+
+    /// Fill in the identifiers of a URL template, such as `$Number%05d$`.
+    fn fill(
+        &self,
+        template: &str,
+        number: Option<u64>,
+        time: Option<u64>,
+    ) -> Result<String, Error> {
+        let mut out = String::with_capacity(template.len());
+        let mut parts = template.split('$');
+        out.push_str(parts.next().unwrap_or_default());
+        while let Some(identifier) = parts.next() {
+            let rest = parts
+                .next()
+                .ok_or(Error::Malformed("unterminated template identifier"))?;
+
+            let (name, width) = match identifier.split_once('%') {
+                Some((name, format)) => {
+                    let width = format
+                        .strip_prefix('0')
+                        .unwrap_or(format)
+                        .strip_suffix('d')
+                        .and_then(|w| {
+                            if w.is_empty() {
+                                Some(0)
+                            } else {
+                                w.parse().ok()
+                            }
+                        })
+                        .ok_or(Error::Malformed("invalid template width"))?;
+                    (name, width)
+                }
+                None => (identifier, 0),
+            };
+            let value = match name {
+                "" => "$".to_string(),
+                "RepresentationID" => self.id.clone(),
+                "Bandwidth" => self.bandwidth.to_string(),
+                "Number" => number
+                    .ok_or(Error::Malformed("$Number$ outside a media template"))?
+                    .to_string(),
+                "Time" => time
+                    .ok_or(Error::Malformed("$Time$ outside a media template"))?
+                    .to_string(),
+                other => {
+                    return Err(Error::Unsupported(format!(
+                        "template identifier ${}$",
+                        other
+                    )))
+                }
+            };
+            out.push_str(&format!("{:0>width$}", value, width = width));
+            out.push_str(rest);
+        }
+        Ok(out)
+    }
+}
+
+/// Parse a byte range of the form `first-last`.
+fn parse_range(s: &str) -> Result<ByteRange, Error> {
+    ByteRange::from_str(s).map_err(|_| Error::Malformed("invalid byte range"))
+}
+
+/// Namespace of the elements and attributes of an MPD.
+const MPD_NAMESPACE: &str = "urn:mpeg:dash:schema:mpd:2011";
+
+/// Namespace of the Common Encryption additions to `ContentProtection`.
+const CENC_NAMESPACE: &str = "urn:mpeg:cenc:2013";
+
+/// Rewrite the MPD document `s` without the elements and attributes of
+/// namespaces other than DASH and Common Encryption, such as those of DRM
+/// vendors or players, whose names could otherwise be mistaken for DASH
+/// ones. Fails unless the root element is an `MPD` in the DASH namespace,
+/// or in none at all.
+fn dash_only(s: &str) -> Result<String, serde_xml_rs::Error> {
+    use ::xml::{reader::XmlEvent, EmitterConfig, EventReader};
+
+    let is_dash = |ns: &Option<String>| ns.as_deref().is_none_or(|ns| ns == MPD_NAMESPACE);
+    let is_known = |ns: &Option<String>| is_dash(ns) || ns.as_deref() == Some(CENC_NAMESPACE);
+    let mut out = Vec::with_capacity(s.len());
+    let mut writer = EmitterConfig::new()
+        .write_document_declaration(false)
+        .create_writer(&mut out);
+    let mut root = true;
+    // Depth within a foreign element being skipped.
+    let mut skipping = 0;
+    for event in EventReader::from_str(s) {
+        let event = match event? {
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                if root && (name.local_name != "MPD" || !is_dash(&name.namespace)) {
+                    return Err(serde_xml_rs::Error::Custom {
+                        field: format!("{} is not a DASH manifest", name),
+                    });
+                }
+                root = false;
+                if skipping > 0 || !is_known(&name.namespace) {
+                    skipping += 1;
+                    continue;
+                }
+                XmlEvent::StartElement {
+                    name,
+                    attributes: attributes
+                        .into_iter()
+                        .filter(|a| {
+                            a.name.namespace.is_none()
+                                || a.name.namespace.as_deref() == Some(CENC_NAMESPACE)
+                        })
+                        .collect(),
+                    namespace,
+                }
+            }
+            XmlEvent::EndElement { .. } if skipping > 0 => {
+                skipping -= 1;
+                continue;
+            }
+            _ if skipping > 0 => continue,
+            event => event,
+        };
+        if let Some(event) = event.as_writer_event() {
+            writer
+                .write(event)
+                .map_err(|e| serde_xml_rs::Error::Custom {
+                    field: e.to_string(),
+                })?;
+        }
+    }
+    Ok(String::from_utf8(out)?)
+}
+
+/// Convert `t` ticks of `timescale` per second to a [Duration].
+fn ticks(t: u64, timescale: u64) -> Duration {
+    Duration::from_nanos((u128::from(t) * 1_000_000_000 / u128::from(timescale)) as u64)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const TEMPLATED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT9S">
+  <Period>
+    <SegmentTemplate timescale="1000" duration="4000" startNumber="1"
+        initialization="$RepresentationID$/init.mp4" media="$RepresentationID$/$Number%03d$.m4s"/>
+    <AdaptationSet mimeType="video/mp4">
+      <Representation id="720p" bandwidth="2000000" width="1280" height="720"/>
+      <Representation id="360p" bandwidth="500000" width="640" height="360">
+        <SegmentTemplate timescale="90000" media="$Bandwidth$-$Time$.m4s">
+          <SegmentTimeline>
+            <S t="90000" d="180000" r="1"/>
+            <S d="90000" r="-1"/>
+            <S t="900000" d="45000"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4">
+      <Representation id="audio" bandwidth="128000">
+        <BaseURL>audio.mp4</BaseURL>
+        <SegmentBase indexRange="700-999">
+          <Initialization range="0-699"/>
+        </SegmentBase>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+    fn representations(m: &Manifest) -> Vec<&Representation> {
+        m.periods[0]
+            .adaptation_sets
+            .iter()
+            .flat_map(|s| s.representations.iter())
+            .collect()
+    }
+
+    #[test]
+    fn numbered_template() {
+        let m = Manifest::try_from(TEMPLATED).unwrap();
+        let rep = representations(&m)[0];
+
+        assert_eq!(
+            rep.initialization().unwrap().unwrap().url(),
+            "720p/init.mp4"
+        );
+        let segments = rep.segments().unwrap();
+        let urls: Vec<_> = segments.iter().map(|s| s.url()).collect();
+        assert_eq!(urls, vec!["720p/001.m4s", "720p/002.m4s", "720p/003.m4s"]);
+        assert_eq!(segments[2].start(), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn timeline_template() {
+        let m = Manifest::try_from(TEMPLATED).unwrap();
+        let rep = representations(&m)[1];
+
+        // The initialization template is inherited from the period.
+        assert_eq!(
+            rep.initialization().unwrap().unwrap().url(),
+            "360p/init.mp4"
+        );
+        let segments = rep.segments().unwrap();
+        let urls: Vec<_> = segments.iter().map(|s| s.url()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "500000-90000.m4s",
+                "500000-270000.m4s",
+                "500000-450000.m4s",
+                "500000-540000.m4s",
+                "500000-630000.m4s",
+                "500000-720000.m4s",
+                "500000-810000.m4s",
+                "500000-900000.m4s",
+            ]
+        );
+        assert_eq!(segments[7].number(), Some(8));
+        assert_eq!(segments[7].duration(), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn lists_segments_since() {
+        let m = Manifest::try_from(TEMPLATED).unwrap();
+        let reps = representations(&m);
+
+        let numbers = |rep: &Representation, since: u64, elapsed: u64| -> Vec<_> {
+            rep.available_segments(Duration::from_secs(since), Duration::from_secs(elapsed))
+                .unwrap()
+                .iter()
+                .map(|s| s.number().unwrap())
+                .collect()
+        };
+        assert_eq!(numbers(reps[0], 5, 9), vec![2]);
+        assert_eq!(numbers(reps[0], 0, 9), vec![1, 2]);
+        // Segments ending exactly at `since` are left out.
+        assert_eq!(numbers(reps[1], 5, 9), vec![3, 4, 5, 6]);
+        assert_eq!(numbers(reps[1], 0, 4), vec![1]);
+    }
+
+    #[test]
+    fn segment_base() {
+        let m = Manifest::try_from(TEMPLATED).unwrap();
+        let rep = representations(&m)[2];
+
+        let init = rep.initialization().unwrap().unwrap();
+        assert_eq!(init.url(), "");
+        assert_eq!(init.range(), Some("0-699".parse().unwrap()));
+        assert_eq!(rep.index_range().unwrap(), Some("700-999".parse().unwrap()));
+        // The rest of the file follows the initialization segment.
+        let segments = rep.segments().unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].range().map(|r| r.start()), Some(700));
+        assert_eq!(segments[0].range().map(|r| r.end()), Some(u64::MAX));
+    }
+
+    #[test]
+    fn locates_segments() {
+        let mut m = Manifest::try_from(TEMPLATED).unwrap();
+        let rep = representations(&m)[0];
+        let segment = &rep.segments().unwrap()[0];
+        assert!(rep.locate(segment).is_err());
+
+        m.set_location("https://cdn.example.com/v/manifest.mpd")
+            .unwrap();
+        let rep = representations(&m)[0];
+        assert_eq!(
+            rep.locate(segment).unwrap(),
+            vec!["https://cdn.example.com/v/720p/001.m4s"]
+        );
+        let rep = representations(&m)[2];
+        let init = rep.initialization().unwrap().unwrap();
+        assert_eq!(
+            rep.locate(&init).unwrap(),
+            vec!["https://cdn.example.com/v/audio.mp4"]
+        );
+    }
+
+    #[test]
+    fn base_url_hierarchy() {
+        let mut m = Manifest::try_from(
+            r#"<MPD type="static" mediaPresentationDuration="PT4S">
+  <BaseURL serviceLocation="a">https://a.example.com/media/</BaseURL>
+  <BaseURL serviceLocation="b">https://b.example.com/mirror/media/</BaseURL>
+  <Period>
+    <BaseURL>../period-1/</BaseURL>
+    <AdaptationSet>
+      <BaseURL>/absolute/</BaseURL>
+      <Representation id="1" bandwidth="1">
+        <BaseURL>video.mp4?sig=1</BaseURL>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet>
+      <Representation id="2" bandwidth="1">
+        <BaseURL>https://c.example.com/audio.mp4</BaseURL>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+        )
+        .unwrap();
+        m.set_location("https://origin.example.com/manifest.mpd")
+            .unwrap();
+
+        let reps = representations(&m);
+        let whole = reps[0].segments().unwrap().remove(0);
+        assert_eq!(
+            reps[0].locate(&whole).unwrap(),
+            vec![
+                "https://a.example.com/absolute/video.mp4?sig=1",
+                "https://b.example.com/absolute/video.mp4?sig=1",
+            ]
+        );
+        assert_eq!(
+            reps[1].locate(&whole).unwrap(),
+            vec!["https://c.example.com/audio.mp4"]
+        );
+    }
+
+    #[test]
+    fn stitches_periods() {
+        let manifest = Manifest::try_from(
+            r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT50S">
+  <Period id="main" duration="PT30S">
+    <SegmentTemplate timescale="1" duration="10" media="$RepresentationID$/$Number$.m4s"/>
+    <AdaptationSet mimeType="video/mp4">
+      <Representation id="hd" bandwidth="3000000"/>
+      <Representation id="sd" bandwidth="1000000"/>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4" lang="en">
+      <Representation id="en" bandwidth="128000"/>
+    </AdaptationSet>
+  </Period>
+  <Period id="ad" duration="PT10S">
+    <SegmentTemplate timescale="90000" duration="900000" presentationTimeOffset="900000"
+        media="ad/$RepresentationID$/$Number$.m4s"/>
+    <AdaptationSet mimeType="video/mp4">
+      <Representation id="ad-high" bandwidth="2500000"/>
+      <Representation id="ad-low" bandwidth="500000"/>
+    </AdaptationSet>
+  </Period>
+  <Period id="rest">
+    <SegmentTemplate timescale="1" duration="10" media="$RepresentationID$/$Number$.m4s"/>
+    <AdaptationSet mimeType="video/mp4">
+      <Representation id="hd" bandwidth="3000000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+        )
+        .unwrap();
+
+        let timing: Vec<_> = manifest
+            .periods()
+            .map(|p| (p.start(), p.duration()))
+            .collect();
+        let secs = |s| Some(Duration::from_secs(s));
+        assert_eq!(
+            timing,
+            vec![
+                (secs(0), secs(30)),
+                (secs(30), secs(10)),
+                (secs(40), secs(10))
+            ]
+        );
+
+        let hd: Vec<_> = manifest
+            .stitch("hd")
+            .unwrap()
+            .iter()
+            .map(|r| r.id())
+            .collect();
+        assert_eq!(hd, vec!["hd", "ad-high", "hd"]);
+        let ad = manifest
+            .periods()
+            .nth(1)
+            .unwrap()
+            .representation("ad-high")
+            .unwrap();
+        assert_eq!(ad.presentation_time_offset(), Duration::from_secs(10));
+        assert_eq!(ad.segments().unwrap().len(), 1);
+
+        // The audio has nothing to continue with during the ad break.
+        assert!(manifest.stitch("en").is_err());
+        assert!(manifest.stitch("missing").is_err());
+    }
+
+    #[tokio::test]
+    async fn content_protection() {
+        let manifest = Manifest::try_from(
+            r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:cenc="urn:mpeg:cenc:2013" type="static">
+  <Period>
+    <AdaptationSet mimeType="video/mp4">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cenc"
+          cenc:default_KID="9eb4050d-e44b-4802-932e-27d75083e266"/>
+      <ContentProtection schemeIdUri="urn:uuid:edef8ba9-79d6-4ace-a3c8-27dcd51d21ed">
+        <cenc:pssh>AAAANHBzc2g=</cenc:pssh>
+      </ContentProtection>
+      <Representation id="v" bandwidth="1"><BaseURL>https://cdn.example/v.mp4</BaseURL></Representation>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4">
+      <Representation id="a" bandwidth="1"><BaseURL>https://cdn.example/a.mp4</BaseURL></Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+        )
+        .unwrap();
+
+        let video = manifest.representations().find(|r| r.id() == "v").unwrap();
+        assert!(video.is_protected());
+        assert_eq!(video.drm_systems(), vec![drm::System::Widevine]);
+        let protections = video.content_protections();
+        assert_eq!(
+            protections[0].default_kid().as_deref(),
+            Some("9eb4050d-e44b-4802-932e-27d75083e266")
+        );
+        assert_eq!(protections[1].pssh().as_deref(), Some("AAAANHBzc2g="));
+        let audio = manifest.representations().find(|r| r.id() == "a").unwrap();
+        assert!(!audio.is_protected());
+
+        let written = Manifest::try_from(manifest.to_xml().as_str()).unwrap();
+        let video = written.representations().find(|r| r.id() == "v").unwrap();
+        assert_eq!(video.drm_systems(), vec![drm::System::Widevine]);
+
+        #[cfg(feature = "client")]
+        {
+            let path = std::env::temp_dir().join("maguro-content-protection.mp4");
+            let mut dest = tokio::fs::File::create(&path).await.unwrap();
+            let error = video.download(&mut dest).await.unwrap_err();
+            assert!(error.downcast_ref::<drm::Protected>().is_some());
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn selects_representations() {
+        let m = Manifest::try_from(
+            r#"<MPD type="static" mediaPresentationDuration="PT1M">
+  <Period id="main" start="PT0S">
+    <AdaptationSet mimeType="video/mp4" codecs="avc1.4d401f">
+      <Representation id="1080" bandwidth="5000000" width="1920" height="1080" frameRate="30000/1001"/>
+      <Representation id="720" bandwidth="2500000" width="1280" height="720"/>
+      <Representation id="360" bandwidth="600000" width="640" height="360"/>
+      <Representation id="720-vp9" bandwidth="2000000" width="1280" height="720" codecs="vp09.00.31.08"/>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4" lang="en-US">
+      <Role schemeIdUri="urn:mpeg:dash:role:2011" value="main"/>
+      <Representation id="en" bandwidth="128000" codecs="mp4a.40.2"/>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4" lang="fr">
+      <Role schemeIdUri="urn:mpeg:dash:role:2011" value="dub"/>
+      <Representation id="fr" bandwidth="96000" codecs="mp4a.40.2"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+        )
+        .unwrap();
+
+        assert_eq!(m.representations().count(), 6);
+        let period = m.periods().next().unwrap();
+        assert_eq!(period.id().as_deref(), Some("main"));
+        assert_eq!(period.start(), Some(Duration::from_secs(0)));
+
+        let video = period.adaptation_sets().next().unwrap();
+        assert_eq!(video.content_type().as_deref(), Some("video"));
+        assert_eq!(video.highest_bandwidth().unwrap().id(), "1080");
+        assert_eq!(video.lowest_bandwidth().unwrap().id(), "360");
+        assert_eq!(video.closest_resolution(1200, 700).unwrap().id(), "720");
+        assert_eq!(
+            video.by_codec("avc1").map(|r| r.id()).collect::<Vec<_>>(),
+            vec!["1080", "720", "360"]
+        );
+        let rate = video.highest_bandwidth().unwrap().frame_rate().unwrap();
+        assert!((rate - 29.97).abs() < 0.01);
+
+        let english: Vec<_> = period.by_language("en").collect();
+        assert_eq!(english.len(), 1);
+        assert_eq!(english[0].roles()[0].value(), "main");
+        assert_eq!(
+            period.by_role("dub").next().unwrap().lang().as_deref(),
+            Some("fr")
+        );
+    }
+
+    #[test]
+    fn namespaces() {
+        let manifest = Manifest::try_from(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<mpd:MPD xmlns:mpd="urn:mpeg:dash:schema:mpd:2011" xmlns:x="urn:example:player"
+    type="static" mediaPresentationDuration="PT1M30.5S">
+  <mpd:Period id="第一部" x:duration="PT5S">
+    <x:BaseURL>https://elsewhere.example/</x:BaseURL>
+    <mpd:AdaptationSet mimeType="audio/mp4" lang="ja">
+      <mpd:Representation id="a" bandwidth="1">
+        <mpd:BaseURL>https://cdn.example/a.mp4</mpd:BaseURL>
+      </mpd:Representation>
+    </mpd:AdaptationSet>
+  </mpd:Period>
+</mpd:MPD>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            manifest.media_presentation_duration(),
+            Some(Duration::from_secs_f64(90.5))
+        );
+        let period = manifest.periods().next().unwrap();
+        assert_eq!(period.id().as_deref(), Some("第一部"));
+        // The foreign `x:duration` is ignored, so the period lasts as long
+        // as the presentation.
+        assert_eq!(period.duration(), Some(Duration::from_secs_f64(90.5)));
+        let rep = manifest.representations().next().unwrap();
+        assert_eq!(
+            rep.bases,
+            vec![Url::parse("https://cdn.example/a.mp4").unwrap()]
+        );
+
+        assert!(Manifest::try_from(r#"<MPD xmlns="urn:example:other" type="static"/>"#).is_err());
+    }
+
+    #[cfg(feature = "client")]
     #[tokio::test]
     /// Tests against a known simple multi-resolution manifest.
     async fn from_url() {
@@ -0,0 +1,515 @@
+//! Writing a [Manifest] back out as MPD XML.
+//!
+//! serde-xml-rs can only write fields as child elements, while MPD keeps
+//! most of its information in attributes, so the document is assembled by
+//! hand from a small tree of [Elements](Element).
+
+use chrono::SecondsFormat;
+use std::{collections::BTreeMap, time::Duration};
+
+use super::{
+    AdaptationSet, BaseURL, ContentProtection, Initialization, Manifest, Period, Representation,
+    Role, SegmentBase, SegmentList, SegmentTemplate, SegmentTimeline, UTCTiming, CENC_NAMESPACE,
+    MPD_NAMESPACE,
+};
+use crate::{
+    drm::{self, MP4_PROTECTION_SCHEME},
+    serde::duration::to_iso8601,
+    Format,
+};
+
+/// An XML element waiting to be written.
+struct Element {
+    name: &'static str,
+    attributes: Vec<(&'static str, String)>,
+    children: Vec<Element>,
+    text: Option<String>,
+}
+
+impl Element {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: None,
+        }
+    }
+
+    /// Adds an attribute, if it has a value.
+    fn attr<T: ToString>(mut self, name: &'static str, value: Option<T>) -> Self {
+        if let Some(value) = value {
+            self.attributes.push((name, value.to_string()));
+        }
+        self
+    }
+
+    fn children<I: IntoIterator<Item = Element>>(mut self, children: I) -> Self {
+        self.children.extend(children);
+        self
+    }
+
+    fn text(mut self, text: &str) -> Self {
+        self.text = Some(text.to_string());
+        self
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth);
+        out.push_str(&indent);
+        out.push('<');
+        out.push_str(self.name);
+        for (name, value) in &self.attributes {
+            out.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+        }
+
+        match (&self.text, self.children.is_empty()) {
+            (Some(text), _) => out.push_str(&format!(">{}</{}>\n", escape(text), self.name)),
+            (None, true) => out.push_str("/>\n"),
+            (None, false) => {
+                out.push_str(">\n");
+                for child in &self.children {
+                    child.write(out, depth + 1);
+                }
+                out.push_str(&format!("{}</{}>\n", indent, self.name));
+            }
+        }
+    }
+}
+
+/// Escape the characters XML reserves in text and attribute values.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Conversion of the dash types into [Elements](Element).
+trait ToElement {
+    fn to_element(&self) -> Element;
+}
+
+/// The element for `value`, if present.
+fn optional<T: ToElement>(value: &Option<T>) -> Option<Element> {
+    value.as_ref().map(ToElement::to_element)
+}
+
+impl Manifest {
+    /// Writes the [Manifest] as an MPD document.
+    pub fn to_xml(&self) -> String {
+        let root = Element::new("MPD")
+            .attr("xmlns", Some(MPD_NAMESPACE))
+            .attr(
+                "xmlns:cenc",
+                self.representations()
+                    .any(Representation::is_protected)
+                    .then_some(CENC_NAMESPACE),
+            )
+            .attr("type", Some(&self.mpd_type))
+            .attr("profiles", self.profiles.as_ref())
+            .attr("minBufferTime", self.min_buffer_time.map(to_iso8601))
+            .attr(
+                "mediaPresentationDuration",
+                self.media_presentation_duration.map(to_iso8601),
+            )
+            .attr(
+                "availabilityStartTime",
+                self.availability_start_time
+                    .map(|t| t.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            )
+            .attr(
+                "minimumUpdatePeriod",
+                self.minimum_update_period.map(to_iso8601),
+            )
+            .attr(
+                "timeShiftBufferDepth",
+                self.time_shift_buffer_depth.map(to_iso8601),
+            )
+            .children(self.base_urls.iter().map(ToElement::to_element))
+            .children(self.periods.iter().map(ToElement::to_element))
+            .children(self.utc_timings.iter().map(ToElement::to_element));
+
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        root.write(&mut out, 0);
+        out
+    }
+
+    /// A static [Manifest] offering every indexed adaptive [Format] of a
+    /// video, grouped into one [AdaptationSet] per MIME type.
+    pub(crate) fn from_formats(formats: &[Format], duration: Option<Duration>) -> Self {
+        let mut groups: BTreeMap<String, Vec<&Format>> = BTreeMap::new();
+        for format in formats {
+            if format.init_range.is_some() && format.index_range.is_some() {
+                groups
+                    .entry(format.mime_type.essence_str().to_string())
+                    .or_default()
+                    .push(format);
+            }
+        }
+
+        let adaptation_sets = groups
+            .into_iter()
+            .enumerate()
+            .map(|(i, (mime_type, formats))| AdaptationSet {
+                segment_alignment: None,
+                id: Some(i as u32),
+                mime_type: mime_type.parse().ok(),
+                subsegment_alignment: Some(true),
+                content_type: None,
+                lang: None,
+                codecs: None,
+                roles: Vec::new(),
+                content_protections: Vec::new(),
+                base_urls: Vec::new(),
+                segment_base: None,
+                segment_list: None,
+                segment_template: None,
+                representations: formats.into_iter().map(representation).collect(),
+            })
+            .collect();
+
+        let mut manifest = Self {
+            periods: vec![Period {
+                id: None,
+                start: Some(Duration::from_secs(0)),
+                duration: None,
+                base_urls: Vec::new(),
+                segment_base: None,
+                segment_list: None,
+                segment_template: None,
+                adaptation_sets,
+            }],
+            mpd_type: "static".into(),
+            profiles: Some("urn:mpeg:dash:profile:isoff-on-demand:2011".into()),
+            min_buffer_time: Some(Duration::from_millis(1500)),
+            media_presentation_duration: duration,
+            base_urls: Vec::new(),
+            availability_start_time: None,
+            minimum_update_period: None,
+            time_shift_buffer_depth: None,
+            utc_timings: Vec::new(),
+            location: None,
+        };
+        manifest.inherit();
+        manifest.resolve_base_urls();
+        manifest
+    }
+}
+
+/// A [Representation] of a single indexed [Format], stored as one resource.
+fn representation(format: &Format) -> Representation {
+    let range = |r: &crate::ByteRange| format!("{}-{}", r.start(), r.end());
+    Representation {
+        profiles: None,
+        width: format.width,
+        height: format.height,
+        sar: None,
+        frame_rate: format.fps.map(|fps| fps.to_string()),
+        mime_type: None,
+        codecs: format
+            .mime_type
+            .get_param("codecs")
+            .map(|c| c.as_str().to_string()),
+        content_protections: content_protections(&format.drm_systems()),
+        base_urls: vec![BaseURL {
+            url: format.url.clone(),
+            service_location: None,
+        }],
+        sub_representations: None,
+        segment_base: Some(SegmentBase {
+            timescale: None,
+            presentation_time_offset: None,
+            index_range: format.index_range.as_ref().map(range),
+            initialization: Some(Initialization {
+                source_url: None,
+                range: format.init_range.as_ref().map(range),
+            }),
+        }),
+        segment_list: None,
+        segment_template: None,
+        id: format.itag.to_string(),
+        bandwidth: format.bitrate.unwrap_or_default(),
+        quality_ranking: None,
+        dependency_id: None,
+        media_stream_structure_id: None,
+        period_start: None,
+        period_duration: None,
+        bases: Vec::new(),
+    }
+}
+
+/// `ContentProtection` elements declaring Common Encryption by `systems`,
+/// or none if the list is empty.
+fn content_protections(systems: &[drm::System]) -> Vec<ContentProtection> {
+    if systems.is_empty() {
+        return Vec::new();
+    }
+    let generic = ContentProtection {
+        scheme_id_uri: MP4_PROTECTION_SCHEME.to_string(),
+        value: Some("cenc".into()),
+        default_kid: None,
+        pssh: None,
+    };
+    let named = systems
+        .iter()
+        .filter_map(drm::System::scheme_id_uri)
+        .map(|scheme| ContentProtection {
+            scheme_id_uri: scheme.to_string(),
+            value: None,
+            default_kid: None,
+            pssh: None,
+        });
+    std::iter::once(generic).chain(named).collect()
+}
+
+impl ToElement for BaseURL {
+    fn to_element(&self) -> Element {
+        Element::new("BaseURL")
+            .attr("serviceLocation", self.service_location.as_ref())
+            .text(&self.url)
+    }
+}
+
+impl ToElement for UTCTiming {
+    fn to_element(&self) -> Element {
+        Element::new("UTCTiming")
+            .attr("schemeIdUri", Some(&self.scheme_id_uri))
+            .attr("value", Some(&self.value))
+    }
+}
+
+impl ToElement for Period {
+    fn to_element(&self) -> Element {
+        Element::new("Period")
+            .attr("id", self.id.as_ref())
+            .attr("start", self.start.map(to_iso8601))
+            .attr("duration", self.duration.map(to_iso8601))
+            .children(self.base_urls.iter().map(ToElement::to_element))
+            .children(optional(&self.segment_base))
+            .children(optional(&self.segment_list))
+            .children(optional(&self.segment_template))
+            .children(self.adaptation_sets.iter().map(ToElement::to_element))
+    }
+}
+
+impl ToElement for AdaptationSet {
+    fn to_element(&self) -> Element {
+        Element::new("AdaptationSet")
+            .attr("id", self.id)
+            .attr("mimeType", self.mime_type.as_ref())
+            .attr("segmentAlignment", self.segment_alignment)
+            .attr("subsegmentAlignment", self.subsegment_alignment)
+            .attr("contentType", self.content_type.as_ref())
+            .attr("lang", self.lang.as_ref())
+            .attr("codecs", self.codecs.as_ref())
+            .children(self.roles.iter().map(ToElement::to_element))
+            .children(self.content_protections.iter().map(ToElement::to_element))
+            .children(self.base_urls.iter().map(ToElement::to_element))
+            .children(optional(&self.segment_base))
+            .children(optional(&self.segment_list))
+            .children(optional(&self.segment_template))
+            .children(self.representations.iter().map(ToElement::to_element))
+    }
+}
+
+impl ToElement for Role {
+    fn to_element(&self) -> Element {
+        Element::new("Role")
+            .attr("schemeIdUri", Some(&self.scheme_id_uri))
+            .attr("value", Some(&self.value))
+    }
+}
+
+impl ToElement for ContentProtection {
+    fn to_element(&self) -> Element {
+        Element::new("ContentProtection")
+            .attr("schemeIdUri", Some(&self.scheme_id_uri))
+            .attr("value", self.value.as_ref())
+            .attr("cenc:default_KID", self.default_kid.as_ref())
+            .children(
+                self.pssh
+                    .as_ref()
+                    .map(|pssh| Element::new("cenc:pssh").text(pssh)),
+            )
+    }
+}
+
+impl ToElement for Representation {
+    fn to_element(&self) -> Element {
+        Element::new("Representation")
+            .attr("id", Some(&self.id))
+            .attr("bandwidth", Some(self.bandwidth))
+            .attr("profiles", self.profiles.as_ref())
+            .attr("width", self.width)
+            .attr("height", self.height)
+            .attr("sar", self.sar.as_ref())
+            .attr("frameRate", self.frame_rate.as_ref())
+            .attr("mimeType", self.mime_type.as_ref())
+            .attr("codecs", self.codecs.as_ref())
+            .attr("qualityRanking", self.quality_ranking)
+            .attr("dependencyId", self.dependency_id.as_ref())
+            .attr(
+                "mediaStreamStructureId",
+                self.media_stream_structure_id.as_ref(),
+            )
+            .children(self.content_protections.iter().map(ToElement::to_element))
+            .children(self.base_urls.iter().map(ToElement::to_element))
+            .children(self.sub_representations.iter().flatten().map(|r| Element {
+                name: "SubRepresentation",
+                ..r.to_element()
+            }))
+            .children(optional(&self.segment_base))
+            .children(optional(&self.segment_list))
+            .children(optional(&self.segment_template))
+    }
+}
+
+/// The `Initialization` element for a location.
+fn initialization(init: &Initialization) -> Element {
+    Element::new("Initialization")
+        .attr("sourceURL", init.source_url.as_ref())
+        .attr("range", init.range.as_ref())
+}
+
+impl ToElement for SegmentBase {
+    fn to_element(&self) -> Element {
+        Element::new("SegmentBase")
+            .attr("timescale", self.timescale)
+            .attr("presentationTimeOffset", self.presentation_time_offset)
+            .attr("indexRange", self.index_range.as_ref())
+            .children(self.initialization.iter().map(initialization))
+    }
+}
+
+impl ToElement for SegmentList {
+    fn to_element(&self) -> Element {
+        Element::new("SegmentList")
+            .attr("timescale", self.timescale)
+            .attr("duration", self.duration)
+            .attr("startNumber", self.start_number)
+            .children(self.initialization.iter().map(initialization))
+            .children(self.segment_urls.iter().map(|s| {
+                Element::new("SegmentURL")
+                    .attr("media", s.media.as_ref())
+                    .attr("mediaRange", s.media_range.as_ref())
+            }))
+    }
+}
+
+impl ToElement for SegmentTemplate {
+    fn to_element(&self) -> Element {
+        Element::new("SegmentTemplate")
+            .attr("media", self.media.as_ref())
+            .attr("initialization", self.initialization.as_ref())
+            .attr("timescale", self.timescale)
+            .attr("duration", self.duration)
+            .attr("startNumber", self.start_number)
+            .attr("presentationTimeOffset", self.presentation_time_offset)
+            .children(self.initialization_element.iter().map(initialization))
+            .children(optional(&self.segment_timeline))
+    }
+}
+
+impl ToElement for SegmentTimeline {
+    fn to_element(&self) -> Element {
+        Element::new("SegmentTimeline").children(self.entries.iter().map(|s| {
+            Element::new("S")
+                .attr("t", s.t)
+                .attr("d", Some(s.d))
+                .attr("r", if s.r != 0 { Some(s.r) } else { None })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn writes_youtube_formats() {
+        let formats: Vec<Format> = serde_json::from_str(
+            r#"[
+                {
+                    "itag": 248, "url": "https://r1.googlevideo.com/videoplayback?itag=248&mime=video%2Fwebm",
+                    "mimeType": "video/webm; codecs=\"vp9\"", "bitrate": 2500000,
+                    "width": 1920, "height": 1080, "fps": 30, "quality": "hd1080",
+                    "contentLength": "51000000", "approxDurationMs": "212000",
+                    "initRange": {"start": "0", "end": "219"},
+                    "indexRange": {"start": "220", "end": "999"}
+                },
+                {
+                    "itag": 251, "url": "https://r1.googlevideo.com/videoplayback?itag=251",
+                    "mimeType": "audio/webm; codecs=\"opus\"", "bitrate": 140000,
+                    "quality": "tiny",
+                    "initRange": {"start": "0", "end": "258"},
+                    "indexRange": {"start": "259", "end": "700"}
+                },
+                {
+                    "itag": 18, "url": "https://r1.googlevideo.com/videoplayback?itag=18",
+                    "mimeType": "video/mp4; codecs=\"avc1.42001E, mp4a.40.2\"",
+                    "width": 640, "height": 360, "quality": "medium"
+                }
+            ]"#,
+        )
+        .unwrap();
+
+        let xml = Manifest::from_formats(&formats, Some(Duration::from_millis(212_000))).to_xml();
+        assert!(xml.contains(r#"mediaPresentationDuration="PT212S""#));
+        assert!(xml.contains("itag=248&amp;mime=video%2Fwebm"));
+
+        // Unindexed formats are left out.
+        let parsed = Manifest::try_from(xml.as_str()).unwrap();
+        let sets = &parsed.periods[0].adaptation_sets;
+        assert_eq!(sets.len(), 2);
+
+        let video = &sets[1].representations[0];
+        assert_eq!(video.id, "248");
+        assert_eq!(video.bandwidth, 2_500_000);
+        assert_eq!(video.codecs.as_deref(), Some("vp9"));
+        assert_eq!(
+            video.bases[0].as_str(),
+            "https://r1.googlevideo.com/videoplayback?itag=248&mime=video%2Fwebm"
+        );
+        assert_eq!(
+            video.initialization().unwrap().unwrap().range(),
+            Some("0-219".parse().unwrap())
+        );
+        assert_eq!(
+            video.index_range().unwrap(),
+            Some("220-999".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn round_trips_templates() {
+        let source = r#"<MPD type="static" mediaPresentationDuration="PT6S">
+  <Period>
+    <AdaptationSet mimeType="video/mp4">
+      <Representation id="a" bandwidth="1">
+        <SegmentTemplate timescale="10" media="$Time$.m4s">
+          <SegmentTimeline>
+            <S t="0" d="20" r="-1"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+        let original = Manifest::try_from(source).unwrap();
+        let written = Manifest::try_from(original.to_xml().as_str()).unwrap();
+
+        let segments = |m: &Manifest| m.periods[0].adaptation_sets[0].representations[0].segments();
+        assert_eq!(segments(&written), segments(&original));
+        assert_eq!(segments(&written).unwrap().len(), 3);
+    }
+}
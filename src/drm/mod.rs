@@ -0,0 +1,156 @@
+//! Detection of DRM-protected media.
+//!
+//! Protected streams are encrypted with keys only a licensed player can
+//! obtain, so downloading them produces a file nothing can decode.
+//! [Formats](crate::Format), DASH [Representations](crate::dash::Representation)
+//! and HLS [MediaPlaylists](crate::hls::MediaPlaylist) all report the
+//! [Systems](System) protecting them, and refuse to download with a
+//! [Protected] error. Callers can tell it apart from other failures
+//! with `error.downcast_ref::<Protected>()`.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    error,
+    fmt::{self, Display},
+};
+
+/// Scheme of a DASH `ContentProtection` signalling Common Encryption,
+/// without naming a DRM system.
+pub const MP4_PROTECTION_SCHEME: &str = "urn:mpeg:dash:mp4protection:2011";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+/// A DRM system protecting a stream.
+pub enum System {
+    Widevine,
+    PlayReady,
+    FairPlay,
+    ClearKey,
+    /// Any other system, by its DASH scheme or YouTube name.
+    Other(String),
+}
+
+impl System {
+    /// The system identified by a DASH `ContentProtection` scheme of the form
+    /// `urn:uuid:<SystemID>`, or [None] for schemes not naming a system,
+    /// such as [MP4_PROTECTION_SCHEME].
+    pub fn from_scheme(scheme_id_uri: &str) -> Option<Self> {
+        let uuid = scheme_id_uri
+            .strip_prefix("urn:uuid:")
+            .or_else(|| scheme_id_uri.strip_prefix("URN:UUID:"))?
+            .to_ascii_lowercase();
+        Some(match uuid.as_str() {
+            "edef8ba9-79d6-4ace-a3c8-27dcd51d21ed" => System::Widevine,
+            "9a04f079-9840-4286-ab92-e65be0885f95" => System::PlayReady,
+            "94ce86fb-07ff-4f43-adb8-93d2fa968ca2" => System::FairPlay,
+            "e2719d58-a985-b3c9-781a-b030af78d30e" => System::ClearKey,
+            _ => System::Other(scheme_id_uri.to_string()),
+        })
+    }
+
+    /// The system named by an entry of a YouTube format's `drmFamilies`,
+    /// such as `WIDEVINE`.
+    pub fn from_family(family: &str) -> Self {
+        match family.to_ascii_uppercase().as_str() {
+            "WIDEVINE" => System::Widevine,
+            "PLAYREADY" => System::PlayReady,
+            "FAIRPLAY" => System::FairPlay,
+            "CLEARKEY" => System::ClearKey,
+            _ => System::Other(family.to_string()),
+        }
+    }
+
+    /// The system named by the `KEYFORMAT` of an HLS `EXT-X-KEY`, or [None]
+    /// for `identity`, the format of plain AES keys.
+    pub fn from_key_format(key_format: &str) -> Option<Self> {
+        match key_format {
+            "identity" => None,
+            "com.apple.streamingkeydelivery" => Some(System::FairPlay),
+            "com.microsoft.playready" => Some(System::PlayReady),
+            _ => System::from_scheme(key_format)
+                .or_else(|| Some(System::Other(key_format.to_string()))),
+        }
+    }
+
+    /// The DASH `ContentProtection` scheme identifying the system, if known.
+    pub fn scheme_id_uri(&self) -> Option<&'static str> {
+        match self {
+            System::Widevine => Some("urn:uuid:edef8ba9-79d6-4ace-a3c8-27dcd51d21ed"),
+            System::PlayReady => Some("urn:uuid:9a04f079-9840-4286-ab92-e65be0885f95"),
+            System::FairPlay => Some("urn:uuid:94ce86fb-07ff-4f43-adb8-93d2fa968ca2"),
+            System::ClearKey => Some("urn:uuid:e2719d58-a985-b3c9-781a-b030af78d30e"),
+            System::Other(_) => None,
+        }
+    }
+}
+
+impl Display for System {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            System::Widevine => write!(f, "Widevine"),
+            System::PlayReady => write!(f, "PlayReady"),
+            System::FairPlay => write!(f, "FairPlay"),
+            System::ClearKey => write!(f, "ClearKey"),
+            System::Other(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Error returned when asked to download DRM-protected media.
+pub struct Protected {
+    systems: Vec<System>,
+}
+
+impl Protected {
+    /// An error for media protected by `systems`, which may be empty when
+    /// the media is only known to be encrypted.
+    pub fn new(systems: Vec<System>) -> Self {
+        Self { systems }
+    }
+
+    /// The DRM systems protecting the media, if named.
+    pub fn systems(&self) -> Vec<System> {
+        self.systems.clone()
+    }
+}
+
+impl Display for Protected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Media is protected by DRM")?;
+        if !self.systems.is_empty() {
+            let names: Vec<_> = self.systems.iter().map(System::to_string).collect();
+            write!(f, " ({})", names.join(", "))?;
+        }
+        write!(f, " and cannot be downloaded")
+    }
+}
+
+impl error::Error for Protected {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_systems() {
+        assert_eq!(
+            System::from_scheme("urn:uuid:EDEF8BA9-79D6-4ACE-A3C8-27DCD51D21ED"),
+            Some(System::Widevine)
+        );
+        assert_eq!(System::from_scheme(MP4_PROTECTION_SCHEME), None);
+        assert_eq!(System::from_family("PLAYREADY"), System::PlayReady);
+        assert_eq!(System::from_key_format("identity"), None);
+        assert_eq!(
+            System::from_key_format("com.apple.streamingkeydelivery"),
+            Some(System::FairPlay)
+        );
+
+        let error: Box<dyn error::Error + Send + Sync> =
+            Protected::new(vec![System::Widevine, System::PlayReady]).into();
+        assert_eq!(
+            error.to_string(),
+            "Media is protected by DRM (Widevine, PlayReady) and cannot be downloaded"
+        );
+        assert!(error.downcast_ref::<Protected>().is_some());
+    }
+}
@@ -0,0 +1,352 @@
+//! Downloading a [MediaPlaylist] segment by segment into a single file.
+//!
+//! Segments are fetched several at a time but written strictly in order, as
+//! with the DASH [Downloader](crate::dash::Downloader), so a [Progress] can
+//! resume an interrupted download from the last whole segment. The
+//! initialization section of each [Map](super::Map) is written ahead of the
+//! first segment needing it, giving one contiguous TS or fragmented MP4
+//! stream.
+//!
+//! Segments encrypted with AES-128 are decrypted as they arrive, with the
+//! IV of their key or, failing that, their media sequence number. Playlists
+//! encrypted in any other way are refused with
+//! [Protected](crate::drm::Protected) before anything is fetched.
+
+use aes::{
+    cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit},
+    Aes128,
+};
+use hyper::{Body, Client};
+use hyper_tls::HttpsConnector;
+use log::warn;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    error,
+    sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
+};
+use tokio::fs::File;
+
+use super::{Error, Key, Map, MediaPlaylist, Progress, Segment};
+use crate::{
+    dash::{self, Fetch, HttpsClient},
+    ByteRange,
+};
+
+/// The key and IV to decrypt with.
+type Decryption = ([u8; 16], [u8; 16]);
+
+/// A segment or initialization section waiting to be fetched.
+pub(super) struct Job {
+    url: String,
+    range: Option<ByteRange>,
+
+    // Key and IV to decrypt the contents with, if encrypted.
+    decrypt: Option<Decryption>,
+}
+
+impl Job {
+    /// Fetches the initialization section of `map`, given the contents of
+    /// the AES-128 `keys` by URI.
+    pub(super) fn map(
+        map: &Map,
+        keys: &HashMap<String, [u8; 16]>,
+    ) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        Ok(Self {
+            url: map.uri.clone(),
+            range: map.range.clone(),
+            decrypt: decryption(map.keys.iter().find(|k| k.is_aes128()), None, keys)?,
+        })
+    }
+
+    /// Fetches `segment`, given the contents of the AES-128 `keys` by URI.
+    pub(super) fn segment(
+        segment: &Segment,
+        keys: &HashMap<String, [u8; 16]>,
+    ) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        Ok(Self {
+            url: segment.uri.clone(),
+            range: segment.range.clone(),
+            decrypt: decryption(segment.aes128_key().as_ref(), Some(segment.sequence), keys)?,
+        })
+    }
+
+    /// Fetches the contents, decrypting them if need be.
+    pub(super) async fn fetch(
+        self,
+        client: HttpsClient,
+        options: dash::Downloader,
+        preferred: Arc<AtomicUsize>,
+    ) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+        let data = dash::fetch(client, vec![self.url], self.range, options, preferred).await?;
+        match self.decrypt {
+            Some((key, iv)) => decrypt(&data, &key, &iv),
+            None => Ok(data),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Fetches the segments of a [MediaPlaylist] and writes them to a `File`.
+/// Concurrency, retries and resumption work as with the DASH
+/// [Downloader](dash::Downloader), which does the fetching.
+pub struct Downloader(dash::Downloader);
+
+impl Downloader {
+    /// A [Downloader] fetching four segments at a time, giving each request
+    /// 30 seconds, and retrying each segment up to three times.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of segments fetched at once.
+    pub fn concurrency(self, concurrency: usize) -> Self {
+        Self(self.0.concurrency(concurrency))
+    }
+
+    /// Sets the number of times a failed segment is retried before giving
+    /// up.
+    pub fn retries(self, retries: u32) -> Self {
+        Self(self.0.retries(retries))
+    }
+
+    /// Sets how long a single request may take before it is abandoned and
+    /// retried.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self(self.0.timeout(timeout))
+    }
+
+    /// Continues an earlier download from the [Progress] it last reported.
+    /// Anything in the `File` past that point is discarded.
+    pub fn resume(self, progress: Progress) -> Self {
+        Self(self.0.resume(progress))
+    }
+
+    /// Downloads every segment of `playlist` into `dest`. Segments marked
+    /// as gaps are skipped. Fails with [Protected](crate::drm::Protected)
+    /// before fetching anything if the playlist is encrypted with anything
+    /// but AES-128.
+    pub async fn download(
+        &self,
+        playlist: &MediaPlaylist,
+        dest: &mut File,
+    ) -> Result<Progress, Box<dyn error::Error + Send + Sync>> {
+        self.download_callback(playlist, dest, |_| Ok(())).await
+    }
+
+    /// Downloads `playlist` into `dest`, calling the closure with the
+    /// [Progress] made after each segment is written. Saving it allows the
+    /// download to be [resumed](Downloader::resume).
+    pub async fn download_callback<T>(
+        &self,
+        playlist: &MediaPlaylist,
+        dest: &mut File,
+        on_segment: T,
+    ) -> Result<Progress, Box<dyn error::Error + Send + Sync>>
+    where
+        T: Fn(Progress) -> Result<(), Box<dyn error::Error + Send + Sync>>,
+    {
+        playlist.check_unprotected()?;
+
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+        let preferred = Arc::new(AtomicUsize::new(0));
+
+        let mut keys = HashMap::new();
+        self.fetch_keys(&client, &preferred, playlist.segments(), &mut keys)
+            .await?;
+
+        let fetches = plan(playlist, &keys)?
+            .into_iter()
+            .map(|job| {
+                Box::pin(job.fetch(client.clone(), self.0.clone(), preferred.clone())) as Fetch
+            })
+            .collect();
+        self.0.run_fetches(fetches, dest, on_segment).await
+    }
+
+    /// Adds the contents of the AES-128 keys of `segments`, and of their
+    /// maps, to `keys`, by URI. Keys are few and shared by many segments, so
+    /// each is only fetched once.
+    pub(super) async fn fetch_keys<'a>(
+        &self,
+        client: &HttpsClient,
+        preferred: &Arc<AtomicUsize>,
+        segments: impl Iterator<Item = &'a Segment>,
+        keys: &mut HashMap<String, [u8; 16]>,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        for key in segments.flat_map(aes128_keys) {
+            let uri = key
+                .uri()
+                .ok_or(Error::Malformed("EXT-X-KEY without a URI"))?;
+            if keys.contains_key(&uri) {
+                continue;
+            }
+            let data = dash::fetch(
+                client.clone(),
+                vec![uri.clone()],
+                None,
+                self.0.clone(),
+                preferred.clone(),
+            )
+            .await?;
+            let data = <[u8; 16]>::try_from(data.as_slice())
+                .map_err(|_| Error::Malformed("AES-128 key is not 16 bytes long"))?;
+            keys.insert(uri, data);
+        }
+        Ok(())
+    }
+
+    /// Options for fetching a single resource.
+    pub(super) fn fetch_options(&self) -> dash::Downloader {
+        self.0.clone()
+    }
+}
+
+/// The AES-128 keys of `segment` and of its map.
+fn aes128_keys(segment: &Segment) -> impl Iterator<Item = Key> {
+    let map_key = segment
+        .map
+        .as_ref()
+        .and_then(|m| m.keys.iter().find(|k| k.is_aes128()).cloned());
+    segment.aes128_key().into_iter().chain(map_key)
+}
+
+/// Lists what to fetch for `playlist`, given the contents of its AES-128
+/// `keys` by URI.
+fn plan(
+    playlist: &MediaPlaylist,
+    keys: &HashMap<String, [u8; 16]>,
+) -> Result<Vec<Job>, Box<dyn error::Error + Send + Sync>> {
+    let mut jobs = Vec::new();
+    let mut last_map = None;
+    for segment in playlist.segments() {
+        if segment.gap {
+            warn!("Skipping segment {}, marked as a gap", segment.sequence);
+            continue;
+        }
+        if let Some(map) = segment.map.as_ref().filter(|&m| last_map != Some(m)) {
+            jobs.push(Job::map(map, keys)?);
+            last_map = Some(map);
+        }
+        jobs.push(Job::segment(segment, keys)?);
+    }
+    Ok(jobs)
+}
+
+/// The key and IV to decrypt with `key`, if any, given the contents of the
+/// AES-128 `keys` by URI. Without an IV of its own, that of a segment is its
+/// `sequence` number.
+fn decryption(
+    key: Option<&Key>,
+    sequence: Option<u64>,
+    keys: &HashMap<String, [u8; 16]>,
+) -> Result<Option<Decryption>, Box<dyn error::Error + Send + Sync>> {
+    let key = match key {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    let uri = key
+        .uri
+        .as_ref()
+        .ok_or(Error::Malformed("EXT-X-KEY without a URI"))?;
+    let data = keys
+        .get(uri)
+        .ok_or_else(|| format!("The AES-128 key at {} was not fetched", uri))?;
+    let iv = match (key.iv, sequence) {
+        (Some(iv), _) => iv,
+        (None, Some(sequence)) => u128::from(sequence).to_be_bytes(),
+        (None, None) => {
+            return Err(
+                Error::Malformed("encrypted EXT-X-MAP without an IV in its EXT-X-KEY").into(),
+            )
+        }
+    };
+    Ok(Some((*data, iv)))
+}
+
+/// Decrypts AES-128 CBC `data` padded with PKCS#7.
+fn decrypt(
+    data: &[u8],
+    key: &[u8; 16],
+    iv: &[u8; 16],
+) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    cbc::Decryptor::<Aes128>::new(key.into(), iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|_| "Failed to decrypt segment; the key or IV is wrong".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockEncryptMut;
+
+    #[test]
+    fn fetches_map_keys() {
+        let playlist = MediaPlaylist::try_from(
+            "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-KEY:METHOD=AES-128,URI=\"https://example.com/map-key\",IV=0x000102030405060708090A0B0C0D0E0F
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXT-X-KEY:METHOD=NONE
+#EXTINF:4,
+https://example.com/0.m4s
+",
+        )
+        .unwrap();
+
+        // Only the map is encrypted, but its key is still fetched.
+        let segment = playlist.segments().next().unwrap();
+        let uris: Vec<_> = aes128_keys(segment).map(|k| k.uri()).collect();
+        assert_eq!(uris, vec![Some("https://example.com/map-key".to_string())]);
+
+        let err = plan(&playlist, &HashMap::new()).err().unwrap();
+        assert!(err.to_string().contains("was not fetched"));
+        let keys = vec![("https://example.com/map-key".to_string(), [1; 16])]
+            .into_iter()
+            .collect();
+        let jobs = plan(&playlist, &keys).unwrap();
+        assert!(jobs[0].decrypt.is_some());
+        assert!(jobs[1].decrypt.is_none());
+    }
+
+    #[test]
+    fn plans_decryption() {
+        let playlist = MediaPlaylist::try_from(
+            "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-MEDIA-SEQUENCE:7
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXT-X-KEY:METHOD=AES-128,URI=\"https://example.com/key\"
+#EXTINF:4,
+https://example.com/7.m4s
+#EXT-X-GAP
+#EXTINF:4,
+https://example.com/8.m4s
+#EXTINF:4,
+https://example.com/9.m4s
+",
+        )
+        .unwrap();
+        let key = [7; 16];
+        let keys = vec![("https://example.com/key".to_string(), key)]
+            .into_iter()
+            .collect();
+        let jobs = plan(&playlist, &keys).unwrap();
+
+        // The map comes before the key, so it is in the clear, and is only
+        // fetched once.
+        assert_eq!(jobs.len(), 3);
+        assert_eq!(jobs[0].url, "init.mp4");
+        assert!(jobs[0].decrypt.is_none());
+        assert_eq!(jobs[2].url, "https://example.com/9.m4s");
+        let (_, iv) = jobs[2].decrypt.unwrap();
+        assert_eq!(iv[15], 9);
+
+        let plain = b"a segment shorter than three blocks".to_vec();
+        let encrypted = cbc::Encryptor::<Aes128>::new(&key.into(), &iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(&plain);
+        assert_eq!(decrypt(&encrypted, &key, &iv).unwrap(), plain);
+        assert!(decrypt(&encrypted[1..], &key, &iv).is_err());
+    }
+}
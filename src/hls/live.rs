@@ -0,0 +1,405 @@
+//! Recording live HLS streams.
+//!
+//! The [MediaPlaylist] of a live stream lists only its most recent
+//! segments, and is fetched again about once every target duration as more
+//! are published. A [Recorder] follows it, appending each new segment to
+//! its output once, until the playlist ends with `EXT-X-ENDLIST` or stops
+//! being updated. Output may be split across several files, rotated after
+//! a given duration or size.
+//!
+//! Segments that are skipped over, whether marked as gaps or dropped from
+//! the playlist before they could be fetched, are logged and listed in the
+//! resulting [Recording].
+
+use hyper::{Body, Client};
+use hyper_tls::HttpsConnector;
+use log::{info, warn};
+use std::{
+    collections::HashMap,
+    error,
+    ops::RangeInclusive,
+    path::Path,
+    sync::{atomic::AtomicUsize, Arc},
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use super::{download::Job, Downloader, Map, MediaPlaylist, Segment};
+
+#[derive(Debug, Clone, Default)]
+/// What a [Recorder] wrote.
+pub struct Recording {
+    files: Vec<String>,
+    segments: usize,
+    bytes: u64,
+    duration: Duration,
+    gaps: Vec<RangeInclusive<u64>>,
+}
+
+impl Recording {
+    /// Paths of the files written, in order.
+    pub fn files(&self) -> Vec<String> {
+        self.files.clone()
+    }
+
+    /// Number of segments written.
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    /// Number of bytes written, across every file.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Duration of the segments written.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Media sequence numbers of the segments missing from the recording.
+    pub fn gaps(&self) -> Vec<RangeInclusive<u64>> {
+        self.gaps.clone()
+    }
+
+    /// Notes that the segments in `missing` were skipped.
+    fn gap(&mut self, missing: RangeInclusive<u64>) {
+        warn!(
+            "Segments {} to {} are missing from the recording",
+            missing.start(),
+            missing.end()
+        );
+        match self.gaps.last_mut() {
+            Some(last) if last.end() + 1 == *missing.start() => {
+                *last = *last.start()..=*missing.end()
+            }
+            _ => self.gaps.push(missing),
+        }
+    }
+}
+
+/// Segments of a live [MediaPlaylist] already accounted for.
+#[derive(Debug, Default)]
+struct Seen {
+    last: Option<u64>,
+}
+
+impl Seen {
+    /// The segments of `playlist` following those already seen, along with
+    /// the sequence numbers of any skipped in between.
+    fn fresh<'a>(
+        &mut self,
+        playlist: &'a MediaPlaylist,
+    ) -> (Vec<&'a Segment>, Option<RangeInclusive<u64>>) {
+        let fresh: Vec<_> = playlist
+            .segments()
+            .filter(|s| self.last.is_none_or(|last| s.sequence > last))
+            .collect();
+        let skipped = match (self.last, fresh.first()) {
+            (Some(last), Some(first)) if first.sequence > last + 1 => {
+                Some(last + 1..=first.sequence - 1)
+            }
+            _ => None,
+        };
+        if let Some(last) = fresh.last() {
+            self.last = Some(last.sequence);
+        }
+        (fresh, skipped)
+    }
+}
+
+/// The file a [Recorder] is currently writing to.
+struct Part {
+    file: File,
+    bytes: u64,
+    duration: Duration,
+    map: Option<Map>,
+}
+
+#[derive(Debug, Clone)]
+/// Records a live [MediaPlaylist] into one or more files.
+pub struct Recorder {
+    url: String,
+    downloader: Downloader,
+    rotate_every: Option<Duration>,
+    rotate_at: Option<u64>,
+    stall_timeout: Option<Duration>,
+}
+
+impl Recorder {
+    /// A [Recorder] for the media playlist at `url`, such as
+    /// [Variant::uri](super::Variant::uri), writing everything to a single
+    /// file.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            downloader: Downloader::new(),
+            rotate_every: None,
+            rotate_at: None,
+            stall_timeout: None,
+        }
+    }
+
+    /// Sets the retries and timeout segments are fetched with.
+    pub fn downloader(mut self, downloader: Downloader) -> Self {
+        self.downloader = downloader;
+        self
+    }
+
+    /// Starts a new file once the current one holds `duration` of media.
+    pub fn rotate_every(mut self, duration: Duration) -> Self {
+        self.rotate_every = Some(duration);
+        self
+    }
+
+    /// Starts a new file once the current one reaches `bytes` in size.
+    pub fn rotate_at(mut self, bytes: u64) -> Self {
+        self.rotate_at = Some(bytes.max(1));
+        self
+    }
+
+    /// Sets how long the playlist may go without new segments before the
+    /// stream is taken to have ended. Defaults to six target durations.
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Whether output is split across several files.
+    fn rotates(&self) -> bool {
+        self.rotate_every.is_some() || self.rotate_at.is_some()
+    }
+
+    /// Records the stream into `path` until it ends. When rotating, files
+    /// are numbered from one before their extension instead, as in
+    /// `stream.001.ts`. Fails with [Protected](crate::drm::Protected) if
+    /// the stream is encrypted with anything but AES-128.
+    pub async fn record(
+        &self,
+        path: &str,
+    ) -> Result<Recording, Box<dyn error::Error + Send + Sync>> {
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+        let preferred = Arc::new(AtomicUsize::new(0));
+
+        let mut recording = Recording::default();
+        let mut seen = Seen::default();
+        let mut keys = HashMap::new();
+        let mut part: Option<Part> = None;
+        let mut updated = Instant::now();
+
+        loop {
+            let playlist = match MediaPlaylist::from_url(&self.url).await {
+                Ok(playlist) => Some(playlist),
+                Err(e) => {
+                    warn!("Failed to refresh playlist: {}", e);
+                    None
+                }
+            };
+            let target_duration = playlist
+                .as_ref()
+                .map_or(Duration::from_secs(2), |p| p.target_duration());
+
+            let mut fresh = false;
+            if let Some(playlist) = &playlist {
+                playlist.check_unprotected()?;
+
+                let (segments, skipped) = seen.fresh(playlist);
+                if let Some(skipped) = skipped {
+                    recording.gap(skipped);
+                }
+                self.downloader
+                    .fetch_keys(&client, &preferred, segments.iter().copied(), &mut keys)
+                    .await?;
+
+                for segment in segments {
+                    fresh = true;
+                    if segment.gap {
+                        recording.gap(segment.sequence..=segment.sequence);
+                        continue;
+                    }
+
+                    if part.as_ref().is_none_or(|p| self.is_full(p)) {
+                        if let Some(mut full) = part.take() {
+                            full.file.flush().await?;
+                        }
+                        let name = if self.rotates() {
+                            numbered(path, recording.files.len() + 1)
+                        } else {
+                            path.to_string()
+                        };
+                        info!("Recording to {}", name);
+                        part = Some(Part {
+                            file: OpenOptions::new()
+                                .write(true)
+                                .create(true)
+                                .truncate(true)
+                                .open(&name)
+                                .await?,
+                            bytes: 0,
+                            duration: Duration::default(),
+                            map: None,
+                        });
+                        recording.files.push(name);
+                    }
+                    let current = part.as_mut().unwrap();
+
+                    // Each file starts with, and so stands alone from, its
+                    // initialization section.
+                    let map = segment
+                        .map
+                        .as_ref()
+                        .filter(|&m| current.map.as_ref() != Some(m));
+                    let mut jobs = Vec::new();
+                    if let Some(map) = map {
+                        jobs.push(Job::map(map, &keys)?);
+                    }
+                    jobs.push(Job::segment(segment, &keys)?);
+
+                    // A segment that cannot be fetched even after retries
+                    // is left out, rather than ending the recording.
+                    let mut fetched = Vec::new();
+                    for job in jobs {
+                        match job
+                            .fetch(
+                                client.clone(),
+                                self.downloader.fetch_options(),
+                                preferred.clone(),
+                            )
+                            .await
+                        {
+                            Ok(data) => fetched.push(data),
+                            Err(e) => {
+                                warn!("Skipping segment {}: {}", segment.sequence, e);
+                                break;
+                            }
+                        }
+                    }
+                    if fetched.len() < 1 + map.is_some() as usize {
+                        recording.gap(segment.sequence..=segment.sequence);
+                        continue;
+                    }
+                    if let Some(map) = map {
+                        current.map = Some(map.clone());
+                    }
+
+                    for data in fetched {
+                        current.file.write_all(&data).await?;
+                        current.bytes += data.len() as u64;
+                        recording.bytes += data.len() as u64;
+                    }
+                    current.duration += segment.duration;
+                    recording.duration += segment.duration;
+                    recording.segments += 1;
+                }
+
+                if !playlist.is_live() {
+                    info!("Stream has ended");
+                    break;
+                }
+            }
+
+            if fresh {
+                updated = Instant::now();
+            } else if updated.elapsed()
+                >= self
+                    .stall_timeout
+                    .unwrap_or(target_duration * 6)
+                    .max(Duration::from_secs(1))
+            {
+                info!("Playlist stopped updating; taking the stream to have ended");
+                break;
+            }
+
+            // Playlists that have not changed are checked again sooner.
+            let wait = if fresh {
+                target_duration
+            } else {
+                target_duration / 2
+            };
+            tokio::time::sleep(wait.max(Duration::from_secs(1))).await;
+        }
+
+        if let Some(mut last) = part {
+            last.file.flush().await?;
+        }
+        Ok(recording)
+    }
+
+    /// Whether `part` is due to be rotated.
+    fn is_full(&self, part: &Part) -> bool {
+        self.rotate_every.is_some_and(|d| part.duration >= d)
+            || self.rotate_at.is_some_and(|b| part.bytes >= b)
+    }
+}
+
+/// `path` with `n` inserted before its extension, if any.
+fn numbered(path: &str, n: usize) -> String {
+    let p = Path::new(path);
+    match (p.file_stem(), p.extension()) {
+        (Some(stem), Some(ext)) => p
+            .with_file_name(format!(
+                "{}.{:03}.{}",
+                stem.to_string_lossy(),
+                n,
+                ext.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{}.{:03}", path, n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn playlist(first: u64, count: u64) -> MediaPlaylist {
+        let mut m3u8 = format!(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:2\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+            first
+        );
+        for sequence in first..first + count {
+            m3u8.push_str(&format!("#EXTINF:2,\n{}.ts\n", sequence));
+        }
+        MediaPlaylist::try_from(m3u8.as_str()).unwrap()
+    }
+
+    #[test]
+    fn follows_sequence() {
+        let mut seen = Seen::default();
+        let first = playlist(10, 3);
+        let (segments, skipped) = seen.fresh(&first);
+        assert_eq!(segments.len(), 3);
+        assert!(skipped.is_none());
+
+        // Segments 11 and 12 were already emitted.
+        let next = playlist(11, 3);
+        let (segments, skipped) = seen.fresh(&next);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].sequence(), 13);
+        assert!(skipped.is_none());
+
+        // Segments 14 and 15 dropped off before being seen.
+        let late = playlist(16, 2);
+        let (segments, skipped) = seen.fresh(&late);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(skipped, Some(14..=15));
+
+        let mut recording = Recording::default();
+        recording.gap(14..=15);
+        recording.gap(16..=16);
+        recording.gap(20..=20);
+        assert_eq!(recording.gaps(), vec![14..=16, 20..=20]);
+    }
+
+    #[test]
+    fn numbers_parts() {
+        assert_eq!(numbered("stream.ts", 1), "stream.001.ts");
+        assert_eq!(numbered("out/live.mp4", 12), "out/live.012.mp4");
+        assert_eq!(numbered("stream", 2), "stream.002");
+    }
+}
@@ -0,0 +1,1352 @@
+//! A typed reading of HTTP Live Streaming playlists, as specified by
+//! [RFC 8216](https://datatracker.ietf.org/doc/html/rfc8216).
+//!
+//! A [Playlist] is either a [MasterPlaylist], offering a stream as several
+//! [Variants](Variant) along with alternative [Renditions](Rendition) of its
+//! audio, video and subtitles, or a [MediaPlaylist], listing the
+//! [Segments](Segment) of one of them.
+
+use chrono::{DateTime, Utc};
+use std::{
+    convert::TryFrom,
+    error,
+    fmt::{self, Display},
+    str,
+    time::Duration,
+};
+use url::Url;
+
+use crate::{drm, serde::date_time, ByteRange};
+
+#[cfg(feature = "client")]
+mod download;
+#[cfg(feature = "client")]
+mod live;
+
+#[cfg(feature = "client")]
+pub use crate::dash::Progress;
+#[cfg(feature = "client")]
+pub use download::Downloader;
+#[cfg(feature = "client")]
+pub use live::{Recorder, Recording};
+
+#[derive(Debug, Clone)]
+/// Entry point; either kind of HLS playlist.
+pub enum Playlist {
+    Master(MasterPlaylist),
+    Media(MediaPlaylist),
+}
+
+impl Playlist {
+    #[cfg(feature = "client")]
+    /// Acquires a [Playlist] from the provided URL source.
+    pub async fn from_url<T: ToString>(
+        url: &T,
+    ) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        let https = hyper_tls::HttpsConnector::new();
+        let client = hyper::Client::builder().build::<_, hyper::Body>(https);
+
+        let mut res = client.get(url.to_string().parse()?).await?;
+        if !res.status().is_success() {
+            return Err(format!("Playlist request failed with {}", res.status()).into());
+        }
+        let body = String::from_utf8(hyper::body::to_bytes(res.body_mut()).await?.to_vec())?;
+
+        let mut playlist = Self::try_from(body.as_str())?;
+        playlist.set_location(&url.to_string())?;
+        Ok(playlist)
+    }
+
+    /// Sets the URL the [Playlist] was fetched from, resolving every
+    /// relative URI within against it. [Playlist::from_url] sets it
+    /// automatically.
+    pub fn set_location(&mut self, location: &str) -> Result<(), Error> {
+        match self {
+            Playlist::Master(master) => master.set_location(location),
+            Playlist::Media(media) => media.set_location(location),
+        }
+    }
+}
+
+impl TryFrom<&str> for Playlist {
+    type Error = Error;
+
+    /// Attempt to parse an M3U8 [&str] into a [Playlist], telling the
+    /// kinds apart by their tags.
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let lines = lines(s)?;
+        let is_master = lines.iter().any(|line| match line {
+            Line::Tag(name, _) => matches!(
+                *name,
+                "EXT-X-STREAM-INF" | "EXT-X-I-FRAME-STREAM-INF" | "EXT-X-MEDIA"
+            ),
+            Line::Uri(_) => false,
+        });
+        if is_master {
+            Ok(Playlist::Master(MasterPlaylist::parse(&lines)?))
+        } else {
+            Ok(Playlist::Media(MediaPlaylist::parse(&lines)?))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Errors encountered while reading a [Playlist].
+pub enum Error {
+    /// The playlist does not follow the M3U8 format.
+    Malformed(&'static str),
+
+    /// The playlist uses a feature maguro does not handle.
+    Unsupported(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed(reason) => write!(f, "Malformed playlist: {}", reason),
+            Error::Unsupported(what) => write!(f, "Unsupported playlist: {}", what),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[derive(Debug, Clone, Default)]
+/// A playlist offering the same content at several qualities.
+pub struct MasterPlaylist {
+    version: Option<u32>,
+    independent_segments: bool,
+    variants: Vec<Variant>,
+    i_frame_variants: Vec<Variant>,
+    renditions: Vec<Rendition>,
+}
+
+impl TryFrom<&str> for MasterPlaylist {
+    type Error = Error;
+
+    /// Attempt to parse an M3U8 [&str] into a [MasterPlaylist].
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match Playlist::try_from(s)? {
+            Playlist::Master(master) => Ok(master),
+            Playlist::Media(_) => Err(Error::Malformed("expected a master playlist")),
+        }
+    }
+}
+
+impl MasterPlaylist {
+    fn parse(lines: &[Line]) -> Result<Self, Error> {
+        let mut playlist = Self::default();
+        let mut pending: Option<Variant> = None;
+        for line in lines {
+            match *line {
+                Line::Tag("EXT-X-VERSION", value) => playlist.version = Some(number(value)?),
+                Line::Tag("EXT-X-INDEPENDENT-SEGMENTS", _) => playlist.independent_segments = true,
+                Line::Tag("EXT-X-STREAM-INF", value) => {
+                    pending = Some(Variant::parse(&Attributes::parse(value)?)?)
+                }
+                Line::Tag("EXT-X-I-FRAME-STREAM-INF", value) => {
+                    let attributes = Attributes::parse(value)?;
+                    let mut variant = Variant::parse(&attributes)?;
+                    variant.uri = attributes
+                        .get("URI")
+                        .ok_or(Error::Malformed("EXT-X-I-FRAME-STREAM-INF without a URI"))?
+                        .to_string();
+                    playlist.i_frame_variants.push(variant);
+                }
+                Line::Tag("EXT-X-MEDIA", value) => playlist
+                    .renditions
+                    .push(Rendition::parse(&Attributes::parse(value)?)?),
+                Line::Tag(..) => {}
+                Line::Uri(uri) => {
+                    let mut variant = pending
+                        .take()
+                        .ok_or(Error::Malformed("URI without an EXT-X-STREAM-INF"))?;
+                    variant.uri = uri.to_string();
+                    playlist.variants.push(variant);
+                }
+            }
+        }
+        Ok(playlist)
+    }
+
+    /// Resolves every relative URI in the [MasterPlaylist] against the URL
+    /// it was fetched from.
+    pub fn set_location(&mut self, location: &str) -> Result<(), Error> {
+        let base = parse_location(location)?;
+        for variant in self
+            .variants
+            .iter_mut()
+            .chain(self.i_frame_variants.iter_mut())
+        {
+            variant.uri = join(&base, &variant.uri);
+        }
+        for rendition in self.renditions.iter_mut() {
+            rendition.uri = rendition.uri.as_deref().map(|uri| join(&base, uri));
+        }
+        Ok(())
+    }
+
+    /// Compatibility version of the playlist, if given.
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    /// Whether every segment of every variant can be decoded on its own.
+    pub fn independent_segments(&self) -> bool {
+        self.independent_segments
+    }
+
+    /// Iterator over the [Variants](Variant) of the stream.
+    pub fn variants(&self) -> impl Iterator<Item = &Variant> {
+        self.variants.iter()
+    }
+
+    /// Iterator over the [Variants](Variant) holding only keyframes, for
+    /// trick play.
+    pub fn i_frame_variants(&self) -> impl Iterator<Item = &Variant> {
+        self.i_frame_variants.iter()
+    }
+
+    /// Iterator over every alternative [Rendition] of the stream.
+    pub fn renditions(&self) -> impl Iterator<Item = &Rendition> {
+        self.renditions.iter()
+    }
+
+    /// Iterator over the [Renditions](Rendition) in the group with the given
+    /// ID, as referred to by [Variant::audio] and its siblings.
+    pub fn group<'a>(&'a self, group_id: &'a str) -> impl Iterator<Item = &'a Rendition> {
+        self.renditions
+            .iter()
+            .filter(move |r| r.group_id == group_id)
+    }
+
+    /// The [Variant] with the highest bandwidth.
+    pub fn highest_bandwidth(&self) -> Option<&Variant> {
+        self.variants.iter().max_by_key(|v| v.bandwidth)
+    }
+
+    /// The [Variant] with the lowest bandwidth.
+    pub fn lowest_bandwidth(&self) -> Option<&Variant> {
+        self.variants.iter().min_by_key(|v| v.bandwidth)
+    }
+
+    /// The [Variant] whose resolution is closest to `width` by `height`
+    /// pixels, preferring the higher bandwidth between equals.
+    pub fn closest_resolution(&self, width: u32, height: u32) -> Option<&Variant> {
+        self.variants
+            .iter()
+            .filter_map(|v| v.resolution.map(|r| (v, r)))
+            .min_by_key(|(v, (w, h))| {
+                let dw = i64::from(*w) - i64::from(width);
+                let dh = i64::from(*h) - i64::from(height);
+                (dw * dw + dh * dh, std::cmp::Reverse(v.bandwidth))
+            })
+            .map(|(v, _)| v)
+    }
+
+    /// Iterator over the [Variants](Variant) using a codec, matched by prefix
+    /// so that `avc1` matches `avc1.4d401f`.
+    pub fn by_codec<'a>(&'a self, codec: &'a str) -> impl Iterator<Item = &'a Variant> {
+        self.variants.iter().filter(move |v| {
+            v.codecs
+                .as_deref()
+                .is_some_and(|c| c.split(',').any(|c| c.trim().starts_with(codec)))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// One quality of a stream, from `EXT-X-STREAM-INF` or
+/// `EXT-X-I-FRAME-STREAM-INF`.
+pub struct Variant {
+    uri: String,
+    bandwidth: u64,
+    average_bandwidth: Option<u64>,
+    codecs: Option<String>,
+    resolution: Option<(u32, u32)>,
+    frame_rate: Option<f64>,
+    audio: Option<String>,
+    video: Option<String>,
+    subtitles: Option<String>,
+    closed_captions: Option<String>,
+}
+
+impl Variant {
+    fn parse(attributes: &Attributes) -> Result<Self, Error> {
+        let resolution = match attributes.get("RESOLUTION") {
+            Some(r) => {
+                let (w, h) = r
+                    .split_once(['x', 'X'])
+                    .ok_or(Error::Malformed("invalid RESOLUTION"))?;
+                Some((number(w)?, number(h)?))
+            }
+            None => None,
+        };
+        Ok(Self {
+            uri: String::new(),
+            bandwidth: number(
+                attributes
+                    .get("BANDWIDTH")
+                    .ok_or(Error::Malformed("variant without a BANDWIDTH"))?,
+            )?,
+            average_bandwidth: attributes
+                .get("AVERAGE-BANDWIDTH")
+                .map(number)
+                .transpose()?,
+            codecs: attributes.string("CODECS"),
+            resolution,
+            frame_rate: attributes.get("FRAME-RATE").map(number).transpose()?,
+            audio: attributes.string("AUDIO"),
+            video: attributes.string("VIDEO"),
+            subtitles: attributes.string("SUBTITLES"),
+            // `NONE` means the variant has no closed captions at all.
+            closed_captions: attributes.string("CLOSED-CAPTIONS").filter(|c| c != "NONE"),
+        })
+    }
+
+    /// URI of the [MediaPlaylist] of the [Variant].
+    pub fn uri(&self) -> String {
+        self.uri.clone()
+    }
+
+    /// Peak bits per second of the [Variant].
+    pub fn bandwidth(&self) -> u64 {
+        self.bandwidth
+    }
+
+    /// Average bits per second of the [Variant], if given.
+    pub fn average_bandwidth(&self) -> Option<u64> {
+        self.average_bandwidth
+    }
+
+    /// Codecs of every stream in the [Variant], comma-separated.
+    pub fn codecs(&self) -> Option<String> {
+        self.codecs.clone()
+    }
+
+    /// Width and height of the video in pixels, if any.
+    pub fn resolution(&self) -> Option<(u32, u32)> {
+        self.resolution
+    }
+
+    /// Frames per second of the video, if given.
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.frame_rate
+    }
+
+    /// Group ID of the audio [Renditions](Rendition) to play alongside.
+    pub fn audio(&self) -> Option<String> {
+        self.audio.clone()
+    }
+
+    /// Group ID of the video [Renditions](Rendition) to play instead.
+    pub fn video(&self) -> Option<String> {
+        self.video.clone()
+    }
+
+    /// Group ID of the subtitle [Renditions](Rendition) to offer.
+    pub fn subtitles(&self) -> Option<String> {
+        self.subtitles.clone()
+    }
+
+    /// Group ID of the closed-caption [Renditions](Rendition) to offer.
+    pub fn closed_captions(&self) -> Option<String> {
+        self.closed_captions.clone()
+    }
+}
+
+impl Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let resolution = match self.resolution {
+            Some((w, h)) => format!("{}x{}", w, h),
+            None => String::new(),
+        };
+        write!(
+            f,
+            "Bandwidth: {:>9} | Resolution: {:<9} | Codecs: {}",
+            self.bandwidth,
+            resolution,
+            self.codecs.as_deref().unwrap_or("unknown")
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Kind of media carried by a [Rendition].
+pub enum MediaType {
+    Audio,
+    Video,
+    Subtitles,
+    ClosedCaptions,
+}
+
+#[derive(Debug, Clone)]
+/// An alternative rendition of a stream, from `EXT-X-MEDIA`, such as a dub
+/// or a subtitle track.
+pub struct Rendition {
+    media_type: MediaType,
+    uri: Option<String>,
+    group_id: String,
+    language: Option<String>,
+    name: String,
+    default: bool,
+    autoselect: bool,
+    forced: bool,
+    instream_id: Option<String>,
+    characteristics: Option<String>,
+    channels: Option<String>,
+}
+
+impl Rendition {
+    fn parse(attributes: &Attributes) -> Result<Self, Error> {
+        let media_type = match attributes.get("TYPE") {
+            Some("AUDIO") => MediaType::Audio,
+            Some("VIDEO") => MediaType::Video,
+            Some("SUBTITLES") => MediaType::Subtitles,
+            Some("CLOSED-CAPTIONS") => MediaType::ClosedCaptions,
+            Some(other) => return Err(Error::Unsupported(format!("media type {}", other))),
+            None => return Err(Error::Malformed("EXT-X-MEDIA without a TYPE")),
+        };
+        Ok(Self {
+            media_type,
+            uri: attributes.string("URI"),
+            group_id: attributes
+                .string("GROUP-ID")
+                .ok_or(Error::Malformed("EXT-X-MEDIA without a GROUP-ID"))?,
+            language: attributes.string("LANGUAGE"),
+            name: attributes
+                .string("NAME")
+                .ok_or(Error::Malformed("EXT-X-MEDIA without a NAME"))?,
+            default: attributes.get("DEFAULT") == Some("YES"),
+            autoselect: attributes.get("AUTOSELECT") == Some("YES"),
+            forced: attributes.get("FORCED") == Some("YES"),
+            instream_id: attributes.string("INSTREAM-ID"),
+            characteristics: attributes.string("CHARACTERISTICS"),
+            channels: attributes.string("CHANNELS"),
+        })
+    }
+
+    /// Kind of media the [Rendition] carries.
+    pub fn media_type(&self) -> MediaType {
+        self.media_type
+    }
+
+    /// URI of the [MediaPlaylist] of the [Rendition]. Renditions without
+    /// one are carried within the [Variant] itself.
+    pub fn uri(&self) -> Option<String> {
+        self.uri.clone()
+    }
+
+    /// ID of the group of alternatives the [Rendition] belongs to.
+    pub fn group_id(&self) -> String {
+        self.group_id.clone()
+    }
+
+    /// Language of the [Rendition], such as `en` or `pt-BR`.
+    pub fn language(&self) -> Option<String> {
+        self.language.clone()
+    }
+
+    /// Human-readable name of the [Rendition].
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Whether to play the [Rendition] when the user has no preference.
+    pub fn is_default(&self) -> bool {
+        self.default
+    }
+
+    /// Whether the [Rendition] may be chosen automatically, such as by the
+    /// system language.
+    pub fn autoselect(&self) -> bool {
+        self.autoselect
+    }
+
+    /// Whether the subtitles must be shown regardless of preference.
+    pub fn forced(&self) -> bool {
+        self.forced
+    }
+
+    /// Channel of the closed captions within the video, such as `CC1`.
+    pub fn instream_id(&self) -> Option<String> {
+        self.instream_id.clone()
+    }
+
+    /// Uniform Type Identifiers describing the [Rendition], such as
+    /// `public.accessibility.describes-video`.
+    pub fn characteristics(&self) -> Option<String> {
+        self.characteristics.clone()
+    }
+
+    /// Audio channel count and layout, such as `6` or `16/JOC`.
+    pub fn channels(&self) -> Option<String> {
+        self.channels.clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Whether, and how, a [MediaPlaylist] may change.
+pub enum PlaylistType {
+    /// Segments may only be appended.
+    Event,
+
+    /// The playlist will never change.
+    Vod,
+}
+
+#[derive(Debug, Clone, Default)]
+/// A playlist listing the segments of one [Variant] or [Rendition].
+pub struct MediaPlaylist {
+    version: Option<u32>,
+    target_duration: Duration,
+    media_sequence: u64,
+    discontinuity_sequence: u64,
+    playlist_type: Option<PlaylistType>,
+    end_list: bool,
+    i_frames_only: bool,
+    independent_segments: bool,
+    segments: Vec<Segment>,
+}
+
+impl TryFrom<&str> for MediaPlaylist {
+    type Error = Error;
+
+    /// Attempt to parse an M3U8 [&str] into a [MediaPlaylist].
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match Playlist::try_from(s)? {
+            Playlist::Media(media) => Ok(media),
+            Playlist::Master(_) => Err(Error::Malformed("expected a media playlist")),
+        }
+    }
+}
+
+impl MediaPlaylist {
+    #[cfg(feature = "client")]
+    /// Acquires a [MediaPlaylist] from the provided URL source, such as
+    /// [Variant::uri].
+    pub async fn from_url<T: ToString>(
+        url: &T,
+    ) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        match Playlist::from_url(url).await? {
+            Playlist::Media(media) => Ok(media),
+            Playlist::Master(_) => Err(Error::Malformed("expected a media playlist").into()),
+        }
+    }
+
+    fn parse(lines: &[Line]) -> Result<Self, Error> {
+        let mut playlist = Self::default();
+        let mut target_duration = None;
+
+        // State applying to the next segment.
+        let mut duration = None;
+        let mut title = None;
+        let mut range: Option<(u64, Option<u64>)> = None;
+        let mut discontinuity = false;
+        let mut gap = false;
+        let mut date_time: Option<DateTime<Utc>> = None;
+
+        // State applying to every segment until changed.
+        let mut keys: Vec<Key> = Vec::new();
+        let mut keys_replaced = false;
+        let mut map: Option<Map> = None;
+        let mut discontinuities = 0;
+        // End of the last byte range, for ranges without an offset.
+        let mut last_range: Option<(&str, u64)> = None;
+
+        for line in lines {
+            match *line {
+                Line::Tag("EXT-X-VERSION", value) => playlist.version = Some(number(value)?),
+                Line::Tag("EXT-X-TARGETDURATION", value) => {
+                    target_duration = Some(Duration::from_secs(number(value)?))
+                }
+                Line::Tag("EXT-X-MEDIA-SEQUENCE", value) => {
+                    playlist.media_sequence = number(value)?
+                }
+                Line::Tag("EXT-X-DISCONTINUITY-SEQUENCE", value) => {
+                    playlist.discontinuity_sequence = number(value)?
+                }
+                Line::Tag("EXT-X-PLAYLIST-TYPE", value) => {
+                    playlist.playlist_type = Some(match value.trim() {
+                        "EVENT" => PlaylistType::Event,
+                        "VOD" => PlaylistType::Vod,
+                        _ => return Err(Error::Malformed("invalid EXT-X-PLAYLIST-TYPE")),
+                    })
+                }
+                Line::Tag("EXT-X-ENDLIST", _) => playlist.end_list = true,
+                Line::Tag("EXT-X-I-FRAMES-ONLY", _) => playlist.i_frames_only = true,
+                Line::Tag("EXT-X-INDEPENDENT-SEGMENTS", _) => playlist.independent_segments = true,
+                Line::Tag("EXTINF", value) => {
+                    let (secs, rest) = value.split_once(',').unwrap_or((value, ""));
+                    let secs: f64 = number(secs)?;
+                    duration = Some(
+                        Duration::try_from_secs_f64(secs)
+                            .map_err(|_| Error::Malformed("invalid EXTINF duration"))?,
+                    );
+                    title = Some(rest.trim().to_string()).filter(|t| !t.is_empty());
+                }
+                Line::Tag("EXT-X-BYTERANGE", value) => range = Some(sub_range(value)?),
+                Line::Tag("EXT-X-DISCONTINUITY", _) => discontinuity = true,
+                Line::Tag("EXT-X-GAP", _) => gap = true,
+                Line::Tag("EXT-X-PROGRAM-DATE-TIME", value) => {
+                    date_time = Some(
+                        date_time::parse(value)
+                            .ok_or(Error::Malformed("invalid EXT-X-PROGRAM-DATE-TIME"))?,
+                    )
+                }
+                Line::Tag("EXT-X-KEY", value) => {
+                    // Consecutive keys are alternatives for different key
+                    // systems; any other tag in between replaces them.
+                    if !keys_replaced {
+                        keys.clear();
+                        keys_replaced = true;
+                    }
+                    let key = Key::parse(&Attributes::parse(value)?)?;
+                    if key.method != KeyMethod::None {
+                        keys.push(key);
+                    }
+                    continue;
+                }
+                Line::Tag("EXT-X-MAP", value) => {
+                    let attributes = Attributes::parse(value)?;
+                    map = Some(Map {
+                        uri: attributes
+                            .string("URI")
+                            .ok_or(Error::Malformed("EXT-X-MAP without a URI"))?,
+                        range: attributes
+                            .get("BYTERANGE")
+                            .map(|r| {
+                                sub_range(r)
+                                    .and_then(|(len, offset)| to_range(len, offset.unwrap_or(0)))
+                            })
+                            .transpose()?,
+                        keys: keys.clone(),
+                    });
+                }
+                Line::Tag(..) => {}
+                Line::Uri(uri) => {
+                    let duration = duration
+                        .take()
+                        .ok_or(Error::Malformed("segment without an EXTINF"))?;
+                    let range = match range.take() {
+                        Some((len, offset)) => {
+                            let start = match (offset, last_range) {
+                                (Some(offset), _) => offset,
+                                (None, Some((last, end))) if last == uri => end,
+                                (None, _) => {
+                                    return Err(Error::Malformed(
+                                        "EXT-X-BYTERANGE without an offset to follow on from",
+                                    ))
+                                }
+                            };
+                            let range = to_range(len, start)?;
+                            last_range = Some((uri, range.end.saturating_add(1)));
+                            Some(range)
+                        }
+                        None => None,
+                    };
+                    if discontinuity {
+                        discontinuities += 1;
+                    }
+
+                    let previous = playlist.segments.last();
+                    let program_date_time = date_time.take().or_else(|| {
+                        previous.and_then(|p| {
+                            p.program_date_time.map(|t| {
+                                t + chrono::Duration::from_std(p.duration)
+                                    .unwrap_or_else(|_| chrono::Duration::zero())
+                            })
+                        })
+                    });
+                    playlist.segments.push(Segment {
+                        uri: uri.to_string(),
+                        duration,
+                        title: title.take(),
+                        range,
+                        sequence: playlist.media_sequence + playlist.segments.len() as u64,
+                        discontinuity,
+                        discontinuity_sequence: playlist.discontinuity_sequence + discontinuities,
+                        gap,
+                        program_date_time,
+                        keys: keys.clone(),
+                        map: map.clone(),
+                    });
+                    discontinuity = false;
+                    gap = false;
+                }
+            }
+            keys_replaced = false;
+        }
+
+        playlist.target_duration =
+            target_duration.ok_or(Error::Malformed("missing EXT-X-TARGETDURATION"))?;
+        Ok(playlist)
+    }
+
+    /// Resolves every relative URI in the [MediaPlaylist] against the URL
+    /// it was fetched from.
+    pub fn set_location(&mut self, location: &str) -> Result<(), Error> {
+        let base = parse_location(location)?;
+        for segment in self.segments.iter_mut() {
+            segment.uri = join(&base, &segment.uri);
+            if let Some(map) = segment.map.as_mut() {
+                map.uri = join(&base, &map.uri);
+                for key in map.keys.iter_mut() {
+                    key.uri = key.uri.as_deref().map(|uri| join(&base, uri));
+                }
+            }
+            for key in segment.keys.iter_mut() {
+                key.uri = key.uri.as_deref().map(|uri| join(&base, uri));
+            }
+        }
+        Ok(())
+    }
+
+    /// Compatibility version of the playlist, if given.
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    /// Upper bound on the duration of every segment. Live playlists should
+    /// be reloaded about this often.
+    pub fn target_duration(&self) -> Duration {
+        self.target_duration
+    }
+
+    /// Sequence number of the first segment listed.
+    pub fn media_sequence(&self) -> u64 {
+        self.media_sequence
+    }
+
+    /// Discontinuity sequence number of the first segment listed.
+    pub fn discontinuity_sequence(&self) -> u64 {
+        self.discontinuity_sequence
+    }
+
+    /// Whether, and how, the playlist may change, if declared.
+    pub fn playlist_type(&self) -> Option<PlaylistType> {
+        self.playlist_type
+    }
+
+    /// Whether segments may still be added to the playlist, as they are
+    /// to that of a live stream.
+    pub fn is_live(&self) -> bool {
+        !self.end_list && self.playlist_type != Some(PlaylistType::Vod)
+    }
+
+    /// Whether every segment holds a single keyframe, for trick play.
+    pub fn i_frames_only(&self) -> bool {
+        self.i_frames_only
+    }
+
+    /// Whether every segment can be decoded on its own.
+    pub fn independent_segments(&self) -> bool {
+        self.independent_segments
+    }
+
+    /// Iterator over the [Segments](Segment) listed, in order.
+    pub fn segments(&self) -> impl Iterator<Item = &Segment> {
+        self.segments.iter()
+    }
+
+    /// Total duration of the [Segments](Segment) listed.
+    pub fn duration(&self) -> Duration {
+        self.segments.iter().map(|s| s.duration).sum()
+    }
+
+    /// Whether any [Segment] is encrypted with a key maguro cannot use.
+    pub fn is_protected(&self) -> bool {
+        self.segments.iter().any(Segment::is_protected)
+    }
+
+    /// The DRM systems the protected [Segments](Segment) may be decrypted
+    /// with.
+    pub fn drm_systems(&self) -> Vec<drm::System> {
+        let mut systems = Vec::new();
+        for system in self
+            .segments
+            .iter()
+            .filter(|s| s.is_protected())
+            .flat_map(|s| s.keys.iter())
+            .filter_map(|k| drm::System::from_key_format(&k.key_format()))
+        {
+            if !systems.contains(&system) {
+                systems.push(system);
+            }
+        }
+        systems
+    }
+
+    #[cfg(feature = "client")]
+    /// Fails with [drm::Protected] if any [Segment] is encrypted with a
+    /// key maguro cannot use.
+    pub(crate) fn check_unprotected(&self) -> Result<(), drm::Protected> {
+        if self.is_protected() {
+            return Err(drm::Protected::new(self.drm_systems()));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "client")]
+    /// Downloads every [Segment] of the [MediaPlaylist], decrypting them as
+    /// needed, into a `File`. See [Downloader] for control over
+    /// concurrency, retries and resuming.
+    pub async fn download(
+        &self,
+        dest: &mut tokio::fs::File,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        Downloader::new().download(self, dest).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A segment of a [MediaPlaylist].
+pub struct Segment {
+    uri: String,
+    duration: Duration,
+    title: Option<String>,
+    range: Option<ByteRange>,
+    sequence: u64,
+    discontinuity: bool,
+    discontinuity_sequence: u64,
+    gap: bool,
+    program_date_time: Option<DateTime<Utc>>,
+    keys: Vec<Key>,
+    map: Option<Map>,
+}
+
+impl Segment {
+    /// URI the [Segment] is fetched from.
+    pub fn uri(&self) -> String {
+        self.uri.clone()
+    }
+
+    /// Duration of the [Segment].
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Human-readable title of the [Segment], if given.
+    pub fn title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    /// Bytes of the resource holding the [Segment], if not all of it.
+    pub fn range(&self) -> Option<ByteRange> {
+        self.range.clone()
+    }
+
+    /// Media sequence number of the [Segment].
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Whether the [Segment] follows a discontinuity, such as a change of
+    /// encoder or the start of an advertisement.
+    pub fn discontinuity(&self) -> bool {
+        self.discontinuity
+    }
+
+    /// Number of discontinuities in the stream before the [Segment].
+    pub fn discontinuity_sequence(&self) -> u64 {
+        self.discontinuity_sequence
+    }
+
+    /// Whether the [Segment] is missing from the stream, and should not be
+    /// fetched.
+    pub fn gap(&self) -> bool {
+        self.gap
+    }
+
+    /// Wall-clock time of the first sample of the [Segment], if the
+    /// playlist gives one for it or an earlier segment.
+    pub fn program_date_time(&self) -> Option<DateTime<Utc>> {
+        self.program_date_time
+    }
+
+    /// The [Keys](Key) the [Segment] is encrypted with, one for each key
+    /// system offered, or none if it is in the clear.
+    pub fn keys(&self) -> Vec<Key> {
+        self.keys.clone()
+    }
+
+    /// The [Map] holding the initialization section for the [Segment], if
+    /// it needs one.
+    pub fn map(&self) -> Option<Map> {
+        self.map.clone()
+    }
+
+    /// The [Key] maguro can decrypt the [Segment] with, if it is encrypted
+    /// with AES-128 at all.
+    pub fn aes128_key(&self) -> Option<Key> {
+        self.keys.iter().find(|k| k.is_aes128()).cloned()
+    }
+
+    /// Whether the [Segment] is encrypted, but not with a plain AES-128
+    /// key maguro can use; such as with SAMPLE-AES, or by a DRM system.
+    pub fn is_protected(&self) -> bool {
+        !self.keys.is_empty() && self.aes128_key().is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The initialization section of the [Segments](Segment) following an
+/// `EXT-X-MAP`, such as the `moov` of fragmented MP4.
+pub struct Map {
+    uri: String,
+    range: Option<ByteRange>,
+
+    // Keys in effect where the map is declared.
+    keys: Vec<Key>,
+}
+
+impl Map {
+    /// URI the initialization section is fetched from.
+    pub fn uri(&self) -> String {
+        self.uri.clone()
+    }
+
+    /// Bytes of the resource holding the initialization section, if not
+    /// all of it.
+    pub fn range(&self) -> Option<ByteRange> {
+        self.range.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// How [Segments](Segment) are encrypted.
+pub enum KeyMethod {
+    None,
+
+    /// Whole segments are encrypted with AES-128 in CBC mode.
+    Aes128,
+
+    /// Individual samples are encrypted, as by a DRM system.
+    SampleAes,
+
+    /// Individual samples are encrypted with AES in CTR mode.
+    SampleAesCtr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A key [Segments](Segment) are encrypted with, from `EXT-X-KEY`.
+pub struct Key {
+    method: KeyMethod,
+    uri: Option<String>,
+    iv: Option<[u8; 16]>,
+    key_format: Option<String>,
+    key_format_versions: Option<String>,
+}
+
+impl Key {
+    fn parse(attributes: &Attributes) -> Result<Self, Error> {
+        let method = match attributes.get("METHOD") {
+            Some("NONE") => KeyMethod::None,
+            Some("AES-128") => KeyMethod::Aes128,
+            Some("SAMPLE-AES") => KeyMethod::SampleAes,
+            Some("SAMPLE-AES-CTR") => KeyMethod::SampleAesCtr,
+            Some(other) => return Err(Error::Unsupported(format!("key method {}", other))),
+            None => return Err(Error::Malformed("EXT-X-KEY without a METHOD")),
+        };
+        let iv = match attributes.get("IV") {
+            Some(iv) => {
+                let hex = iv
+                    .strip_prefix("0x")
+                    .or_else(|| iv.strip_prefix("0X"))
+                    .filter(|h| h.len() == 32 && h.bytes().all(|b| b.is_ascii_hexdigit()))
+                    .ok_or(Error::Malformed("invalid IV"))?;
+                let mut bytes = [0; 16];
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                        .map_err(|_| Error::Malformed("invalid IV"))?;
+                }
+                Some(bytes)
+            }
+            None => None,
+        };
+        if method != KeyMethod::None && !attributes.has("URI") {
+            return Err(Error::Malformed("EXT-X-KEY without a URI"));
+        }
+        Ok(Self {
+            method,
+            uri: attributes.string("URI"),
+            iv,
+            key_format: attributes.string("KEYFORMAT"),
+            key_format_versions: attributes.string("KEYFORMATVERSIONS"),
+        })
+    }
+
+    /// How the [Segments](Segment) are encrypted.
+    pub fn method(&self) -> KeyMethod {
+        self.method.clone()
+    }
+
+    /// URI the key is fetched from.
+    pub fn uri(&self) -> Option<String> {
+        self.uri.clone()
+    }
+
+    /// Initialization vector, if given. Otherwise, that of each [Segment]
+    /// is its media sequence number.
+    pub fn iv(&self) -> Option<[u8; 16]> {
+        self.iv
+    }
+
+    /// How the key is represented, such as `identity` for a plain AES key
+    /// or the URN of a DRM system.
+    pub fn key_format(&self) -> String {
+        self.key_format
+            .clone()
+            .unwrap_or_else(|| "identity".to_string())
+    }
+
+    /// Versions of the key format the key complies with, if given.
+    pub fn key_format_versions(&self) -> Option<String> {
+        self.key_format_versions.clone()
+    }
+
+    /// Whether the key is a plain AES-128 key, fetched as is from its URI.
+    pub fn is_aes128(&self) -> bool {
+        self.method == KeyMethod::Aes128 && self.key_format() == "identity"
+    }
+}
+
+/// A meaningful line of a playlist.
+enum Line<'a> {
+    /// An `#EXT` tag with its name and the value after any colon.
+    Tag(&'a str, &'a str),
+    Uri(&'a str),
+}
+
+/// Split a playlist into its tags and URIs, dropping comments and blank
+/// lines.
+fn lines(s: &str) -> Result<Vec<Line<'_>>, Error> {
+    let mut lines = s
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty());
+    if lines.next() != Some("#EXTM3U") {
+        return Err(Error::Malformed("missing #EXTM3U"));
+    }
+    Ok(lines
+        .filter_map(|line| match line.strip_prefix('#') {
+            Some(tag) if tag.starts_with("EXT") => Some(match tag.split_once(':') {
+                Some((name, value)) => Line::Tag(name, value),
+                None => Line::Tag(tag, ""),
+            }),
+            Some(_) => None,
+            None => Some(Line::Uri(line)),
+        })
+        .collect())
+}
+
+/// The attribute list of a tag, such as `BANDWIDTH=1280000,CODECS="a,b"`.
+struct Attributes<'a>(Vec<(&'a str, &'a str)>);
+
+impl<'a> Attributes<'a> {
+    fn parse(s: &'a str) -> Result<Self, Error> {
+        let mut out = Vec::new();
+        let mut rest = s.trim();
+        while !rest.is_empty() {
+            let (name, after) = rest
+                .split_once('=')
+                .ok_or(Error::Malformed("attribute without a value"))?;
+            let (value, after) = match after.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = quoted
+                        .find('"')
+                        .ok_or(Error::Malformed("unterminated quoted string"))?;
+                    (&quoted[..end], &quoted[end + 1..])
+                }
+                None => after.split_at(after.find(',').unwrap_or(after.len())),
+            };
+            out.push((name.trim(), value));
+            rest = after.trim_start().trim_start_matches(',').trim_start();
+        }
+        Ok(Self(out))
+    }
+
+    fn get(&self, name: &str) -> Option<&'a str> {
+        self.0.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    fn string(&self, name: &str) -> Option<String> {
+        self.get(name).map(str::to_string)
+    }
+}
+
+/// Parse a decimal number from a tag or attribute value.
+fn number<T: str::FromStr>(s: &str) -> Result<T, Error> {
+    s.trim()
+        .parse()
+        .map_err(|_| Error::Malformed("invalid number"))
+}
+
+/// Parse a sub-range of the form `length[@offset]`.
+fn sub_range(s: &str) -> Result<(u64, Option<u64>), Error> {
+    let (len, offset) = match s.split_once('@') {
+        Some((len, offset)) => (len, Some(number(offset)?)),
+        None => (s, None),
+    };
+    let len = number(len)?;
+    if len == 0 {
+        return Err(Error::Malformed("empty byte range"));
+    }
+    Ok((len, offset))
+}
+
+/// The inclusive [ByteRange] of `len` bytes from `start`.
+fn to_range(len: u64, start: u64) -> Result<ByteRange, Error> {
+    let end = len
+        .checked_sub(1)
+        .and_then(|last| start.checked_add(last))
+        .ok_or(Error::Malformed("invalid byte range"))?;
+    Ok(ByteRange { start, end })
+}
+
+fn parse_location(location: &str) -> Result<Url, Error> {
+    Url::parse(location).map_err(|_| Error::Malformed("invalid playlist URL"))
+}
+
+/// Resolve `uri` against `base`, leaving it be if it cannot be.
+fn join(base: &Url, uri: &str) -> String {
+    base.join(uri)
+        .map(String::from)
+        .unwrap_or_else(|_| uri.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn master_playlist() {
+        let playlist = Playlist::try_from(
+            r#"#EXTM3U
+#EXT-X-VERSION:6
+#EXT-X-INDEPENDENT-SEGMENTS
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aac",LANGUAGE="en",NAME="English",DEFAULT=YES,AUTOSELECT=YES,URI="audio/en.m3u8"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aac",LANGUAGE="ja",NAME="日本語",URI="audio/ja.m3u8"
+#EXT-X-MEDIA:TYPE=CLOSED-CAPTIONS,GROUP-ID="cc",NAME="CC1",INSTREAM-ID="CC1"
+#EXT-X-STREAM-INF:BANDWIDTH=2500000,AVERAGE-BANDWIDTH=2000000,CODECS="avc1.4d401f,mp4a.40.2",RESOLUTION=1280x720,FRAME-RATE=29.970,AUDIO="aac",CLOSED-CAPTIONS="cc"
+720p.m3u8
+# A comment.
+#EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS="avc1.4d401e,mp4a.40.2",RESOLUTION=640x360,AUDIO="aac",CLOSED-CAPTIONS=NONE
+https://cdn.example/360p.m3u8
+#EXT-X-I-FRAME-STREAM-INF:BANDWIDTH=100000,CODECS="avc1.4d401f",URI="720p-iframes.m3u8"
+"#,
+        )
+        .unwrap();
+        let mut master = match playlist {
+            Playlist::Master(master) => master,
+            Playlist::Media(_) => panic!("read a master playlist as a media playlist"),
+        };
+        master
+            .set_location("https://example.com/live/master.m3u8")
+            .unwrap();
+
+        assert_eq!(master.version(), Some(6));
+        assert!(master.independent_segments());
+        assert_eq!(master.variants().count(), 2);
+        assert_eq!(master.i_frame_variants().count(), 1);
+
+        let best = master.highest_bandwidth().unwrap();
+        assert_eq!(best.uri(), "https://example.com/live/720p.m3u8");
+        assert_eq!(best.average_bandwidth(), Some(2_000_000));
+        assert_eq!(best.resolution(), Some((1280, 720)));
+        assert!((best.frame_rate().unwrap() - 29.97).abs() < 0.001);
+        assert_eq!(best.closed_captions().as_deref(), Some("cc"));
+        assert_eq!(
+            master.lowest_bandwidth().unwrap().uri(),
+            "https://cdn.example/360p.m3u8"
+        );
+        assert_eq!(master.lowest_bandwidth().unwrap().closed_captions(), None);
+        assert_eq!(
+            master.closest_resolution(700, 400).unwrap().bandwidth(),
+            800_000
+        );
+        assert_eq!(master.by_codec("mp4a").count(), 2);
+
+        let group = best.audio().unwrap();
+        let audio: Vec<_> = master.group(&group).collect();
+        assert_eq!(audio.len(), 2);
+        assert!(audio[0].is_default());
+        assert_eq!(audio[1].name(), "日本語");
+        assert_eq!(
+            audio[1].uri().as_deref(),
+            Some("https://example.com/live/audio/ja.m3u8")
+        );
+        let cc = master.group("cc").next().unwrap();
+        assert_eq!(cc.media_type(), MediaType::ClosedCaptions);
+        assert_eq!(cc.uri(), None);
+    }
+
+    #[test]
+    fn media_playlist() {
+        let mut media = MediaPlaylist::try_from(
+            "#EXTM3U\r
+#EXT-X-VERSION:7\r
+#EXT-X-TARGETDURATION:6\r
+#EXT-X-MEDIA-SEQUENCE:100\r
+#EXT-X-DISCONTINUITY-SEQUENCE:3\r
+#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"\r
+#EXT-X-PROGRAM-DATE-TIME:2021-05-01T12:00:00.000Z\r
+#EXTINF:6.006,Opening\r
+#EXT-X-BYTERANGE:1000@720\r
+main.mp4\r
+#EXTINF:5.5,\r
+#EXT-X-BYTERANGE:2000\r
+main.mp4\r
+#EXT-X-DISCONTINUITY\r
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x000102030405060708090A0B0C0D0E0F\r
+#EXTINF:4,\r
+ad.ts\r
+#EXT-X-KEY:METHOD=NONE\r
+#EXT-X-GAP\r
+#EXTINF:6,\r
+missing.ts\r
+#EXT-X-ENDLIST\r
+",
+        )
+        .unwrap();
+        media
+            .set_location("https://example.com/vod/index.m3u8")
+            .unwrap();
+
+        assert_eq!(media.version(), Some(7));
+        assert_eq!(media.target_duration(), Duration::from_secs(6));
+        assert!(!media.is_live());
+        assert_eq!(media.duration(), Duration::from_secs_f64(21.506));
+
+        let segments: Vec<_> = media.segments().collect();
+        assert_eq!(segments.len(), 4);
+        assert_eq!(segments[0].title().as_deref(), Some("Opening"));
+        assert_eq!(segments[0].sequence(), 100);
+        assert_eq!(segments[0].range(), Some("720-1719".parse().unwrap()));
+        assert_eq!(segments[1].range(), Some("1720-3719".parse().unwrap()));
+        assert_eq!(segments[1].uri(), "https://example.com/vod/main.mp4");
+        let map = segments[1].map().unwrap();
+        assert_eq!(map.uri(), "https://example.com/vod/init.mp4");
+        assert_eq!(map.range(), Some("0-719".parse().unwrap()));
+
+        let start = date_time::parse("2021-05-01T12:00:00Z").unwrap();
+        assert_eq!(segments[0].program_date_time(), Some(start));
+        assert_eq!(
+            segments[2].program_date_time(),
+            Some(start + chrono::Duration::milliseconds(11_506))
+        );
+
+        assert!(!segments[1].discontinuity());
+        assert!(segments[2].discontinuity());
+        assert_eq!(segments[1].discontinuity_sequence(), 3);
+        assert_eq!(segments[2].discontinuity_sequence(), 4);
+
+        let keys = segments[2].keys();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].method(), KeyMethod::Aes128);
+        assert_eq!(
+            keys[0].uri().as_deref(),
+            Some("https://example.com/vod/key.bin")
+        );
+        assert_eq!(keys[0].iv().unwrap()[15], 0x0f);
+        assert_eq!(keys[0].key_format(), "identity");
+        assert!(segments[3].keys().is_empty());
+        assert!(segments[3].gap());
+    }
+
+    #[test]
+    fn live_playlist() {
+        let media = MediaPlaylist::try_from(
+            "#EXTM3U
+#EXT-X-TARGETDURATION:2
+#EXT-X-MEDIA-SEQUENCE:5000
+#EXTINF:2.0,
+5000.ts
+#EXTINF:2.0,
+5001.ts
+",
+        )
+        .unwrap();
+        assert!(media.is_live());
+        assert_eq!(media.segments().last().unwrap().sequence(), 5001);
+
+        assert!(Playlist::try_from("#EXT-X-TARGETDURATION:2\n").is_err());
+        assert!(MediaPlaylist::try_from("#EXTM3U\n#EXTINF:2,\na.ts\n").is_err());
+        assert!(MasterPlaylist::try_from("#EXTM3U\n#EXT-X-TARGETDURATION:2\n").is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let media = |body: &str| {
+            MediaPlaylist::try_from(format!("#EXTM3U\n#EXT-X-TARGETDURATION:2\n{}", body).as_str())
+        };
+        assert!(media("#EXTINF:1e300,\na.ts\n").is_err());
+        assert!(media("#EXTINF:-1,\na.ts\n").is_err());
+        assert!(media("#EXTINF:2,\n#EXT-X-BYTERANGE:2@18446744073709551615\na.ts\n").is_err());
+        assert!(media("#EXTINF:2,\n#EXT-X-BYTERANGE:0@0\na.ts\n").is_err());
+        assert!(media("#EXT-X-MAP:URI=\"i.mp4\",BYTERANGE=\"18446744073709551615@2\"\n").is_err());
+        // An IV of the right length, but not of hexadecimal digits.
+        assert!(media(&format!(
+            "#EXT-X-KEY:METHOD=AES-128,URI=\"k\",IV=0x{}\n#EXTINF:2,\na.ts\n",
+            "é".repeat(16)
+        ))
+        .is_err());
+        assert!(media(&format!(
+            "#EXT-X-KEY:METHOD=AES-128,URI=\"k\",IV=0x{}\n#EXTINF:2,\na.ts\n",
+            "0g".repeat(16)
+        ))
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn key_protection() {
+        let fairplay = MediaPlaylist::try_from(
+            r#"#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXT-X-KEY:METHOD=SAMPLE-AES,URI="skd://key",KEYFORMAT="com.apple.streamingkeydelivery",KEYFORMATVERSIONS="1"
+#EXT-X-KEY:METHOD=SAMPLE-AES-CTR,URI="data:text/plain;base64,AAAA",KEYFORMAT="urn:uuid:edef8ba9-79d6-4ace-a3c8-27dcd51d21ed"
+#EXTINF:6,
+0.mp4
+#EXT-X-ENDLIST
+"#,
+        )
+        .unwrap();
+        let segment = fairplay.segments().next().unwrap();
+        assert_eq!(segment.keys().len(), 2);
+        assert!(segment.is_protected());
+        assert_eq!(
+            fairplay.drm_systems(),
+            vec![drm::System::FairPlay, drm::System::Widevine]
+        );
+
+        // SAMPLE-AES with a plain key is encrypted, but by no DRM system.
+        let sample_aes = MediaPlaylist::try_from(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"k\"\n#EXTINF:6,\n0.ts\n",
+        )
+        .unwrap();
+        assert!(sample_aes.is_protected());
+        assert!(sample_aes.drm_systems().is_empty());
+
+        let aes = MediaPlaylist::try_from(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXT-X-KEY:METHOD=AES-128,URI=\"k\"\n#EXTINF:6,\n0.ts\n",
+        )
+        .unwrap();
+        assert!(!aes.is_protected());
+        assert!(aes.segments().next().unwrap().aes128_key().is_some());
+
+        #[cfg(feature = "client")]
+        {
+            let path = std::env::temp_dir().join("maguro-key-protection.ts");
+            let mut dest = tokio::fs::File::create(&path).await.unwrap();
+            let error = sample_aes.download(&mut dest).await.unwrap_err();
+            assert!(error.downcast_ref::<drm::Protected>().is_some());
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
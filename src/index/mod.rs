@@ -0,0 +1,401 @@
+//! Segment indexes of adaptive formats.
+//!
+//! Adaptive [Formats](crate::Format) carry the byte range of an index
+//! describing where each of their media segments starts: an MP4 `sidx` box,
+//! or the Cues of a WebM file. Reading it lets maguro fetch a single
+//! segment without downloading everything before it.
+//!
+//! The parsers work on byte slices, so they can be used on local files as
+//! well as on the ranges fetched from a [Format].
+
+use std::time::Duration;
+
+use crate::{
+    mux::{ebml, mp4, webm::id, Error},
+    Format,
+};
+
+/// A media segment located by an index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    /// Presentation time at which the segment starts.
+    pub start: Duration,
+
+    /// Length of the segment, unless it is the last one of a WebM file.
+    pub duration: Option<Duration>,
+
+    /// Offset of the segment's first byte within the file.
+    pub offset: u64,
+
+    /// Length of the segment in bytes, unless it runs to the end of a WebM
+    /// file of unknown length.
+    pub size: Option<u64>,
+}
+
+impl Segment {
+    /// Whether any part of the segment falls between `start` and `end`.
+    pub fn overlaps(&self, start: Duration, end: Duration) -> bool {
+        self.start < end && self.duration.is_none_or(|d| self.start + d > start)
+    }
+}
+
+/// Read the segments of a [Format] from the bytes of its `initRange` and
+/// `indexRange`.
+pub fn parse(format: &Format, init: &[u8], index: &[u8]) -> Result<Vec<Segment>, Error> {
+    let index_range = format
+        .index_range()
+        .ok_or_else(|| Error::Unsupported(format!("format {} is not indexed", format.itag())))?;
+    if format.mime_type().subtype() == "webm" {
+        cues(init, index, format.size().map(u64::from))
+    } else {
+        sidx(index, index_range.start())
+    }
+}
+
+/// Read the segments listed by an MP4 `sidx` box, which begins `offset`
+/// bytes into the file. Boxes referencing further `sidx` boxes are followed
+/// as long as those lie within `index`.
+pub fn sidx(index: &[u8], offset: u64) -> Result<Vec<Segment>, Error> {
+    let sidx = mp4::Atoms::new(index)
+        .find(b"sidx")?
+        .ok_or(Error::Malformed("missing sidx"))?;
+    let mut segments = Vec::new();
+    read_sidx(index, offset, sidx.offset, &mut segments)?;
+    Ok(segments)
+}
+
+/// Read the `sidx` box found `at` bytes into `index`, appending its
+/// segments to `out`.
+fn read_sidx(index: &[u8], offset: u64, at: usize, out: &mut Vec<Segment>) -> Result<(), Error> {
+    let sidx = mp4::Atoms::new(&index[at..])
+        .next_atom()?
+        .filter(|a| &a.kind == b"sidx")
+        .ok_or(Error::Malformed("reference to a missing sidx"))?;
+    let (version, _, body) = sidx.full()?;
+    let mut c = mp4::Cursor::new(body);
+    c.skip(4)?;
+    let timescale = u64::from(c.u32()?.max(1));
+    let mut time = c.versioned(version)?;
+    let first_offset = c.versioned(version)?;
+    c.skip(2)?;
+    let count = c.u16()?;
+
+    // Offsets count from the first byte after the sidx.
+    let out_of_range = || Error::Malformed("sidx offset or time out of range");
+    let mut pos = offset
+        .checked_add((at + sidx.len()) as u64)
+        .and_then(|p| p.checked_add(first_offset))
+        .ok_or_else(out_of_range)?;
+    for _ in 0..count {
+        let reference = c.u32()?;
+        let duration = u64::from(c.u32()?);
+        c.skip(4)?;
+        let size = u64::from(reference & 0x7FFF_FFFF);
+        let end = time.checked_add(duration).ok_or_else(out_of_range)?;
+
+        if reference & 0x8000_0000 != 0 {
+            // The reference is to another sidx, which must have been fetched
+            // along with this one.
+            let nested = pos
+                .checked_sub(offset)
+                .map(|p| p as usize)
+                .filter(|p| *p < index.len())
+                .ok_or_else(|| {
+                    Error::Unsupported("sidx referencing data outside the index".into())
+                })?;
+            read_sidx(index, offset, nested, out)?;
+        } else {
+            out.push(Segment {
+                start: ticks(time, timescale),
+                duration: Some(ticks(end, timescale) - ticks(time, timescale)),
+                offset: pos,
+                size: Some(size),
+            });
+        }
+        time = end;
+        pos = pos.checked_add(size).ok_or_else(out_of_range)?;
+    }
+    Ok(())
+}
+
+/// Read the clusters listed by the Cues of a WebM file. `init` must hold the
+/// file from its first byte through its Info, and `len` is the length of
+/// the whole file, if known.
+pub fn cues(init: &[u8], index: &[u8], len: Option<u64>) -> Result<Vec<Segment>, Error> {
+    let mut top = ebml::Reader::new(init);
+    top.next_element()?;
+    let segment = top
+        .find(id::SEGMENT)?
+        .ok_or(Error::Malformed("missing Segment"))?;
+    // Positions count from the start of the Segment's body.
+    let base = (segment.offset + segment.header_len) as u64;
+
+    let mut scale = 1_000_000;
+    if let Some(info) = segment.children().find(id::INFO)? {
+        if let Some(s) = info.children().find(id::TIMECODE_SCALE)? {
+            scale = s.uint()?;
+        }
+    }
+
+    let cues = ebml::Reader::new(index)
+        .find(id::CUES)?
+        .ok_or(Error::Malformed("missing Cues"))?;
+    let mut points = Vec::new();
+    for point in cues.children() {
+        let point = point?;
+        let time = match point.children().find(id::CUE_TIME)? {
+            Some(t) => t.uint()?,
+            None => continue,
+        };
+        let position = match point.children().find(id::CUE_TRACK_POSITIONS)? {
+            Some(p) => match p.children().find(id::CUE_CLUSTER_POSITION)? {
+                Some(c) => c.uint()?,
+                None => continue,
+            },
+            None => continue,
+        };
+        let time = time
+            .checked_mul(scale)
+            .ok_or(Error::Malformed("cue time out of range"))?;
+        let position = base
+            .checked_add(position)
+            .ok_or(Error::Malformed("cue position out of range"))?;
+        points.push((Duration::from_nanos(time), position));
+    }
+    // Several tracks may share a cluster; only its first cue matters.
+    points.dedup_by_key(|(_, offset)| *offset);
+
+    let mut segments = Vec::with_capacity(points.len());
+    for (i, (start, offset)) in points.iter().enumerate() {
+        let next = points.get(i + 1);
+        let duration = match next {
+            Some((t, _)) => Some(
+                t.checked_sub(*start)
+                    .ok_or(Error::Malformed("cue times out of order"))?,
+            ),
+            None => None,
+        };
+        let size = match next.map(|(_, o)| *o).or(len) {
+            Some(end) => Some(
+                end.checked_sub(*offset)
+                    .ok_or(Error::Malformed("cue positions out of order"))?,
+            ),
+            None => None,
+        };
+        segments.push(Segment {
+            start: *start,
+            duration,
+            offset: *offset,
+            size,
+        });
+    }
+    Ok(segments)
+}
+
+/// Convert `t` ticks of `timescale` per second to a [Duration].
+fn ticks(t: u64, timescale: u64) -> Duration {
+    Duration::from_secs(t / timescale)
+        + Duration::from_nanos((t % timescale) * 1_000_000_000 / timescale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mux::ebml::{write_element, write_uint};
+
+    /// A version 0 sidx with a timescale of 1000 and the given
+    /// (hierarchical, size, duration) references.
+    fn sidx_box(first_offset: u32, time: u32, refs: &[(bool, u32, u32)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&1u32.to_be_bytes());
+        body.extend_from_slice(&1000u32.to_be_bytes());
+        body.extend_from_slice(&time.to_be_bytes());
+        body.extend_from_slice(&first_offset.to_be_bytes());
+        body.extend_from_slice(&0u16.to_be_bytes());
+        body.extend_from_slice(&(refs.len() as u16).to_be_bytes());
+        for (nested, size, duration) in refs {
+            body.extend_from_slice(&(size | if *nested { 0x8000_0000 } else { 0 }).to_be_bytes());
+            body.extend_from_slice(&duration.to_be_bytes());
+            body.extend_from_slice(&0x9000_0000u32.to_be_bytes());
+        }
+        let mut out = Vec::new();
+        mp4::write_full_box(&mut out, b"sidx", 0, 0, &body);
+        out
+    }
+
+    #[test]
+    fn rejects_overflowing_sidx() {
+        // A version 1 sidx whose first offset or earliest time leaves no
+        // room for its reference.
+        let sidx = |time: u64, first_offset: u64| {
+            let mut body = Vec::new();
+            body.extend_from_slice(&1u32.to_be_bytes());
+            body.extend_from_slice(&1000u32.to_be_bytes());
+            body.extend_from_slice(&time.to_be_bytes());
+            body.extend_from_slice(&first_offset.to_be_bytes());
+            body.extend_from_slice(&0u16.to_be_bytes());
+            body.extend_from_slice(&1u16.to_be_bytes());
+            body.extend_from_slice(&100u32.to_be_bytes());
+            body.extend_from_slice(&1000u32.to_be_bytes());
+            body.extend_from_slice(&0x9000_0000u32.to_be_bytes());
+            let mut out = Vec::new();
+            mp4::write_full_box(&mut out, b"sidx", 1, 0, &body);
+            out
+        };
+        assert!(read_sidx(&sidx(0, 0), 0, 0, &mut Vec::new()).is_ok());
+        assert!(read_sidx(&sidx(0, u64::MAX), 0, 0, &mut Vec::new()).is_err());
+        assert!(read_sidx(&sidx(0, u64::MAX - 60), 0, 0, &mut Vec::new()).is_err());
+        assert!(read_sidx(&sidx(u64::MAX, 0), 0, 0, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn reads_sidx() {
+        let index = sidx_box(0, 0, &[(false, 100, 5000), (false, 200, 2500)]);
+        let segments = sidx(&index, 700).unwrap();
+        let media = 700 + index.len() as u64;
+        assert_eq!(
+            segments,
+            vec![
+                Segment {
+                    start: Duration::from_secs(0),
+                    duration: Some(Duration::from_secs(5)),
+                    offset: media,
+                    size: Some(100),
+                },
+                Segment {
+                    start: Duration::from_secs(5),
+                    duration: Some(Duration::from_millis(2500)),
+                    offset: media + 100,
+                    size: Some(200),
+                },
+            ]
+        );
+        assert!(segments[1].overlaps(Duration::from_secs(6), Duration::from_secs(60)));
+        assert!(!segments[0].overlaps(Duration::from_secs(5), Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn follows_hierarchical_sidx() {
+        let first = sidx_box(0, 0, &[(false, 10, 1000), (false, 20, 1000)]);
+        let second = sidx_box(0, 2000, &[(false, 40, 1000)]);
+        // Each nested sidx is followed by the media it describes.
+        let top = sidx_box(
+            0,
+            0,
+            &[
+                (true, (first.len() + 30) as u32, 2000),
+                (true, (second.len() + 40) as u32, 1000),
+            ],
+        );
+        let mut index = top.clone();
+        index.extend_from_slice(&first);
+        index.extend_from_slice(&[0; 30]);
+        index.extend_from_slice(&second);
+
+        let segments = sidx(&index, 0).unwrap();
+        let offsets: Vec<_> = segments.iter().map(|s| s.offset).collect();
+        let after_first = (top.len() + first.len()) as u64;
+        assert_eq!(
+            offsets,
+            vec![
+                after_first,
+                after_first + 10,
+                after_first + 30 + second.len() as u64,
+            ]
+        );
+        assert_eq!(segments[2].start, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn rejects_sidx_outside_index() {
+        let index = sidx_box(0, 0, &[(true, 50, 1000)]);
+        assert!(matches!(sidx(&index, 0), Err(Error::Unsupported(_))));
+    }
+
+    /// The start of a WebM file with a millisecond timecode scale, Cues with
+    /// a point for each (time, cluster position), and where positions count
+    /// from.
+    fn webm_index(points: &[(u64, u64)]) -> (Vec<u8>, Vec<u8>, u64) {
+        let mut info = Vec::new();
+        write_uint(&mut info, id::TIMECODE_SCALE, 1_000_000);
+        let mut body = Vec::new();
+        write_element(&mut body, id::INFO, &info);
+        let mut init = Vec::new();
+        write_element(&mut init, id::EBML, &[]);
+        // The Segment is written with its full size, as it would be on disk.
+        ebml::write_id(&mut init, id::SEGMENT);
+        ebml::write_size_exact(&mut init, 10_000, 8);
+        let base = init.len() as u64;
+        init.extend_from_slice(&body);
+
+        let mut cues_body = Vec::new();
+        for (time, position) in points {
+            let mut positions = Vec::new();
+            write_uint(&mut positions, id::CUE_TRACK, 1);
+            write_uint(&mut positions, id::CUE_CLUSTER_POSITION, *position);
+            let mut point = Vec::new();
+            write_uint(&mut point, id::CUE_TIME, *time);
+            write_element(&mut point, id::CUE_TRACK_POSITIONS, &positions);
+            write_element(&mut cues_body, id::CUE_POINT, &point);
+        }
+        let mut index = Vec::new();
+        write_element(&mut index, id::CUES, &cues_body);
+        (init, index, base)
+    }
+
+    #[test]
+    fn reads_cues() {
+        let (init, index, base) = webm_index(&[(0, 100), (0, 100), (4000, 900)]);
+        let segments = cues(&init, &index, Some(5000)).unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                Segment {
+                    start: Duration::from_secs(0),
+                    duration: Some(Duration::from_secs(4)),
+                    offset: base + 100,
+                    size: Some(800),
+                },
+                Segment {
+                    start: Duration::from_secs(4),
+                    duration: None,
+                    offset: base + 900,
+                    size: Some(5000 - base - 900),
+                },
+            ]
+        );
+        assert!(segments[1].overlaps(Duration::from_secs(100), Duration::from_secs(101)));
+
+        // Without the file's length, the last cluster runs to an unknown end.
+        let segments = cues(&init, &index, None).unwrap();
+        assert_eq!(segments[1].size, None);
+    }
+
+    #[test]
+    fn rejects_unordered_cues() {
+        let (init, index, _) = webm_index(&[(4000, 100), (0, 900)]);
+        assert!(matches!(
+            cues(&init, &index, None),
+            Err(Error::Malformed(_))
+        ));
+
+        let (init, index, _) = webm_index(&[(0, 900), (4000, 100)]);
+        assert!(matches!(
+            cues(&init, &index, None),
+            Err(Error::Malformed(_))
+        ));
+
+        // A file shorter than its last cluster's position is as bad.
+        let (init, index, base) = webm_index(&[(0, 100), (4000, 900)]);
+        assert!(matches!(
+            cues(&init, &index, Some(base + 500)),
+            Err(Error::Malformed(_))
+        ));
+
+        let (init, index, _) = webm_index(&[(u64::MAX / 2, 100)]);
+        assert!(matches!(
+            cues(&init, &index, None),
+            Err(Error::Malformed(_))
+        ));
+    }
+}
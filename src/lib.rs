@@ -27,24 +27,44 @@
 //! // Download the video.
 //! format.download(&mut output).await?;
 //! ```
+//!
+//! ## Features
+//!
+//! Fetching and downloading is provided by the `client` feature, which is
+//! enabled by default along with the `cli` feature for the command-line
+//! tool. Without them, maguro has no network stack, and only parses what
+//! it is given: player responses through [InfoResponse::from_video_info],
+//! DASH manifests through [dash::Manifest]'s `TryFrom<&str>`, HLS playlists
+//! through [hls::Playlist]'s, and channel feeds through [query::Feed]'s.
 
 use ::serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "client")]
 use hyper::{
     body::{self, HttpBody},
-    Client,
+    header::RANGE,
+    Body, Client, Request, StatusCode,
 };
+#[cfg(feature = "client")]
 use hyper_tls::HttpsConnector;
-use log::info;
+#[cfg(feature = "client")]
+use log::{info, warn};
 use std::{
     cmp::Ordering,
+    convert::TryFrom,
     error,
     fmt::{self, Display},
     str,
     time::Duration,
 };
+#[cfg(feature = "client")]
 use tokio::{fs::File, io::AsyncWriteExt};
 
 pub mod dash;
+pub mod drm;
+pub mod hls;
+pub mod index;
+pub mod mux;
 pub mod query;
 mod serde;
 
@@ -77,6 +97,9 @@ pub struct Format {
     quality: String,
     fps: Option<u32>,
 
+    // Average bits per second.
+    bitrate: Option<u32>,
+
     #[serde(
         default,
         rename = "approxDurationMs",
@@ -84,6 +107,17 @@ pub struct Format {
     )]
     // A stream may not have a defined length.
     approx_duration: Option<Duration>,
+
+    // Only adaptive formats are indexed.
+    #[serde(default, rename = "initRange")]
+    init_range: Option<ByteRange>,
+
+    #[serde(default, rename = "indexRange")]
+    index_range: Option<ByteRange>,
+
+    // DRM systems the stream is encrypted for, such as `WIDEVINE`.
+    #[serde(default, rename = "drmFamilies")]
+    drm_families: Vec<String>,
 }
 
 impl Format {
@@ -100,21 +134,66 @@ impl Format {
         self.itag
     }
 
+    /// MIME type of the [Format], including its codecs.
+    pub fn mime_type(&self) -> mime::Mime {
+        self.mime_type.clone()
+    }
+
+    /// Average bitrate of the [Format] in bits per second.
+    pub fn bitrate(&self) -> Option<u32> {
+        self.bitrate
+    }
+
     /// Content length of the [Format].
     pub fn size(&self) -> Option<u32> {
         self.content_length.clone()
     }
 
+    /// Bytes holding the [Format]'s initialization data, if it is adaptive.
+    pub fn init_range(&self) -> Option<ByteRange> {
+        self.init_range.clone()
+    }
+
+    /// Bytes holding the [Format]'s segment index, if it is adaptive.
+    pub fn index_range(&self) -> Option<ByteRange> {
+        self.index_range.clone()
+    }
+
     /// Returns the URL to download the [Format].
     pub fn url(&self) -> String {
         self.url.clone()
     }
 
+    /// Whether the [Format] is encrypted with DRM, and so cannot be
+    /// downloaded.
+    pub fn is_protected(&self) -> bool {
+        !self.drm_families.is_empty()
+    }
+
+    /// The DRM systems the [Format] is encrypted for, if any.
+    pub fn drm_systems(&self) -> Vec<drm::System> {
+        self.drm_families
+            .iter()
+            .map(|f| drm::System::from_family(f))
+            .collect()
+    }
+
+    #[cfg(feature = "client")]
+    /// Fails with [drm::Protected] if the [Format] is encrypted.
+    fn check_unprotected(&self) -> Result<(), drm::Protected> {
+        if self.is_protected() {
+            return Err(drm::Protected::new(self.drm_systems()));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "client")]
     /// Read the entire YouTube video into a vector.
     pub async fn to_vec(&self) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
         self.to_vec_callback(|_| Ok(())).await
     }
 
+    #[cfg(feature = "client")]
     /// Downloads the entire YouTube video in chunks with the given closure.
     /// On receipt of a new chunk of bytes, it calls the closure.
     pub async fn to_vec_callback<T>(
@@ -124,6 +203,7 @@ impl Format {
     where
         T: Fn(Vec<u8>) -> Result<(), Box<dyn error::Error + Send + Sync>>,
     {
+        self.check_unprotected()?;
         let https = HttpsConnector::new();
         let client = Client::builder().build::<_, hyper::Body>(https);
 
@@ -138,11 +218,118 @@ impl Format {
         Ok(v)
     }
 
-    /// Downloads the entire YouTube video into a `File`.
+    #[cfg(feature = "client")]
+    /// Downloads the bytes of the [Format] from `start` through `end`
+    /// inclusive, or through the end of the stream if `end` is [None].
+    async fn fetch_range(
+        &self,
+        start: u64,
+        end: Option<u64>,
+    ) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+        self.check_unprotected()?;
+        let https = HttpsConnector::new();
+        let client = Client::builder().build::<_, hyper::Body>(https);
+
+        let range = match end {
+            Some(end) => format!("bytes={}-{}", start, end),
+            None => format!("bytes={}-", start),
+        };
+        let req = Request::get(self.url.as_str())
+            .header(RANGE, range)
+            .body(Body::empty())?;
+        let mut res = client.request(req).await?;
+        match res.status() {
+            StatusCode::PARTIAL_CONTENT => Ok(body::to_bytes(res.body_mut()).await?.to_vec()),
+            // The server ignored the range and sent the whole format.
+            StatusCode::OK => {
+                let body = body::to_bytes(res.body_mut()).await?;
+                let start = start as usize;
+                let end = end.map_or(body.len(), |end| (end as usize + 1).min(body.len()));
+                if start > end {
+                    return Err(format!(
+                        "Range starts past the end of the {} bytes sent",
+                        body.len()
+                    )
+                    .into());
+                }
+                Ok(body[start..end].to_vec())
+            }
+            status => Err(format!("Range request failed with {}", status).into()),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    /// Downloads only the part of the [Format] between `start` and `end`
+    /// into a vector, as a playable file of its own.
+    ///
+    /// The media segments covering the window are located through the
+    /// format's index, so the clip begins and ends on segment boundaries
+    /// and may run slightly past either end of the window. Only adaptive
+    /// formats are indexed.
+    pub async fn range_to_vec(
+        &self,
+        start: Duration,
+        end: Duration,
+    ) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+        let (init_range, index_range) = match (&self.init_range, &self.index_range) {
+            (Some(init), Some(index)) => (init, index),
+            _ => return Err(format!("Format {} has no segment index", self.itag).into()),
+        };
+        let init = self
+            .fetch_range(init_range.start, Some(init_range.end))
+            .await?;
+        let index = self
+            .fetch_range(index_range.start, Some(index_range.end))
+            .await?;
+
+        let segments = index::parse(self, &init, &index)?;
+        let covering: Vec<_> = segments.iter().filter(|s| s.overlaps(start, end)).collect();
+        let (first, last) = match (covering.first(), covering.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err("No segments fall within the requested range".into()),
+        };
+
+        info!(
+            "Fetching {} segments from {:?} to {:?}",
+            covering.len(),
+            first.start,
+            last.duration.map(|d| last.start + d)
+        );
+        let media = self
+            .fetch_range(first.offset, last.size.map(|s| last.offset + s - 1))
+            .await?;
+
+        let mut out = if self.mime_type.subtype() == "webm" {
+            mux::webm::clip_header(&init)?
+        } else {
+            init
+        };
+        out.extend_from_slice(&media);
+        Ok(out)
+    }
+
+    #[cfg(feature = "client")]
+    /// Downloads only the part of the [Format] between `start` and `end`
+    /// into a `File`. See [Format::range_to_vec].
+    pub async fn download_range(
+        &self,
+        start: Duration,
+        end: Duration,
+        dest: &mut File,
+    ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let clip = self.range_to_vec(start, end).await?;
+        dest.write_all(&clip).await?;
+        Ok(())
+    }
+
+    #[cfg(feature = "client")]
+    /// Downloads the entire YouTube video into a `File`. Fails with
+    /// [drm::Protected] if the [Format] is encrypted.
     pub async fn download(
         &self,
         dest: &mut File,
     ) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        self.check_unprotected()?;
         let https = HttpsConnector::new();
         let client = Client::builder().build::<_, hyper::Body>(https);
 
@@ -165,13 +352,55 @@ impl Format {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+/// An inclusive range of bytes within a [Format].
+pub struct ByteRange {
+    #[serde(deserialize_with = "serde::u64::from_str")]
+    start: u64,
+
+    #[serde(deserialize_with = "serde::u64::from_str")]
+    end: u64,
+}
+
+impl str::FromStr for ByteRange {
+    type Err = Box<dyn error::Error + Send + Sync>;
+
+    /// Parse a range of the form `first-last`, as written in DASH manifests.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid byte range {}", s))?;
+        Ok(Self {
+            start: start.trim().parse()?,
+            end: end.trim().parse()?,
+        })
+    }
+}
+
+impl ByteRange {
+    /// Offset of the first byte in the range.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Offset of the last byte in the range.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+}
+
 impl Display for Format {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
             "itag: {:>3} | Quality: {:<7} | Mime Type: {:<20}",
             self.itag, self.quality, self.mime_type
-        )
+        )?;
+        if self.is_protected() {
+            let systems: Vec<_> = self.drm_systems().iter().map(ToString::to_string).collect();
+            write!(f, " | DRM: {}", systems.join(", "))?;
+        }
+        Ok(())
     }
 }
 
@@ -193,7 +422,7 @@ impl PartialOrd for Format {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 /// The set of sources available to download a YouTube
 /// video with.
 pub struct StreamingData {
@@ -206,8 +435,15 @@ pub struct StreamingData {
     // In the case of streams, the `formats` field is empty.
     formats: Option<Vec<Format>>,
 
-    #[serde(rename = "adaptiveFormats")]
+    #[serde(default, rename = "adaptiveFormats")]
     adaptive_formats: Vec<Format>,
+
+    // Streams that are live now only.
+    #[serde(rename = "dashManifestUrl")]
+    dash_manifest_url: Option<String>,
+
+    #[serde(rename = "hlsManifestUrl")]
+    hls_manifest_url: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -222,6 +458,7 @@ pub struct VideoDetails {
     author: String,
 
     #[serde(
+        default,
         rename = "lengthSeconds",
         deserialize_with = "serde::duration::from_secs_option"
     )]
@@ -235,25 +472,229 @@ pub struct VideoDetails {
 
     #[serde(rename = "isLiveContent")]
     live: bool,
+
+    #[serde(default, rename = "shortDescription")]
+    description: String,
+
+    #[serde(default)]
+    thumbnail: Thumbnails,
 }
 
 impl VideoDetails {
     pub fn id(&self) -> String {
         self.video_id.clone()
     }
+
+    /// Title of the video.
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    /// Name of the channel that uploaded the video.
+    pub fn author(&self) -> String {
+        self.author.clone()
+    }
+
+    /// Description of the video.
+    pub fn description(&self) -> String {
+        self.description.clone()
+    }
+
+    /// Whether the video is, or was, a live stream.
+    pub fn is_live(&self) -> bool {
+        self.live
+    }
+
+    /// Thumbnails available for the video, ordered from smallest to largest.
+    pub fn thumbnails(&self) -> Vec<Thumbnail> {
+        let mut sorted = self.thumbnail.thumbnails.clone();
+        sorted.sort_by_key(|t| t.width * t.height);
+        sorted
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+/// Wrapper around the list of [Thumbnails](Thumbnail) of a video.
+struct Thumbnails {
+    thumbnails: Vec<Thumbnail>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A still image representing a video.
+pub struct Thumbnail {
+    url: String,
+    width: u32,
+    height: u32,
+}
+
+impl Thumbnail {
+    /// Returns the URL to download the [Thumbnail].
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Width of the [Thumbnail] in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height of the [Thumbnail] in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+/// Supplementary details about a video, as used for embedding.
+struct Microformat {
+    #[serde(rename = "playerMicroformatRenderer")]
+    renderer: MicroformatRenderer,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct MicroformatRenderer {
+    #[serde(rename = "publishDate")]
+    publish_date: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+/// Whether a video can be played and, if it is an upcoming premiere or
+/// scheduled live stream, when it will start.
+pub struct PlayabilityStatus {
+    status: String,
+    reason: Option<String>,
+
+    // Upcoming live streams and premieres only.
+    #[serde(rename = "liveStreamability")]
+    live_streamability: Option<LiveStreamability>,
+}
+
+impl PlayabilityStatus {
+    /// Status of the video, such as `OK`, `LIVE_STREAM_OFFLINE` or
+    /// `UNPLAYABLE`.
+    pub fn status(&self) -> String {
+        self.status.clone()
+    }
+
+    /// Why the video cannot be played, if it cannot.
+    pub fn reason(&self) -> Option<String> {
+        self.reason.clone()
+    }
+
+    /// Whether the video can be played, and so downloaded, now.
+    pub fn is_playable(&self) -> bool {
+        self.status == "OK"
+    }
+
+    /// Whether the video is a premiere or live stream yet to start.
+    pub fn is_upcoming(&self) -> bool {
+        !self.is_playable()
+            && (self.status == "LIVE_STREAM_OFFLINE" || self.scheduled_start().is_some())
+    }
+
+    /// When an upcoming video is scheduled to start.
+    pub fn scheduled_start(&self) -> Option<DateTime<Utc>> {
+        let slate = self
+            .live_streamability
+            .as_ref()?
+            .renderer
+            .offline_slate
+            .as_ref()?;
+        let secs = slate.renderer.scheduled_start_time?;
+        Some(DateTime::<Utc>::from(std::time::UNIX_EPOCH + secs))
+    }
+
+    /// How often YouTube asks that an upcoming video be checked on.
+    pub fn poll_delay(&self) -> Option<Duration> {
+        self.live_streamability.as_ref()?.renderer.poll_delay
+    }
+
+    #[cfg(feature = "client")]
+    /// How long to wait, from `now`, before checking on an upcoming video
+    /// again: until it is due to start, but no longer than ten minutes in
+    /// case it is rescheduled, and no sooner than YouTube asks.
+    fn next_poll(&self, now: DateTime<Utc>) -> Duration {
+        let poll = self
+            .poll_delay()
+            .unwrap_or(Duration::from_secs(15))
+            .max(Duration::from_secs(5));
+        match self.scheduled_start().and_then(|s| (s - now).to_std().ok()) {
+            Some(until) => until.min(Duration::from_secs(600)).max(poll),
+            None => poll,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct LiveStreamability {
+    #[serde(rename = "liveStreamabilityRenderer")]
+    renderer: LiveStreamabilityRenderer,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct LiveStreamabilityRenderer {
+    #[serde(rename = "offlineSlate")]
+    offline_slate: Option<OfflineSlate>,
+
+    #[serde(
+        default,
+        rename = "pollDelayMs",
+        deserialize_with = "serde::duration::from_millis_option"
+    )]
+    poll_delay: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct OfflineSlate {
+    #[serde(rename = "liveStreamOfflineSlateRenderer")]
+    renderer: OfflineSlateRenderer,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct OfflineSlateRenderer {
+    #[serde(
+        default,
+        rename = "scheduledStartTime",
+        deserialize_with = "serde::duration::from_secs_option"
+    )]
+    scheduled_start_time: Option<Duration>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
 /// YouTube get_video_info response.
 pub struct InfoResponse {
-    #[serde(rename = "streamingData")]
+    // Videos that cannot be played yet have no streaming data.
+    #[serde(default, rename = "streamingData")]
     streaming_data: StreamingData,
 
+    #[serde(default, rename = "playabilityStatus")]
+    playability_status: PlayabilityStatus,
+
     #[serde(rename = "videoDetails")]
     video_details: VideoDetails,
+
+    #[serde(default)]
+    microformat: Microformat,
+}
+
+impl TryFrom<&str> for InfoResponse {
+    type Error = serde_json::Error;
+
+    /// Attempt to parse the JSON of a YouTube player response into an
+    /// [InfoResponse].
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        serde_json::from_str(s)
+    }
 }
 
 impl InfoResponse {
+    /// Parse the URL-encoded body of a get_video_info response, as fetched
+    /// by [get_video_info].
+    pub fn from_video_info(body: &[u8]) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        let wrapper: InfoWrapper = serde_urlencoded::from_bytes(body)?;
+        Ok(Self::try_from(wrapper.player_response.as_str())?)
+    }
+
     /// `itag`-ordered vector of streaming formats available for the given
     /// video.
     pub fn formats(&self) -> Option<Vec<Format>> {
@@ -274,11 +715,49 @@ impl InfoResponse {
         sorted
     }
 
+    /// URL of the HLS [MasterPlaylist](hls::MasterPlaylist) of a video
+    /// that is live now, which it can be recorded from.
+    pub fn hls_manifest_url(&self) -> Option<String> {
+        self.streaming_data.hls_manifest_url.clone()
+    }
+
+    /// URL of the live DASH [Manifest](dash::Manifest) of a video that is
+    /// live now, whose representations are its adaptive formats by `itag`.
+    pub fn dash_manifest_url(&self) -> Option<String> {
+        self.streaming_data.dash_manifest_url.clone()
+    }
+
+    /// Whether the video can be played now and, if not, why or when.
+    pub fn playability_status(&self) -> PlayabilityStatus {
+        self.playability_status.clone()
+    }
+
     /// Details for the video.
     pub fn details(&self) -> VideoDetails {
         self.video_details.clone()
     }
 
+    /// A DASH [Manifest](dash::Manifest) offering the video's adaptive
+    /// formats, for playback in any DASH player. Write it out with
+    /// [Manifest::to_xml](dash::Manifest::to_xml).
+    ///
+    /// Only formats with a segment index are included, and the manifest is
+    /// only usable for as long as the format URLs remain valid.
+    pub fn to_dash_manifest(&self) -> dash::Manifest {
+        let formats = self.adaptive_formats();
+        let duration = formats
+            .iter()
+            .filter_map(|f| f.approx_duration)
+            .max()
+            .or(self.video_details.approx_length);
+        dash::Manifest::from_formats(&formats, duration)
+    }
+
+    /// Date the video was published, as `YYYY-MM-DD`.
+    pub fn publish_date(&self) -> Option<String> {
+        self.microformat.renderer.publish_date.clone()
+    }
+
     /// Vector of all formats available for the given video.
     /// Order is not guaranteed.
     pub fn all_formats(&self) -> Vec<Format> {
@@ -300,26 +779,70 @@ struct InfoWrapper {
     pub player_response: String,
 }
 
+#[cfg(feature = "client")]
 /// Acquires the [InfoResponse] for a given video ID.
 pub async fn get_video_info(id: &str) -> Result<InfoResponse, Box<dyn error::Error>> {
     let https = HttpsConnector::new();
     let client = Client::builder().build::<_, hyper::Body>(https);
 
     let mut res = client
-        .get(
-            format!("https://www.youtube.com/get_video_info?video_id={}", id)
-                .parse()
-                .unwrap(),
-        )
-        .await
-        .unwrap();
-    let body = body::to_bytes(res.body_mut()).await.unwrap();
-
-    let stream_info: InfoResponse =
-        serde_json::from_str(&serde_urlencoded::from_bytes::<InfoWrapper>(&body)?.player_response)?;
-    Ok(stream_info)
+        .get(format!("https://www.youtube.com/get_video_info?video_id={}", id).parse()?)
+        .await?;
+    if !res.status().is_success() {
+        return Err(format!("Video info request failed with {}", res.status()).into());
+    }
+    let body = body::to_bytes(res.body_mut()).await?;
+
+    InfoResponse::from_video_info(&body).map_err(|e| e as Box<dyn error::Error>)
 }
 
+#[cfg(feature = "client")]
+/// Acquires the [InfoResponse] for a given video ID once it can be played.
+/// Upcoming premieres and live streams are checked on periodically until
+/// they start; see [PlayabilityStatus::scheduled_start]. Failed checks are
+/// retried with exponential backoff, up to five times in a row.
+pub async fn wait_for_video(id: &str) -> Result<InfoResponse, Box<dyn error::Error>> {
+    let mut failures = 0;
+    loop {
+        let info = match get_video_info(id).await {
+            Ok(info) => info,
+            Err(e) if failures < 5 => {
+                let wait = Duration::from_secs(1 << failures);
+                warn!(
+                    "Failed to check on video {}: {}; retrying in {:?}",
+                    id, e, wait
+                );
+                failures += 1;
+                tokio::time::sleep(wait).await;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        failures = 0;
+        let status = info.playability_status();
+        if status.is_playable() {
+            return Ok(info);
+        }
+        if !status.is_upcoming() {
+            return Err(format!(
+                "Video {} is unplayable: {}",
+                id,
+                status.reason().unwrap_or_else(|| status.status())
+            )
+            .into());
+        }
+
+        let wait = status.next_poll(Utc::now());
+        match status.scheduled_start() {
+            Some(start) => info!("Video {} is scheduled to start at {}", id, start),
+            None => info!("Video {} has yet to start", id),
+        }
+        info!("Checking again in {:?}", wait);
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(feature = "client")]
 /// Acquires the [InfoResponses](InfoResponse) for a given set of video, playlist,
 /// or channel IDs.
 pub async fn videos_from(query: &query::Query) -> Result<Vec<InfoResponse>, Box<dyn error::Error>> {
@@ -330,10 +853,97 @@ pub async fn videos_from(query: &query::Query) -> Result<Vec<InfoResponse>, Box<
     for url in query.urls().await? {
         let mut res = client.get(url.parse().unwrap()).await.unwrap();
         let body = body::to_bytes(res.body_mut()).await.unwrap();
-        info.push(serde_json::from_str(
-            &serde_urlencoded::from_bytes::<InfoWrapper>(&body)?.player_response,
-        )?);
+        info.push(InfoResponse::from_video_info(&body).map_err(|e| e as Box<dyn error::Error>)?);
     }
 
     Ok(info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(json: &str) -> PlayabilityStatus {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn reads_playability_status() {
+        let playable = status(r#"{"status": "OK"}"#);
+        assert!(playable.is_playable());
+        assert!(!playable.is_upcoming());
+        assert_eq!(playable.scheduled_start(), None);
+
+        let unplayable = status(r#"{"status": "UNPLAYABLE", "reason": "Private video"}"#);
+        assert!(!unplayable.is_upcoming());
+        assert_eq!(unplayable.reason().as_deref(), Some("Private video"));
+
+        let offline = status(r#"{"status": "LIVE_STREAM_OFFLINE"}"#);
+        assert!(offline.is_upcoming());
+        assert_eq!(offline.scheduled_start(), None);
+
+        let scheduled = status(
+            r#"{
+                "status": "LIVE_STREAM_OFFLINE",
+                "reason": "Premieres in 2 hours",
+                "liveStreamability": {
+                    "liveStreamabilityRenderer": {
+                        "offlineSlate": {
+                            "liveStreamOfflineSlateRenderer": {
+                                "scheduledStartTime": "1620000000"
+                            }
+                        },
+                        "pollDelayMs": "30000"
+                    }
+                }
+            }"#,
+        );
+        assert!(scheduled.is_upcoming());
+        assert_eq!(
+            scheduled.scheduled_start(),
+            Some(
+                DateTime::parse_from_rfc3339("2021-05-03T00:00:00Z")
+                    .unwrap()
+                    .into()
+            )
+        );
+        assert_eq!(scheduled.poll_delay(), Some(Duration::from_secs(30)));
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn polls_until_start() {
+        let scheduled = status(
+            r#"{
+                "status": "LIVE_STREAM_OFFLINE",
+                "liveStreamability": {
+                    "liveStreamabilityRenderer": {
+                        "offlineSlate": {
+                            "liveStreamOfflineSlateRenderer": {
+                                "scheduledStartTime": "1620000000"
+                            }
+                        },
+                        "pollDelayMs": "30000"
+                    }
+                }
+            }"#,
+        );
+        let start = scheduled.scheduled_start().unwrap();
+
+        // Far from the start, checks are capped at ten minutes.
+        let now = start - chrono::Duration::hours(2);
+        assert_eq!(scheduled.next_poll(now), Duration::from_secs(600));
+        // Close to it, the wait runs until the start.
+        let now = start - chrono::Duration::seconds(90);
+        assert_eq!(scheduled.next_poll(now), Duration::from_secs(90));
+        // But never sooner than YouTube asks, even once it is overdue.
+        let now = start - chrono::Duration::seconds(10);
+        assert_eq!(scheduled.next_poll(now), Duration::from_secs(30));
+        let now = start + chrono::Duration::seconds(10);
+        assert_eq!(scheduled.next_poll(now), Duration::from_secs(30));
+
+        // Without a schedule or delay, the default delay is used.
+        let offline = status(r#"{"status": "LIVE_STREAM_OFFLINE"}"#);
+        assert_eq!(offline.next_poll(now), Duration::from_secs(15));
+    }
+}
@@ -0,0 +1,333 @@
+//! A minimal reader and writer for the Extensible Binary Meta Language
+//! (EBML), the binary format underlying Matroska and WebM.
+//!
+//! Elements are read lazily from byte slices; nothing is copied until a
+//! caller asks for it.
+
+use super::Error;
+
+/// Marker for an element whose size was written as "unknown", as is the
+/// case for live WebM streams.
+pub const UNKNOWN_SIZE: u64 = 0x00FF_FFFF_FFFF_FFFF;
+
+/// A single element read from an EBML document.
+#[derive(Debug, Clone, Copy)]
+pub struct Element<'a> {
+    /// Element ID, including its length marker bits.
+    pub id: u32,
+
+    /// Offset of the element's header relative to the slice it was read from.
+    pub offset: usize,
+
+    /// Length of the ID and size fields.
+    pub header_len: usize,
+
+    /// Whether the element's size was written as unknown.
+    pub unknown_size: bool,
+
+    /// Contents of the element.
+    pub body: &'a [u8],
+}
+
+impl<'a> Element<'a> {
+    /// Total length of the element, header included.
+    pub fn len(&self) -> usize {
+        self.header_len + self.body.len()
+    }
+
+    /// Whether the element has an empty body.
+    pub fn is_empty(&self) -> bool {
+        self.body.is_empty()
+    }
+
+    /// Iterator over the children of a master element.
+    pub fn children(&self) -> Reader<'a> {
+        Reader::new(self.body)
+    }
+
+    /// Interpret the body as a big-endian unsigned integer.
+    pub fn uint(&self) -> Result<u64, Error> {
+        if self.body.len() > 8 {
+            return Err(Error::Malformed("unsigned integer wider than 8 bytes"));
+        }
+        Ok(self.body.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b)))
+    }
+
+    /// Interpret the body as a big-endian signed integer.
+    pub fn int(&self) -> Result<i64, Error> {
+        if self.body.is_empty() {
+            return Ok(0);
+        }
+        let shift = 64 - 8 * self.body.len() as u32;
+        Ok(((self.uint()? << shift) as i64) >> shift)
+    }
+
+    /// Interpret the body as an IEEE 754 float.
+    pub fn float(&self) -> Result<f64, Error> {
+        match self.body.len() {
+            0 => Ok(0.0),
+            4 => {
+                let mut b = [0; 4];
+                b.copy_from_slice(self.body);
+                Ok(f64::from(f32::from_be_bytes(b)))
+            }
+            8 => {
+                let mut b = [0; 8];
+                b.copy_from_slice(self.body);
+                Ok(f64::from_be_bytes(b))
+            }
+            _ => Err(Error::Malformed("float must be 4 or 8 bytes")),
+        }
+    }
+
+    /// Interpret the body as a string, dropping any trailing null padding.
+    pub fn string(&self) -> Result<String, Error> {
+        let end = self
+            .body
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.body.len());
+        String::from_utf8(self.body[..end].to_vec())
+            .map_err(|_| Error::Malformed("string is not valid UTF-8"))
+    }
+}
+
+/// Sequential reader over the elements in a slice.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Find the first element with the given ID.
+    pub fn find(mut self, id: u32) -> Result<Option<Element<'a>>, Error> {
+        while let Some(el) = self.next_element()? {
+            if el.id == id {
+                return Ok(Some(el));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read the next element, or [None] at the end of the slice.
+    pub fn next_element(&mut self) -> Result<Option<Element<'a>>, Error> {
+        let rest = &self.buf[self.pos..];
+        if rest.is_empty() {
+            return Ok(None);
+        }
+
+        let (id, id_len) = read_id(rest)?;
+        let (size, size_len) = read_size(&rest[id_len..])?;
+        let header_len = id_len + size_len;
+
+        let unknown_size = size == UNKNOWN_SIZE;
+        let body_len = if unknown_size {
+            unknown_len(id, &rest[header_len..])?
+        } else {
+            // Truncated downloads are common enough that we clamp the final
+            // element rather than refusing the whole document.
+            (size as usize).min(rest.len() - header_len)
+        };
+
+        let el = Element {
+            id,
+            offset: self.pos,
+            header_len,
+            unknown_size,
+            body: &rest[header_len..header_len + body_len],
+        };
+        self.pos += header_len + body_len;
+        Ok(Some(el))
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<Element<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_element().transpose()
+    }
+}
+
+/// Top-level Matroska elements. An element of unknown size ends where one
+/// of these begins.
+const LEVEL_1: [u32; 8] = [
+    0x114D_9B74, // SeekHead
+    0x1549_A966, // Info
+    0x1654_AE6B, // Tracks
+    0x1F43_B675, // Cluster
+    0x1C53_BB6B, // Cues
+    0x1941_A469, // Attachments
+    0x1043_A770, // Chapters
+    0x1254_C367, // Tags
+];
+
+/// Length of an element of unknown size: everything up to the next
+/// top-level element, or the end of the buffer.
+fn unknown_len(id: u32, buf: &[u8]) -> Result<usize, Error> {
+    // Segments of unknown size extend to the end of the file.
+    if !LEVEL_1.contains(&id) {
+        return Ok(buf.len());
+    }
+
+    let mut pos = 0;
+    while pos < buf.len() {
+        let (child, id_len) = read_id(&buf[pos..])?;
+        if LEVEL_1.contains(&child) {
+            break;
+        }
+        let (size, size_len) = read_size(&buf[pos + id_len..])?;
+        if size == UNKNOWN_SIZE {
+            return Err(Error::Malformed("nested element of unknown size"));
+        }
+        pos = (pos + id_len + size_len + size as usize).min(buf.len());
+    }
+    Ok(pos)
+}
+
+/// Read a variable-length integer, returning its value with the length
+/// marker intact and its length in bytes.
+fn read_vint(buf: &[u8]) -> Result<(u64, usize), Error> {
+    let first = *buf.first().ok_or(Error::Malformed("unexpected end of data"))?;
+    if first == 0 {
+        return Err(Error::Malformed("variable-length integer wider than 8 bytes"));
+    }
+
+    let len = first.leading_zeros() as usize + 1;
+    if buf.len() < len {
+        return Err(Error::Malformed("unexpected end of data"));
+    }
+    Ok((
+        buf[..len].iter().fold(0, |acc, b| (acc << 8) | u64::from(*b)),
+        len,
+    ))
+}
+
+/// Read an element ID.
+pub fn read_id(buf: &[u8]) -> Result<(u32, usize), Error> {
+    let (id, len) = read_vint(buf)?;
+    if len > 4 {
+        return Err(Error::Malformed("element ID wider than 4 bytes"));
+    }
+    Ok((id as u32, len))
+}
+
+/// Read an element size or other variable-length integer, stripping its
+/// length marker. Sizes of all ones are normalized to [UNKNOWN_SIZE].
+pub fn read_size(buf: &[u8]) -> Result<(u64, usize), Error> {
+    let (raw, len) = read_vint(buf)?;
+    let mask = (1u64 << (7 * len)) - 1;
+    let value = raw & mask;
+    if value == mask {
+        return Ok((UNKNOWN_SIZE, len));
+    }
+    Ok((value, len))
+}
+
+/// Append an element ID.
+pub fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = (id.leading_zeros() / 8) as usize;
+    out.extend_from_slice(&bytes[skip.min(3)..]);
+}
+
+/// Append a size using the shortest encoding that fits.
+pub fn write_size(out: &mut Vec<u8>, size: u64) {
+    let mut len = 1;
+    // All ones is reserved for unknown sizes, hence the `- 1`.
+    while len < 8 && size >= (1u64 << (7 * len)) - 1 {
+        len += 1;
+    }
+    write_size_exact(out, size, len);
+}
+
+/// Append a size using exactly `len` bytes.
+pub fn write_size_exact(out: &mut Vec<u8>, size: u64, len: usize) {
+    let marked = size | (1u64 << (7 * len));
+    out.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+/// Append a master or binary element with the given body.
+pub fn write_element(out: &mut Vec<u8>, id: u32, body: &[u8]) {
+    write_id(out, id);
+    write_size(out, body.len() as u64);
+    out.extend_from_slice(body);
+}
+
+/// Append an unsigned integer element using the fewest bytes possible.
+pub fn write_uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = ((value.leading_zeros() / 8) as usize).min(7);
+    write_element(out, id, &bytes[skip..]);
+}
+
+/// Append an unsigned integer element that always occupies eight bytes,
+/// so that its length is known before its value.
+pub fn write_uint_fixed(out: &mut Vec<u8>, id: u32, value: u64) {
+    write_element(out, id, &value.to_be_bytes());
+}
+
+/// Append a signed integer element.
+pub fn write_int(out: &mut Vec<u8>, id: u32, value: i64) {
+    write_element(out, id, &value.to_be_bytes());
+}
+
+/// Append a double-precision float element.
+pub fn write_float(out: &mut Vec<u8>, id: u32, value: f64) {
+    write_element(out, id, &value.to_be_bytes());
+}
+
+/// Append a string element.
+pub fn write_string(out: &mut Vec<u8>, id: u32, value: &str) {
+    write_element(out, id, value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut child = Vec::new();
+        write_uint(&mut child, 0xD7, 300);
+        write_string(&mut child, 0x86, "V_VP9");
+        write_float(&mut child, 0x4489, 1234.5);
+        write_int(&mut child, 0xFB, -20);
+
+        let mut doc = Vec::new();
+        write_element(&mut doc, 0xAE, &child);
+
+        let entry = Reader::new(&doc).find(0xAE).unwrap().unwrap();
+        let mut children = entry.children();
+        assert_eq!(children.next_element().unwrap().unwrap().uint().unwrap(), 300);
+        assert_eq!(
+            children.next_element().unwrap().unwrap().string().unwrap(),
+            "V_VP9"
+        );
+        assert_eq!(
+            children.next_element().unwrap().unwrap().float().unwrap(),
+            1234.5
+        );
+        assert_eq!(children.next_element().unwrap().unwrap().int().unwrap(), -20);
+        assert!(children.next_element().unwrap().is_none());
+    }
+
+    #[test]
+    fn sizes() {
+        for size in [0, 126, 127, 16_382, 16_383, 1 << 40] {
+            let mut out = Vec::new();
+            write_size(&mut out, size);
+            assert_eq!(read_size(&out).unwrap(), (size, out.len()));
+        }
+        assert_eq!(read_size(&[0xFF]).unwrap(), (UNKNOWN_SIZE, 1));
+        assert_eq!(
+            read_size(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).unwrap(),
+            (UNKNOWN_SIZE, 8)
+        );
+    }
+}
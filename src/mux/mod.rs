@@ -0,0 +1,190 @@
+//! Combining separately-downloaded streams into a single file.
+//!
+//! YouTube serves its highest qualities as adaptive [Formats](Format) that
+//! carry either video or audio, but not both. This module joins a pair of
+//! them back together without re-encoding, choosing a container from the
+//! formats' MIME types.
+//!
+//! It also rewrites single downloads, such as the transport streams of HLS,
+//! into forms that other software handles better; see [remux].
+
+#[cfg(feature = "client")]
+use std::io::SeekFrom;
+use std::{
+    error,
+    fmt::{self, Display},
+};
+#[cfg(feature = "client")]
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+#[cfg(feature = "client")]
+use hyper::{body, Client};
+#[cfg(feature = "client")]
+use hyper_tls::HttpsConnector;
+
+use crate::Format;
+#[cfg(feature = "client")]
+use crate::InfoResponse;
+
+pub mod ebml;
+pub mod mp4;
+pub mod ogg;
+pub mod tags;
+pub mod ts;
+pub mod webm;
+
+pub use tags::Metadata;
+pub use webm::DocType;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Failure to read or write a container.
+pub enum Error {
+    /// The input is not a well-formed file of the expected type.
+    Malformed(&'static str),
+
+    /// The input is well-formed, but cannot be handled.
+    Unsupported(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed(why) => write!(f, "Malformed input: {}", why),
+            Error::Unsupported(why) => write!(f, "Unsupported input: {}", why),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Merge the downloaded contents of a video [Format] and an audio [Format]
+/// into a single file.
+///
+/// WebM inputs are written as WebM; use [webm::merge] directly to write
+/// Matroska instead.
+pub fn merge(
+    video: &Format,
+    video_data: &[u8],
+    audio: &Format,
+    audio_data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    if is_webm(video) && is_webm(audio) {
+        return webm::merge(&[video_data, audio_data], DocType::WebM);
+    }
+
+    Err(Error::Unsupported(format!(
+        "cannot merge {} with {}",
+        video.mime_type().essence_str(),
+        audio.mime_type().essence_str()
+    )))
+}
+
+#[cfg(feature = "client")]
+/// Downloads a video [Format] and an audio [Format] concurrently, and
+/// writes them merged into a `File`.
+pub async fn download(
+    video: &Format,
+    audio: &Format,
+    dest: &mut File,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let (video_data, audio_data) = tokio::try_join!(video.to_vec(), audio.to_vec())?;
+    let merged = merge(video, &video_data, audio, &audio_data)?;
+    dest.write_all(&merged).await?;
+    Ok(())
+}
+
+#[cfg(feature = "client")]
+/// Rewrites a downloaded `File` in place into a form most players and
+/// editors can handle.
+///
+/// Fragmented MP4, as served for adaptive formats, becomes a progressive MP4
+/// with its `moov` at the front, and audio-only MP4 is branded as M4A.
+/// MPEG-TS, as served by most HLS streams, is remuxed into MP4 with
+/// [ts::to_mp4]. Other files are left untouched. The `File` must be open for
+/// reading and writing.
+pub async fn remux(file: &mut File) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    rewrite(file, |data| {
+        if ts::is_ts(data) {
+            return ts::to_mp4(data).map(Some);
+        }
+        // Only MP4 files begin with a box header.
+        if data.get(4..8) != Some(b"ftyp") {
+            return Ok(None);
+        }
+        mp4::faststart(data).map(Some)
+    })
+    .await
+}
+
+#[cfg(feature = "client")]
+/// Embeds the details of a video, along with its largest thumbnail as
+/// cover art, into a downloaded `File` in place. See [tags::write].
+///
+/// WebM files are left without cover art, as WebM does not allow
+/// attachments.
+///
+/// The `File` must be open for reading and writing.
+pub async fn tag(
+    file: &mut File,
+    info: &InfoResponse,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let mut metadata = Metadata::from(info);
+    metadata.cover = cover(info).await?;
+    rewrite(file, |data| tags::write(data, &metadata).map(Some)).await
+}
+
+#[cfg(feature = "client")]
+/// Downloads the largest thumbnail of a video, preferring JPEG since not
+/// every container accepts WebP artwork.
+async fn cover(
+    info: &InfoResponse,
+) -> Result<Option<tags::Cover>, Box<dyn error::Error + Send + Sync>> {
+    let thumbnails = info.details().thumbnails();
+    let best = match thumbnails
+        .iter()
+        .rev()
+        .find(|t| t.url().contains(".jpg"))
+        .or_else(|| thumbnails.last())
+    {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+
+    let https = HttpsConnector::new();
+    let client = Client::builder().build::<_, hyper::Body>(https);
+    let mut res = client.get(best.url().parse()?).await?;
+    let data = body::to_bytes(res.body_mut()).await?.to_vec();
+
+    Ok(tags::ImageType::sniff(&data).map(|kind| tags::Cover {
+        kind,
+        width: best.width(),
+        height: best.height(),
+        data,
+    }))
+}
+
+#[cfg(feature = "client")]
+/// Replace the contents of `file` with the output of `f`, unless it
+/// returns [None].
+async fn rewrite<F>(file: &mut File, f: F) -> Result<(), Box<dyn error::Error + Send + Sync>>
+where
+    F: FnOnce(&[u8]) -> Result<Option<Vec<u8>>, Error>,
+{
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(0)).await?;
+    file.read_to_end(&mut data).await?;
+
+    if let Some(out) = f(&data)? {
+        file.seek(SeekFrom::Start(0)).await?;
+        file.set_len(0).await?;
+        file.write_all(&out).await?;
+    }
+    Ok(())
+}
+
+fn is_webm(format: &Format) -> bool {
+    format.mime_type().subtype() == "webm"
+}
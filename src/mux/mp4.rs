@@ -0,0 +1,1371 @@
+//! Reading and writing ISO base media (MP4) files.
+//!
+//! YouTube's adaptive MP4 formats are fragmented: a `moov` describing the
+//! tracks is followed by a series of `moof`/`mdat` pairs, each holding a few
+//! seconds of media. [faststart] rewrites these as a single progressive
+//! `mdat` indexed by a `moov` at the front of the file, which is what most
+//! players and editors expect.
+
+use std::{borrow::Cow, convert::TryFrom};
+
+use super::{
+    tags::{ImageType, Metadata},
+    Error,
+};
+
+/// A single box read from an MP4 file.
+#[derive(Debug, Clone, Copy)]
+pub struct Atom<'a> {
+    /// Four-character type of the box.
+    pub kind: [u8; 4],
+
+    /// Offset of the box's header relative to the slice it was read from.
+    pub offset: usize,
+
+    /// Length of the size and type fields.
+    pub header_len: usize,
+
+    /// Contents of the box.
+    pub body: &'a [u8],
+}
+
+impl<'a> Atom<'a> {
+    /// Total length of the box, header included.
+    pub fn len(&self) -> usize {
+        self.header_len + self.body.len()
+    }
+
+    /// Whether the box has an empty body.
+    pub fn is_empty(&self) -> bool {
+        self.body.is_empty()
+    }
+
+    /// Iterator over the boxes nested in this one.
+    pub fn children(&self) -> Atoms<'a> {
+        Atoms::new(self.body)
+    }
+
+    /// Version and flags of a full box, along with the rest of its body.
+    pub fn full(&self) -> Result<(u8, u32, &'a [u8]), Error> {
+        if self.body.len() < 4 {
+            return Err(Error::Malformed("truncated full box"));
+        }
+        let flags = u32::from_be_bytes([0, self.body[1], self.body[2], self.body[3]]);
+        Ok((self.body[0], flags, &self.body[4..]))
+    }
+}
+
+/// Sequential reader over the boxes in a slice.
+#[derive(Debug, Clone)]
+pub struct Atoms<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Atoms<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Find the first box of the given type.
+    pub fn find(mut self, kind: &[u8; 4]) -> Result<Option<Atom<'a>>, Error> {
+        while let Some(atom) = self.next_atom()? {
+            if &atom.kind == kind {
+                return Ok(Some(atom));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read the next box, or [None] at the end of the slice.
+    pub fn next_atom(&mut self) -> Result<Option<Atom<'a>>, Error> {
+        let rest = &self.buf[self.pos..];
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        if rest.len() < 8 {
+            return Err(Error::Malformed("truncated box header"));
+        }
+
+        let mut kind = [0; 4];
+        kind.copy_from_slice(&rest[4..8]);
+        let (size, header_len) = match u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) {
+            // A box of size zero extends to the end of the file.
+            0 => (rest.len() as u64, 8),
+            1 => {
+                if rest.len() < 16 {
+                    return Err(Error::Malformed("truncated box header"));
+                }
+                let mut large = [0; 8];
+                large.copy_from_slice(&rest[8..16]);
+                (u64::from_be_bytes(large), 16)
+            }
+            s => (u64::from(s), 8),
+        };
+        if size < header_len as u64 {
+            return Err(Error::Malformed("box smaller than its header"));
+        }
+
+        // Truncated downloads are clamped rather than refused outright.
+        let len = (size as usize).min(rest.len());
+        let atom = Atom {
+            kind,
+            offset: self.pos,
+            header_len,
+            body: &rest[header_len..len],
+        };
+        self.pos += len;
+        Ok(Some(atom))
+    }
+}
+
+impl<'a> Iterator for Atoms<'a> {
+    type Item = Result<Atom<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_atom().transpose()
+    }
+}
+
+/// Append a box with the given body.
+pub fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+    let size = body.len() as u64 + 8;
+    if size > u64::from(u32::MAX) {
+        out.extend_from_slice(&1u32.to_be_bytes());
+        out.extend_from_slice(kind);
+        out.extend_from_slice(&(size + 8).to_be_bytes());
+    } else {
+        out.extend_from_slice(&(size as u32).to_be_bytes());
+        out.extend_from_slice(kind);
+    }
+    out.extend_from_slice(body);
+}
+
+/// Append a full box with the given version, flags and body.
+pub fn write_full_box(out: &mut Vec<u8>, kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) {
+    let mut full = Vec::with_capacity(body.len() + 4);
+    full.push(version);
+    full.extend_from_slice(&flags.to_be_bytes()[1..]);
+    full.extend_from_slice(body);
+    write_box(out, kind, &full);
+}
+
+/// Big-endian cursor over a box body.
+pub(crate) struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn skip(&mut self, n: usize) -> Result<(), Error> {
+        self.bytes(n).map(|_| ())
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() - self.pos < n {
+            return Err(Error::Malformed("truncated box"));
+        }
+        self.pos += n;
+        Ok(&self.buf[self.pos - n..self.pos])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok((u64::from(self.u32()?) << 32) | u64::from(self.u32()?))
+    }
+
+    /// A 32-bit field in version 0 boxes, and a 64-bit one otherwise.
+    pub fn versioned(&mut self, version: u8) -> Result<u64, Error> {
+        match version {
+            0 => Ok(u64::from(self.u32()?)),
+            _ => self.u64(),
+        }
+    }
+}
+
+/// The kind of media a [Track] carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Handler {
+    Video { width: u32, height: u32 },
+    Audio,
+}
+
+/// A sample within a [Track].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sample {
+    pub size: u32,
+    pub duration: u32,
+    pub composition_offset: i32,
+    pub sync: bool,
+}
+
+/// A contiguous run of samples stored together in the `mdat`.
+#[derive(Debug, Clone)]
+pub(crate) struct Chunk<'a> {
+    pub samples: usize,
+    pub data: Cow<'a, [u8]>,
+}
+
+/// A track to be written by [Movie].
+#[derive(Debug, Clone)]
+pub(crate) struct Track<'a> {
+    pub id: u32,
+    pub handler: Handler,
+    pub timescale: u32,
+
+    /// Packed ISO 639-2/T language code, as stored in `mdhd`.
+    pub language: u16,
+
+    /// The complete `stsd` box describing the track's codec.
+    pub stsd: Vec<u8>,
+
+    pub samples: Vec<Sample>,
+    pub chunks: Vec<Chunk<'a>>,
+
+    /// Time in the track's timescale before its first sample is presented,
+    /// written as an empty edit.
+    pub delay: u64,
+
+    /// Composition time of the first sample to present, written as an
+    /// edit. Samples composed earlier are decoded but not shown.
+    pub media_start: u64,
+}
+
+impl<'a> Track<'a> {
+    /// Duration of the track in its own timescale.
+    fn duration(&self) -> u64 {
+        self.samples.iter().map(|s| u64::from(s.duration)).sum()
+    }
+
+    /// Duration of the track in movie ticks, after its edits.
+    fn presented(&self) -> u64 {
+        movie_time(
+            self.delay + self.duration().saturating_sub(self.media_start),
+            self.timescale,
+        )
+    }
+}
+
+/// Timescale of the movie header, in ticks per second.
+const MOVIE_TIMESCALE: u32 = 1000;
+
+const IDENTITY: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// A progressive MP4 file, written with its `moov` first.
+#[derive(Debug, Clone)]
+pub(crate) struct Movie<'a> {
+    pub tracks: Vec<Track<'a>>,
+
+    /// Extra boxes, such as `udta`, to append to the `moov`.
+    pub extra: Vec<u8>,
+}
+
+impl<'a> Movie<'a> {
+    /// Whether the movie holds nothing but audio.
+    fn audio_only(&self) -> bool {
+        self.tracks.iter().all(|t| t.handler == Handler::Audio)
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut ftyp = Vec::new();
+        let brands: &[&[u8; 4]] = if self.audio_only() {
+            &[b"M4A ", b"M4A ", b"mp42", b"isom"]
+        } else {
+            &[b"isom", b"isom", b"iso2", b"mp41"]
+        };
+        ftyp.extend_from_slice(brands[0]);
+        ftyp.extend_from_slice(&0u32.to_be_bytes());
+        for brand in &brands[1..] {
+            ftyp.extend_from_slice(*brand);
+        }
+        let mut out = Vec::new();
+        write_box(&mut out, b"ftyp", &ftyp);
+
+        // Chunks are laid out in order of their start time, interleaving
+        // the tracks.
+        let mut order = Vec::new();
+        for (t, track) in self.tracks.iter().enumerate() {
+            let mut time = 0;
+            let mut sample = 0;
+            for (c, chunk) in track.chunks.iter().enumerate() {
+                order.push((time as f64 / f64::from(track.timescale), t, c));
+                for s in &track.samples[sample..sample + chunk.samples] {
+                    time += u64::from(s.duration);
+                }
+                sample += chunk.samples;
+            }
+        }
+        order.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mdat_len: u64 = self
+            .tracks
+            .iter()
+            .flat_map(|t| t.chunks.iter())
+            .map(|c| c.data.len() as u64)
+            .sum();
+        let mdat_header = if mdat_len + 8 > u64::from(u32::MAX) { 16 } else { 8 };
+
+        // The length of the moov does not depend on the offsets it holds,
+        // so it is measured with placeholders first. 64-bit offsets are
+        // only used when the media ends past 4 GiB.
+        let mut offsets: Vec<Vec<u64>> = self
+            .tracks
+            .iter()
+            .map(|t| vec![0; t.chunks.len()])
+            .collect();
+        let mut wide = false;
+        let mut moov_len = self.moov(&offsets, wide).len() as u64;
+        if out.len() as u64 + moov_len + mdat_header + mdat_len > u64::from(u32::MAX) {
+            wide = true;
+            moov_len = self.moov(&offsets, wide).len() as u64;
+        }
+
+        let mut pos = out.len() as u64 + moov_len + mdat_header;
+        for (_, t, c) in &order {
+            offsets[*t][*c] = pos;
+            pos += self.tracks[*t].chunks[*c].data.len() as u64;
+        }
+        out.extend_from_slice(&self.moov(&offsets, wide));
+
+        if mdat_header == 16 {
+            out.extend_from_slice(&1u32.to_be_bytes());
+            out.extend_from_slice(b"mdat");
+            out.extend_from_slice(&(mdat_len + 16).to_be_bytes());
+        } else {
+            out.extend_from_slice(&((mdat_len + 8) as u32).to_be_bytes());
+            out.extend_from_slice(b"mdat");
+        }
+        for (_, t, c) in &order {
+            out.extend_from_slice(&self.tracks[*t].chunks[*c].data);
+        }
+        out
+    }
+
+    fn moov(&self, offsets: &[Vec<u64>], wide: bool) -> Vec<u8> {
+        let duration = self.tracks.iter().map(Track::presented).max().unwrap_or(0);
+        let next_id = self.tracks.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+
+        let mut mvhd = Vec::new();
+        mvhd.extend_from_slice(&[0; 16]);
+        mvhd.extend_from_slice(&MOVIE_TIMESCALE.to_be_bytes());
+        mvhd.extend_from_slice(&duration.to_be_bytes());
+        mvhd.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+        mvhd.extend_from_slice(&0x0100u16.to_be_bytes());
+        mvhd.extend_from_slice(&[0; 10]);
+        for m in &IDENTITY {
+            mvhd.extend_from_slice(&m.to_be_bytes());
+        }
+        mvhd.extend_from_slice(&[0; 24]);
+        mvhd.extend_from_slice(&next_id.to_be_bytes());
+
+        let mut body = Vec::new();
+        write_full_box(&mut body, b"mvhd", 1, 0, &mvhd);
+        for (track, offsets) in self.tracks.iter().zip(offsets) {
+            body.extend_from_slice(&trak(track, offsets, wide));
+        }
+        body.extend_from_slice(&self.extra);
+
+        let mut out = Vec::new();
+        write_box(&mut out, b"moov", &body);
+        out
+    }
+}
+
+/// Convert a duration in `timescale` ticks to movie ticks.
+fn movie_time(duration: u64, timescale: u32) -> u64 {
+    duration * u64::from(MOVIE_TIMESCALE) / u64::from(timescale.max(1))
+}
+
+fn trak(track: &Track, offsets: &[u64], wide: bool) -> Vec<u8> {
+    let (width, height, volume) = match track.handler {
+        Handler::Video { width, height } => (width, height, 0u16),
+        Handler::Audio => (0, 0, 0x0100),
+    };
+
+    let mut tkhd = Vec::new();
+    tkhd.extend_from_slice(&[0; 16]);
+    tkhd.extend_from_slice(&track.id.to_be_bytes());
+    tkhd.extend_from_slice(&[0; 4]);
+    tkhd.extend_from_slice(&track.presented().to_be_bytes());
+    tkhd.extend_from_slice(&[0; 12]);
+    tkhd.extend_from_slice(&volume.to_be_bytes());
+    tkhd.extend_from_slice(&[0; 2]);
+    for m in &IDENTITY {
+        tkhd.extend_from_slice(&m.to_be_bytes());
+    }
+    tkhd.extend_from_slice(&(width << 16).to_be_bytes());
+    tkhd.extend_from_slice(&(height << 16).to_be_bytes());
+
+    let mut mdhd = Vec::new();
+    mdhd.extend_from_slice(&[0; 16]);
+    mdhd.extend_from_slice(&track.timescale.to_be_bytes());
+    mdhd.extend_from_slice(&track.duration().to_be_bytes());
+    mdhd.extend_from_slice(&track.language.to_be_bytes());
+    mdhd.extend_from_slice(&[0; 2]);
+
+    let (handler, name, header): (&[u8; 4], &str, Vec<u8>) = match track.handler {
+        Handler::Video { .. } => {
+            let mut vmhd = Vec::new();
+            write_full_box(&mut vmhd, b"vmhd", 0, 1, &[0; 8]);
+            (b"vide", "VideoHandler", vmhd)
+        }
+        Handler::Audio => {
+            let mut smhd = Vec::new();
+            write_full_box(&mut smhd, b"smhd", 0, 0, &[0; 4]);
+            (b"soun", "SoundHandler", smhd)
+        }
+    };
+    let mut hdlr = vec![0; 4];
+    hdlr.extend_from_slice(handler);
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(name.as_bytes());
+    hdlr.push(0);
+
+    let mut url = Vec::new();
+    write_full_box(&mut url, b"url ", 0, 1, &[]);
+    let mut dref = 1u32.to_be_bytes().to_vec();
+    dref.extend_from_slice(&url);
+    let mut dref_box = Vec::new();
+    write_full_box(&mut dref_box, b"dref", 0, 0, &dref);
+
+    let mut minf = header;
+    write_box(&mut minf, b"dinf", &dref_box);
+    write_box(&mut minf, b"stbl", &stbl(track, offsets, wide));
+
+    let mut mdia = Vec::new();
+    write_full_box(&mut mdia, b"mdhd", 1, 0, &mdhd);
+    write_full_box(&mut mdia, b"hdlr", 0, 0, &hdlr);
+    write_box(&mut mdia, b"minf", &minf);
+
+    let mut body = Vec::new();
+    write_full_box(&mut body, b"tkhd", 1, 3, &tkhd);
+    if track.delay > 0 || track.media_start > 0 {
+        write_box(&mut body, b"edts", &elst(track));
+    }
+    write_box(&mut body, b"mdia", &mdia);
+
+    let mut out = Vec::new();
+    write_box(&mut out, b"trak", &body);
+    out
+}
+
+/// An `elst` delaying a track by its `delay`, then presenting it from its
+/// `media_start`.
+fn elst(track: &Track) -> Vec<u8> {
+    let mut entries: Vec<(u64, i64)> = Vec::new();
+    if track.delay > 0 {
+        entries.push((movie_time(track.delay, track.timescale), -1));
+    }
+    entries.push((
+        movie_time(
+            track.duration().saturating_sub(track.media_start),
+            track.timescale,
+        ),
+        track.media_start as i64,
+    ));
+
+    let mut body = (entries.len() as u32).to_be_bytes().to_vec();
+    for (duration, media_time) in entries {
+        body.extend_from_slice(&duration.to_be_bytes());
+        body.extend_from_slice(&media_time.to_be_bytes());
+        body.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+    }
+    let mut out = Vec::new();
+    write_full_box(&mut out, b"elst", 1, 0, &body);
+    out
+}
+
+/// Run-length encode `items`, returning (count, value) pairs.
+fn runs<T: PartialEq + Copy>(items: impl Iterator<Item = T>) -> Vec<(u32, T)> {
+    let mut out: Vec<(u32, T)> = Vec::new();
+    for item in items {
+        match out.last_mut() {
+            Some((count, value)) if *value == item => *count += 1,
+            _ => out.push((1, item)),
+        }
+    }
+    out
+}
+
+fn table(out: &mut Vec<u8>, kind: &[u8; 4], version: u8, entries: &[Vec<u32>]) {
+    let mut body = (entries.len() as u32).to_be_bytes().to_vec();
+    for entry in entries {
+        for field in entry {
+            body.extend_from_slice(&field.to_be_bytes());
+        }
+    }
+    write_full_box(out, kind, version, 0, &body);
+}
+
+/// The body of a track's `stbl`.
+fn stbl(track: &Track, offsets: &[u64], wide: bool) -> Vec<u8> {
+    let mut out = track.stsd.clone();
+
+    let stts: Vec<_> = runs(track.samples.iter().map(|s| s.duration))
+        .into_iter()
+        .map(|(n, d)| vec![n, d])
+        .collect();
+    table(&mut out, b"stts", 0, &stts);
+
+    if track.samples.iter().any(|s| s.composition_offset != 0) {
+        let negative = track.samples.iter().any(|s| s.composition_offset < 0);
+        let ctts: Vec<_> = runs(track.samples.iter().map(|s| s.composition_offset))
+            .into_iter()
+            .map(|(n, o)| vec![n, o as u32])
+            .collect();
+        table(&mut out, b"ctts", if negative { 1 } else { 0 }, &ctts);
+    }
+
+    if track.samples.iter().any(|s| !s.sync) {
+        let stss: Vec<_> = track
+            .samples
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.sync)
+            .map(|(i, _)| vec![i as u32 + 1])
+            .collect();
+        table(&mut out, b"stss", 0, &stss);
+    }
+
+    let mut stsc = Vec::new();
+    let mut previous = None;
+    for (i, chunk) in track.chunks.iter().enumerate() {
+        if previous != Some(chunk.samples) {
+            stsc.push(vec![i as u32 + 1, chunk.samples as u32, 1]);
+            previous = Some(chunk.samples);
+        }
+    }
+    table(&mut out, b"stsc", 0, &stsc);
+
+    let mut stsz = Vec::new();
+    let constant = match track.samples.first() {
+        Some(first) if track.samples.iter().all(|s| s.size == first.size) => first.size,
+        _ => 0,
+    };
+    stsz.extend_from_slice(&constant.to_be_bytes());
+    stsz.extend_from_slice(&(track.samples.len() as u32).to_be_bytes());
+    if constant == 0 {
+        for s in &track.samples {
+            stsz.extend_from_slice(&s.size.to_be_bytes());
+        }
+    }
+    write_full_box(&mut out, b"stsz", 0, 0, &stsz);
+
+    let mut chunks = (offsets.len() as u32).to_be_bytes().to_vec();
+    for offset in offsets {
+        if wide {
+            chunks.extend_from_slice(&offset.to_be_bytes());
+        } else {
+            chunks.extend_from_slice(&(*offset as u32).to_be_bytes());
+        }
+    }
+    write_full_box(&mut out, if wide { b"co64" } else { b"stco" }, 0, 0, &chunks);
+    out
+}
+
+/// Per-track defaults from a `trex` box.
+#[derive(Debug, Clone, Copy, Default)]
+struct Defaults {
+    duration: u32,
+    size: u32,
+    flags: u32,
+}
+
+/// Whether a set of sample flags marks a sync sample.
+fn is_sync(flags: u32) -> bool {
+    flags & 0x0001_0000 == 0
+}
+
+/// Read the tracks declared by a `moov`, without any samples.
+fn read_tracks<'a>(moov: &Atom) -> Result<Vec<(Track<'a>, Defaults)>, Error> {
+    let mut tracks = Vec::new();
+    let mut defaults = Vec::new();
+
+    for atom in moov.children() {
+        let atom = atom?;
+        match &atom.kind {
+            b"trak" => tracks.push(read_trak(&atom)?),
+            b"mvex" => {
+                for trex in atom.children() {
+                    let trex = trex?;
+                    if &trex.kind != b"trex" {
+                        continue;
+                    }
+                    let (_, _, body) = trex.full()?;
+                    let mut c = Cursor::new(body);
+                    let id = c.u32()?;
+                    c.skip(4)?;
+                    defaults.push((
+                        id,
+                        Defaults {
+                            duration: c.u32()?,
+                            size: c.u32()?,
+                            flags: c.u32()?,
+                        },
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(tracks
+        .into_iter()
+        .map(|t| {
+            let d = defaults
+                .iter()
+                .find(|(id, _)| *id == t.id)
+                .map(|(_, d)| *d)
+                .unwrap_or_default();
+            (t, d)
+        })
+        .collect())
+}
+
+fn read_trak<'a>(trak: &Atom) -> Result<Track<'a>, Error> {
+    let tkhd = trak
+        .children()
+        .find(b"tkhd")?
+        .ok_or(Error::Malformed("trak without a tkhd"))?;
+    let (version, _, body) = tkhd.full()?;
+    let mut c = Cursor::new(body);
+    c.skip(if version == 0 { 8 } else { 16 })?;
+    let id = c.u32()?;
+    c.skip(4)?;
+    c.versioned(version)?;
+    c.skip(52)?;
+    let width = c.u32()? >> 16;
+    let height = c.u32()? >> 16;
+
+    let mdia = trak
+        .children()
+        .find(b"mdia")?
+        .ok_or(Error::Malformed("trak without an mdia"))?;
+    let mdhd = mdia
+        .children()
+        .find(b"mdhd")?
+        .ok_or(Error::Malformed("mdia without an mdhd"))?;
+    let (version, _, body) = mdhd.full()?;
+    let mut c = Cursor::new(body);
+    c.skip(if version == 0 { 8 } else { 16 })?;
+    let timescale = c.u32()?;
+    c.versioned(version)?;
+    let language = c.u16()?;
+
+    let hdlr = mdia
+        .children()
+        .find(b"hdlr")?
+        .ok_or(Error::Malformed("mdia without an hdlr"))?;
+    let (_, _, body) = hdlr.full()?;
+    let handler = match body.get(4..8) {
+        Some(b"vide") => Handler::Video { width, height },
+        Some(b"soun") => Handler::Audio,
+        _ => return Err(Error::Unsupported("tracks other than audio and video".into())),
+    };
+
+    let stsd = mdia
+        .children()
+        .find(b"minf")?
+        .ok_or(Error::Malformed("mdia without a minf"))?
+        .children()
+        .find(b"stbl")?
+        .ok_or(Error::Malformed("minf without an stbl"))?
+        .children()
+        .find(b"stsd")?
+        .ok_or(Error::Malformed("stbl without an stsd"))?;
+
+    Ok(Track {
+        id,
+        handler,
+        timescale,
+        language,
+        stsd: raw_box(&stsd),
+        samples: Vec::new(),
+        chunks: Vec::new(),
+        delay: 0,
+        media_start: 0,
+    })
+}
+
+fn raw_box(atom: &Atom) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_box(&mut out, &atom.kind, atom.body);
+    out
+}
+
+/// Append the samples described by a `moof` to their tracks.
+fn read_moof<'a>(
+    buf: &'a [u8],
+    moof: &Atom,
+    tracks: &mut [(Track<'a>, Defaults)],
+) -> Result<(), Error> {
+    let moof_start = moof.offset as u64;
+
+    for traf in moof.children() {
+        let traf = traf?;
+        if &traf.kind != b"traf" {
+            continue;
+        }
+
+        let tfhd = traf
+            .children()
+            .find(b"tfhd")?
+            .ok_or(Error::Malformed("traf without a tfhd"))?;
+        let (_, flags, body) = tfhd.full()?;
+        let mut c = Cursor::new(body);
+        let id = c.u32()?;
+        let (track, trex) = tracks
+            .iter_mut()
+            .find(|(t, _)| t.id == id)
+            .ok_or(Error::Malformed("fragment for an undeclared track"))?;
+
+        let mut defaults = *trex;
+        let mut base = moof_start;
+        if flags & 0x01 != 0 {
+            base = c.u64()?;
+        }
+        if flags & 0x02 != 0 {
+            c.skip(4)?;
+        }
+        if flags & 0x08 != 0 {
+            defaults.duration = c.u32()?;
+        }
+        if flags & 0x10 != 0 {
+            defaults.size = c.u32()?;
+        }
+        if flags & 0x20 != 0 {
+            defaults.flags = c.u32()?;
+        }
+
+        // Without an explicit offset, each run follows the previous one.
+        let mut next = base;
+        for trun in traf.children() {
+            let trun = trun?;
+            if &trun.kind != b"trun" {
+                continue;
+            }
+            let (_, flags, body) = trun.full()?;
+            let mut c = Cursor::new(body);
+            let count = c.u32()? as usize;
+            let mut start = next;
+            if flags & 0x01 != 0 {
+                start = base
+                    .checked_add_signed(i64::from(c.u32()? as i32))
+                    .ok_or(Error::Malformed("sample data offset out of range"))?;
+            }
+            let first_flags = if flags & 0x04 != 0 {
+                Some(c.u32()?)
+            } else {
+                None
+            };
+
+            let mut len = 0u64;
+            for i in 0..count {
+                let duration = if flags & 0x100 != 0 {
+                    c.u32()?
+                } else {
+                    defaults.duration
+                };
+                let size = if flags & 0x200 != 0 {
+                    c.u32()?
+                } else {
+                    defaults.size
+                };
+                let sample_flags = if flags & 0x400 != 0 {
+                    c.u32()?
+                } else if i == 0 {
+                    first_flags.unwrap_or(defaults.flags)
+                } else {
+                    defaults.flags
+                };
+                // Version 0 offsets are unsigned, but never large enough in
+                // practice for the distinction to matter.
+                let composition_offset = if flags & 0x800 != 0 {
+                    c.u32()? as i32
+                } else {
+                    0
+                };
+                track.samples.push(Sample {
+                    size,
+                    duration,
+                    composition_offset,
+                    sync: is_sync(sample_flags),
+                });
+                len += u64::from(size);
+            }
+
+            let end = start
+                .checked_add(len)
+                .ok_or(Error::Malformed("sample data offset out of range"))?;
+            if end > buf.len() as u64 {
+                return Err(Error::Malformed("sample data past the end of the file"));
+            }
+            track.chunks.push(Chunk {
+                samples: count,
+                data: Cow::Borrowed(&buf[start as usize..end as usize]),
+            });
+            next = end;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite an MP4 file as a progressive MP4 with its `moov` at the front.
+///
+/// Fragmented files have their samples gathered into a single `mdat` and
+/// indexed by a rebuilt sample table. Progressive files with a trailing
+/// `moov` have it moved forward. Audio-only output is branded as M4A.
+pub fn faststart(input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut moov = None;
+    let mut fragmented = false;
+    let mut mdat_before_moov = false;
+    for atom in Atoms::new(input) {
+        let atom = atom?;
+        match &atom.kind {
+            b"moov" => moov = Some(atom),
+            b"moof" => fragmented = true,
+            b"mdat" if moov.is_none() => mdat_before_moov = true,
+            _ => {}
+        }
+    }
+    let moov = moov.ok_or(Error::Malformed("missing moov"))?;
+
+    if !fragmented {
+        if !mdat_before_moov {
+            return Ok(input.to_vec());
+        }
+        return relocate(input, &moov);
+    }
+
+    let mut tracks = read_tracks(&moov)?;
+    for atom in Atoms::new(input) {
+        let atom = atom?;
+        if &atom.kind == b"moof" {
+            read_moof(input, &atom, &mut tracks)?;
+        }
+    }
+
+    Ok(Movie {
+        tracks: tracks.into_iter().map(|(t, _)| t).collect(),
+        extra: Vec::new(),
+    }
+    .to_vec())
+}
+
+/// Move the `moov` of a progressive file ahead of its media, shifting its
+/// chunk offsets to match.
+fn relocate(input: &[u8], moov: &Atom) -> Result<Vec<u8>, Error> {
+    let moov_bytes = shift_offsets(moov, 0, moov.len() as i64)?;
+
+    let mut out = Vec::with_capacity(input.len());
+    let mut rest = Vec::new();
+    for atom in Atoms::new(input) {
+        let atom = atom?;
+        let raw = &input[atom.offset..atom.offset + atom.len()];
+        match &atom.kind {
+            b"ftyp" => out.extend_from_slice(raw),
+            b"moov" => {}
+            _ => rest.extend_from_slice(raw),
+        }
+    }
+    // The ftyp stays in place with the moov inserted behind it, so the media
+    // that preceded the moov moves forward by exactly its length.
+    if out.is_empty() {
+        return Err(Error::Malformed("missing ftyp"));
+    }
+    out.extend_from_slice(&moov_bytes);
+    out.extend_from_slice(&rest);
+    Ok(out)
+}
+
+/// Copy a box, adding `shift` to every `stco` and `co64` offset within that
+/// is at least `from`.
+fn shift_offsets(atom: &Atom, from: u64, shift: i64) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::new();
+    match &atom.kind {
+        b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" => {
+            let mut body = Vec::new();
+            for child in atom.children() {
+                body.extend_from_slice(&shift_offsets(&child?, from, shift)?);
+            }
+            write_box(&mut out, &atom.kind, &body);
+        }
+        b"stco" | b"co64" => {
+            let (version, flags, body) = atom.full()?;
+            let mut c = Cursor::new(body);
+            let count = c.u32()?;
+            let mut shifted = count.to_be_bytes().to_vec();
+            for _ in 0..count {
+                let offset = if &atom.kind == b"stco" {
+                    u64::from(c.u32()?)
+                } else {
+                    c.u64()?
+                };
+                let offset = if offset >= from {
+                    offset as i64 + shift
+                } else {
+                    offset as i64
+                };
+                if &atom.kind == b"stco" {
+                    if offset < 0 || offset > i64::from(u32::MAX) {
+                        return Err(Error::Unsupported("chunk offsets past 4 GiB".into()));
+                    }
+                    shifted.extend_from_slice(&(offset as u32).to_be_bytes());
+                } else {
+                    shifted.extend_from_slice(&(offset as u64).to_be_bytes());
+                }
+            }
+            write_full_box(&mut out, &atom.kind, version, flags, &shifted);
+        }
+        _ => write_box(&mut out, &atom.kind, atom.body),
+    }
+    Ok(out)
+}
+
+/// The ID and timescale of each track described by the `moov` of an
+/// initialization segment.
+pub fn timescales(init: &[u8]) -> Result<Vec<(u32, u32)>, Error> {
+    let moov = Atoms::new(init)
+        .find(b"moov")?
+        .ok_or(Error::Malformed("missing moov"))?;
+    Ok(read_tracks(&moov)?
+        .iter()
+        .map(|(t, _)| (t.id, t.timescale))
+        .collect())
+}
+
+/// Whether the fragments following initialization segment `b` could follow
+/// `a` instead: both must describe the same tracks, with the same IDs,
+/// timescales and sample descriptions.
+pub fn compatible(a: &[u8], b: &[u8]) -> Result<bool, Error> {
+    let tracks = |init: &[u8]| -> Result<Vec<(u32, u32, Vec<u8>)>, Error> {
+        let moov = Atoms::new(init)
+            .find(b"moov")?
+            .ok_or(Error::Malformed("missing moov"))?;
+        Ok(read_tracks(&moov)?
+            .into_iter()
+            .map(|(t, _)| (t.id, t.timescale, t.stsd))
+            .collect())
+    };
+    Ok(tracks(a)? == tracks(b)?)
+}
+
+/// Copy the fragments of a media segment, moving the decode time in the
+/// `tfdt` of each listed track by the given number of ticks of its
+/// timescale. The `ftyp`, `moov` and `sidx` of a segment stored with its
+/// header are dropped, so that segments of several files can follow a
+/// single initialization segment.
+pub fn restamp(segment: &[u8], shifts: &[(u32, i64)]) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::with_capacity(segment.len());
+    for atom in Atoms::new(segment) {
+        let atom = atom?;
+        let start = out.len();
+        match &atom.kind {
+            b"ftyp" | b"moov" | b"sidx" => continue,
+            _ => out.extend_from_slice(&segment[atom.offset..atom.offset + atom.len()]),
+        }
+        if &atom.kind != b"moof" {
+            continue;
+        }
+
+        for traf in atom.children() {
+            let traf = traf?;
+            if &traf.kind != b"traf" {
+                continue;
+            }
+            let tfhd = traf
+                .children()
+                .find(b"tfhd")?
+                .ok_or(Error::Malformed("traf without a tfhd"))?;
+            let (_, _, body) = tfhd.full()?;
+            let id = Cursor::new(body).u32()?;
+            let shift = match shifts.iter().find(|(track, _)| *track == id) {
+                Some((_, shift)) => *shift,
+                None => continue,
+            };
+
+            let tfdt = traf
+                .children()
+                .find(b"tfdt")?
+                .ok_or(Error::Malformed("traf without a tfdt"))?;
+            let (version, _, body) = tfdt.full()?;
+            let time = i128::from(Cursor::new(body).versioned(version)?) + i128::from(shift);
+            let at = start
+                + atom.header_len
+                + traf.offset
+                + traf.header_len
+                + tfdt.offset
+                + tfdt.header_len
+                + 4;
+            match version {
+                0 => {
+                    let time = u32::try_from(time).map_err(|_| {
+                        Error::Unsupported("decode time outside a version 0 tfdt".into())
+                    })?;
+                    out[at..at + 4].copy_from_slice(&time.to_be_bytes());
+                }
+                _ => {
+                    let time = u64::try_from(time)
+                        .map_err(|_| Error::Unsupported("negative decode time".into()))?;
+                    out[at..at + 8].copy_from_slice(&time.to_be_bytes());
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Embed `metadata` as iTunes-style `ilst` atoms in the `moov` of an MP4
+/// file, replacing any that are already present.
+pub fn tag(input: &[u8], metadata: &Metadata) -> Result<Vec<u8>, Error> {
+    let moov = Atoms::new(input)
+        .find(b"moov")?
+        .ok_or(Error::Malformed("missing moov"))?;
+
+    let mut udta = Vec::new();
+    let mut body = Vec::new();
+    for child in moov.children() {
+        let child = child?;
+        match &child.kind {
+            b"udta" => {
+                for item in child.children() {
+                    let item = item?;
+                    if &item.kind != b"meta" {
+                        write_box(&mut udta, &item.kind, item.body);
+                    }
+                }
+            }
+            _ => write_box(&mut body, &child.kind, child.body),
+        }
+    }
+    write_full_box(&mut udta, b"meta", 0, 0, &meta(metadata));
+    write_box(&mut body, b"udta", &udta);
+
+    let mut grown = Vec::new();
+    write_box(&mut grown, b"moov", &body);
+
+    // Media behind the moov moves by however much the moov grew.
+    let end = moov.offset + moov.len();
+    let grown = Atoms::new(&grown).find(b"moov")?.unwrap();
+    let grown = shift_offsets(&grown, end as u64, grown.len() as i64 - moov.len() as i64)?;
+
+    let mut out = Vec::with_capacity(input.len() + grown.len());
+    out.extend_from_slice(&input[..moov.offset]);
+    out.extend_from_slice(&grown);
+    out.extend_from_slice(&input[end..]);
+    Ok(out)
+}
+
+/// The body of a `meta` box holding an `ilst` for `metadata`.
+fn meta(metadata: &Metadata) -> Vec<u8> {
+    // Well-known type indicators for `data` atoms.
+    const UTF8: u32 = 1;
+    const JPEG: u32 = 13;
+    const PNG: u32 = 14;
+
+    let mut ilst = Vec::new();
+    let text = [
+        (b"\xa9nam", &metadata.title),
+        (b"\xa9ART", &metadata.artist),
+        (b"\xa9day", &metadata.date),
+        (b"desc", &metadata.description),
+        (b"\xa9cmt", &metadata.url),
+    ];
+    for (kind, value) in text.iter() {
+        if let Some(value) = value {
+            ilst_item(&mut ilst, kind, UTF8, value.as_bytes());
+        }
+    }
+    if let Some(cover) = &metadata.cover {
+        // MP4 only defines type indicators for JPEG and PNG artwork.
+        match cover.kind {
+            ImageType::Jpeg => ilst_item(&mut ilst, b"covr", JPEG, &cover.data),
+            ImageType::Png => ilst_item(&mut ilst, b"covr", PNG, &cover.data),
+            ImageType::WebP => {}
+        }
+    }
+
+    let mut hdlr = vec![0; 4];
+    hdlr.extend_from_slice(b"mdirappl");
+    hdlr.extend_from_slice(&[0; 9]);
+
+    let mut out = Vec::new();
+    write_full_box(&mut out, b"hdlr", 0, 0, &hdlr);
+    write_box(&mut out, b"ilst", &ilst);
+    out
+}
+
+fn ilst_item(out: &mut Vec<u8>, kind: &[u8; 4], data_type: u32, value: &[u8]) {
+    let mut data = vec![0; 4];
+    data.extend_from_slice(value);
+    let mut item = Vec::new();
+    write_full_box(&mut item, b"data", 0, data_type, &data);
+    write_box(out, kind, &item);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stsd() -> Vec<u8> {
+        let mut mp4a = Vec::new();
+        write_box(&mut mp4a, b"mp4a", &[0; 28]);
+        let mut body = 1u32.to_be_bytes().to_vec();
+        body.extend_from_slice(&mp4a);
+        let mut out = Vec::new();
+        write_full_box(&mut out, b"stsd", 0, 0, &body);
+        out
+    }
+
+    /// An audio track without samples, at `timescale`.
+    fn empty_track<'a>(timescale: u32) -> Track<'a> {
+        Track {
+            id: 1,
+            handler: Handler::Audio,
+            timescale,
+            // "und"
+            language: 0x55C4,
+            stsd: stsd(),
+            samples: Vec::new(),
+            chunks: Vec::new(),
+            delay: 0,
+            media_start: 0,
+        }
+    }
+
+    /// A fragmented audio file with two fragments of three samples each.
+    fn fragmented() -> Vec<u8> {
+        let init = Movie {
+            tracks: vec![empty_track(44_100)],
+            extra: Vec::new(),
+        }
+        .to_vec();
+
+        let moov = Atoms::new(&init).find(b"moov").unwrap().unwrap();
+        let mut trex = Vec::new();
+        for field in &[1u32, 1, 1024, 0, 0] {
+            trex.extend_from_slice(&field.to_be_bytes());
+        }
+        let mut mvex = Vec::new();
+        write_full_box(&mut mvex, b"trex", 0, 0, &trex);
+        let mut moov_body = moov.body.to_vec();
+        write_box(&mut moov_body, b"mvex", &mvex);
+
+        let mut out = Vec::new();
+        write_box(&mut out, b"ftyp", b"dash\0\0\0\0iso6");
+        write_box(&mut out, b"moov", &moov_body);
+
+        for fragment in 0..2u8 {
+            let sizes = [3u32, 4, 5];
+            let mut trun = (sizes.len() as u32).to_be_bytes().to_vec();
+            let data_offset_at = trun.len();
+            trun.extend_from_slice(&0u32.to_be_bytes());
+            for size in &sizes {
+                trun.extend_from_slice(&size.to_be_bytes());
+            }
+            let mut traf = Vec::new();
+            // default-base-is-moof
+            write_full_box(&mut traf, b"tfhd", 0, 0x02_0000, &1u32.to_be_bytes());
+            let trun_at = traf.len() + 12;
+            write_full_box(&mut traf, b"trun", 0, 0x201, &trun);
+
+            let mut moof = Vec::new();
+            write_full_box(&mut moof, b"mfhd", 0, 0, &u32::from(fragment).to_be_bytes());
+            let traf_at = moof.len() + 8;
+            write_box(&mut moof, b"traf", &traf);
+            let mut moof_box = Vec::new();
+            write_box(&mut moof_box, b"moof", &moof);
+
+            let offset = (moof_box.len() + 8) as u32;
+            let at = 8 + traf_at + trun_at + data_offset_at;
+            moof_box[at..at + 4].copy_from_slice(&offset.to_be_bytes());
+
+            out.extend_from_slice(&moof_box);
+            let data: Vec<u8> = (0..12).map(|i| fragment * 100 + i).collect();
+            write_box(&mut out, b"mdat", &data);
+        }
+        out
+    }
+
+    #[test]
+    fn defragments_audio() {
+        let out = faststart(&fragmented()).unwrap();
+
+        let kinds: Vec<[u8; 4]> = Atoms::new(&out).map(|a| a.unwrap().kind).collect();
+        assert_eq!(kinds, vec![*b"ftyp", *b"moov", *b"mdat"]);
+
+        let ftyp = Atoms::new(&out).find(b"ftyp").unwrap().unwrap();
+        assert_eq!(&ftyp.body[..4], b"M4A ");
+
+        let moov = Atoms::new(&out).find(b"moov").unwrap().unwrap();
+        let stbl = moov
+            .children()
+            .find(b"trak")
+            .unwrap()
+            .unwrap()
+            .children()
+            .find(b"mdia")
+            .unwrap()
+            .unwrap()
+            .children()
+            .find(b"minf")
+            .unwrap()
+            .unwrap()
+            .children()
+            .find(b"stbl")
+            .unwrap()
+            .unwrap();
+        let stco = stbl.children().find(b"stco").unwrap().unwrap();
+        let (_, _, body) = stco.full().unwrap();
+        let mut c = Cursor::new(body);
+        assert_eq!(c.u32().unwrap(), 2);
+        let first = c.u32().unwrap() as usize;
+        let second = c.u32().unwrap() as usize;
+        assert_eq!(&out[first..first + 12], &(0..12).collect::<Vec<u8>>()[..]);
+        assert_eq!(second, first + 12);
+        assert_eq!(out[second], 100);
+
+        // The result is already progressive, so it is left as-is.
+        assert_eq!(faststart(&out).unwrap(), out);
+    }
+
+    #[test]
+    fn rejects_bad_data_offsets() {
+        // A data offset pointing just before the start of the file.
+        let mut input = fragmented();
+        let moof = input.windows(4).position(|w| w == b"moof").unwrap() - 4;
+        let trun = input.windows(4).position(|w| w == b"trun").unwrap();
+        let at = trun + 4 + 4 + 4;
+        input[at..at + 4].copy_from_slice(&(-(moof as i32) - 1).to_be_bytes());
+        assert!(faststart(&input).is_err());
+    }
+
+    #[test]
+    fn restamps_fragments() {
+        assert_eq!(timescales(&fragmented()).unwrap(), vec![(1, 44_100)]);
+
+        let mut traf = Vec::new();
+        write_full_box(&mut traf, b"tfhd", 0, 0x02_0000, &1u32.to_be_bytes());
+        write_full_box(&mut traf, b"tfdt", 0, 0, &1000u32.to_be_bytes());
+        let mut moof = Vec::new();
+        write_box(&mut moof, b"traf", &traf);
+        let mut segment = Vec::new();
+        write_box(&mut segment, b"ftyp", b"dash\0\0\0\0iso6");
+        write_box(&mut segment, b"moof", &moof);
+        write_box(&mut segment, b"mdat", &[1, 2, 3]);
+
+        let out = restamp(&segment, &[(1, 500)]).unwrap();
+        let kinds: Vec<[u8; 4]> = Atoms::new(&out).map(|a| a.unwrap().kind).collect();
+        assert_eq!(kinds, vec![*b"moof", *b"mdat"]);
+        let tfdt = Atoms::new(&out)
+            .find(b"moof")
+            .unwrap()
+            .unwrap()
+            .children()
+            .find(b"traf")
+            .unwrap()
+            .unwrap()
+            .children()
+            .find(b"tfdt")
+            .unwrap()
+            .unwrap();
+        let (_, _, body) = tfdt.full().unwrap();
+        assert_eq!(Cursor::new(body).u32().unwrap(), 1500);
+
+        // Other tracks are left alone, and times cannot go negative.
+        assert_eq!(restamp(&segment, &[(2, 500)]).unwrap()[..], segment[20..]);
+        assert!(restamp(&segment, &[(1, -2000)]).is_err());
+    }
+
+    #[test]
+    fn moves_trailing_moov() {
+        let progressive = faststart(&fragmented()).unwrap();
+        let mut atoms = Atoms::new(&progressive);
+        let ftyp = atoms.next_atom().unwrap().unwrap();
+        let moov = atoms.next_atom().unwrap().unwrap();
+        let mdat = atoms.next_atom().unwrap().unwrap();
+
+        // Swap the moov behind the mdat, pointing its offsets at the media's
+        // new position.
+        let mut trailing = progressive[..ftyp.len()].to_vec();
+        trailing.extend_from_slice(&progressive[mdat.offset..mdat.offset + mdat.len()]);
+        trailing.extend_from_slice(&shift_offsets(&moov, 0, -(moov.len() as i64)).unwrap());
+
+        assert_eq!(faststart(&trailing).unwrap(), progressive);
+    }
+
+    #[test]
+    fn tags_progressive() {
+        let progressive = faststart(&fragmented()).unwrap();
+        let metadata = Metadata {
+            title: Some("Title".into()),
+            url: Some("https://www.youtube.com/watch?v=VfWgE7D1pYY".into()),
+            ..Default::default()
+        };
+        let tagged = tag(&progressive, &metadata).unwrap();
+
+        let moov = Atoms::new(&tagged).find(b"moov").unwrap().unwrap();
+        let ilst = moov
+            .children()
+            .find(b"udta")
+            .unwrap()
+            .unwrap()
+            .children()
+            .find(b"meta")
+            .unwrap()
+            .map(|m| {
+                Atoms::new(m.full().unwrap().2)
+                    .find(b"ilst")
+                    .unwrap()
+                    .unwrap()
+            })
+            .unwrap();
+        let title = ilst.children().find(b"\xa9nam").unwrap().unwrap();
+        assert!(title.body.ends_with(b"Title"));
+
+        // Re-tagging replaces the existing tags rather than adding to them,
+        // and the media is still where the sample table says it is.
+        let retagged = tag(&tagged, &metadata).unwrap();
+        assert_eq!(retagged, tagged);
+        let mdat = Atoms::new(&tagged).find(b"mdat").unwrap().unwrap();
+        let first = moov.body.windows(4).position(|w| w == b"stco").unwrap() + 12;
+        let offset = Cursor::new(&moov.body[first..]).u32().unwrap() as usize;
+        assert_eq!(offset, mdat.offset + 8);
+    }
+
+    #[test]
+    fn compares_initialization_segments() {
+        let movie = |timescale| {
+            Movie {
+                tracks: vec![empty_track(timescale)],
+                extra: Vec::new(),
+            }
+            .to_vec()
+        };
+        assert!(compatible(&fragmented(), &movie(44_100)).unwrap());
+        assert!(!compatible(&fragmented(), &movie(48_000)).unwrap());
+
+        let mut other = empty_track(44_100);
+        *other.stsd.last_mut().unwrap() = 1;
+        let other = Movie {
+            tracks: vec![other],
+            extra: Vec::new(),
+        }
+        .to_vec();
+        assert!(!compatible(&fragmented(), &other).unwrap());
+        assert!(compatible(&fragmented(), b"not mp4").is_err());
+    }
+}
@@ -0,0 +1,425 @@
+//! Rewriting the comment header of Ogg Opus and Ogg Vorbis files.
+
+use super::{tags::Metadata, Error};
+
+/// Comment fields replaced by [tag]; any others are kept.
+const REPLACED: [&str; 6] = [
+    "TITLE",
+    "ARTIST",
+    "DATE",
+    "DESCRIPTION",
+    "URL",
+    "METADATA_BLOCK_PICTURE",
+];
+
+/// Ogg page header flags.
+const CONTINUED: u8 = 0x01;
+const FIRST: u8 = 0x02;
+
+/// Lacing values a single page may hold.
+const MAX_SEGMENTS: usize = 255;
+
+/// A page read from an Ogg stream.
+#[derive(Debug, Clone)]
+struct Page<'a> {
+    /// The page exactly as read.
+    raw: &'a [u8],
+
+    flags: u8,
+    granule: u64,
+    serial: u32,
+    lacing: &'a [u8],
+    data: &'a [u8],
+}
+
+impl<'a> Page<'a> {
+    fn parse(buf: &'a [u8]) -> Result<(Self, usize), Error> {
+        if buf.len() < 27 || &buf[..4] != b"OggS" {
+            return Err(Error::Malformed("missing Ogg page"));
+        }
+        let segments = buf[26] as usize;
+        let lacing = buf
+            .get(27..27 + segments)
+            .ok_or(Error::Malformed("truncated Ogg page"))?;
+        let len: usize = lacing.iter().map(|l| *l as usize).sum();
+        let data = buf
+            .get(27 + segments..27 + segments + len)
+            .ok_or(Error::Malformed("truncated Ogg page"))?;
+
+        let mut granule = [0; 8];
+        granule.copy_from_slice(&buf[6..14]);
+        Ok((
+            Page {
+                raw: &buf[..27 + segments + len],
+                flags: buf[5],
+                granule: u64::from_le_bytes(granule),
+                serial: u32::from_le_bytes([buf[14], buf[15], buf[16], buf[17]]),
+                lacing,
+                data,
+            },
+            27 + segments + len,
+        ))
+    }
+
+    fn write(&self, out: &mut Vec<u8>, sequence: u32) {
+        let start = out.len();
+        out.extend_from_slice(b"OggS");
+        out.push(0);
+        out.push(self.flags);
+        out.extend_from_slice(&self.granule.to_le_bytes());
+        out.extend_from_slice(&self.serial.to_le_bytes());
+        out.extend_from_slice(&sequence.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.push(self.lacing.len() as u8);
+        out.extend_from_slice(self.lacing);
+        out.extend_from_slice(self.data);
+
+        let crc = crc32(&out[start..]);
+        out[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+    }
+}
+
+/// Embed `metadata` as Vorbis comments in the first logical stream of an
+/// Ogg Opus or Ogg Vorbis file, replacing any fields it sets.
+///
+/// Cover art is stored as a `METADATA_BLOCK_PICTURE`.
+pub fn tag(input: &[u8], metadata: &Metadata) -> Result<Vec<u8>, Error> {
+    let mut pages = Vec::new();
+    let mut pos = 0;
+    while pos < input.len() {
+        let (page, len) = Page::parse(&input[pos..])?;
+        pages.push(page);
+        pos += len;
+    }
+    let serial = pages
+        .first()
+        .ok_or(Error::Malformed("empty Ogg stream"))?
+        .serial;
+
+    // Reassemble header packets until the page they end on.
+    let mut packets: Vec<Vec<u8>> = vec![Vec::new()];
+    let mut header_pages = 0;
+    let mut wanted = None;
+    for page in pages.iter().filter(|p| p.serial == serial) {
+        let mut data = page.data;
+        for lacing in page.lacing {
+            let (segment, rest) = data.split_at(*lacing as usize);
+            packets.last_mut().unwrap().extend_from_slice(segment);
+            data = rest;
+            if *lacing < 255 {
+                if packets.len() == 1 {
+                    wanted = Some(match packets[0].as_slice() {
+                        p if p.starts_with(b"OpusHead") => 2,
+                        p if p.starts_with(b"\x01vorbis") => 3,
+                        _ => return Err(Error::Unsupported("Ogg codec".into())),
+                    });
+                }
+                packets.push(Vec::new());
+            }
+        }
+        header_pages += 1;
+        if let Some(n) = wanted {
+            if packets.len() > n {
+                break;
+            }
+        }
+    }
+    let wanted = wanted.ok_or(Error::Malformed("missing Ogg header"))?;
+    if packets.len() <= wanted {
+        return Err(Error::Malformed("truncated Ogg header"));
+    }
+    packets.truncate(wanted);
+
+    let opus = wanted == 2;
+    let magic: &[u8] = if opus { b"OpusTags" } else { b"\x03vorbis" };
+    packets[1] = comments(&packets[1], magic, metadata, !opus)?;
+
+    let mut out = Vec::with_capacity(input.len());
+    let mut sequence = 0;
+    for page in paginate(&packets[..1], serial, FIRST)
+        .iter()
+        .chain(paginate(&packets[1..], serial, 0).iter())
+    {
+        page.write(&mut out, sequence);
+        sequence += 1;
+    }
+
+    let mut skipped = 0;
+    for page in &pages {
+        if page.serial != serial {
+            // Other streams are left exactly as they were.
+            out.extend_from_slice(page.raw);
+        } else if skipped < header_pages {
+            skipped += 1;
+        } else {
+            page.write(&mut out, sequence);
+            sequence += 1;
+        }
+    }
+    Ok(out)
+}
+
+/// Rebuild a comment header packet with `metadata` applied.
+fn comments(
+    packet: &[u8],
+    magic: &[u8],
+    metadata: &Metadata,
+    framing: bool,
+) -> Result<Vec<u8>, Error> {
+    let body = packet
+        .strip_prefix(magic)
+        .ok_or(Error::Malformed("missing comment header"))?;
+
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    let vendor_len = read_len(body, &mut pos)?;
+    let vendor = read(body, &mut pos, vendor_len)?.to_vec();
+    let count = read_len(body, &mut pos)?;
+    for _ in 0..count {
+        let len = read_len(body, &mut pos)?;
+        let field = read(body, &mut pos, len)?;
+        let key = field.split(|b| *b == b'=').next().unwrap_or(&[]);
+        let key = String::from_utf8_lossy(key).to_uppercase();
+        if !REPLACED.contains(&key.as_str()) {
+            fields.push(field.to_vec());
+        }
+    }
+
+    for (key, value) in metadata.fields() {
+        fields.push(format!("{}={}", key, value).into_bytes());
+    }
+    if let Some(cover) = &metadata.cover {
+        let mut picture = Vec::new();
+        // Picture type 3 is the front cover.
+        picture.extend_from_slice(&3u32.to_be_bytes());
+        let mime = cover.kind.mime_type().as_bytes();
+        picture.extend_from_slice(&(mime.len() as u32).to_be_bytes());
+        picture.extend_from_slice(mime);
+        picture.extend_from_slice(&0u32.to_be_bytes());
+        picture.extend_from_slice(&cover.width.to_be_bytes());
+        picture.extend_from_slice(&cover.height.to_be_bytes());
+        picture.extend_from_slice(&24u32.to_be_bytes());
+        picture.extend_from_slice(&0u32.to_be_bytes());
+        picture.extend_from_slice(&(cover.data.len() as u32).to_be_bytes());
+        picture.extend_from_slice(&cover.data);
+        fields.push(format!("METADATA_BLOCK_PICTURE={}", base64(&picture)).into_bytes());
+    }
+
+    let mut out = magic.to_vec();
+    out.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    out.extend_from_slice(&vendor);
+    out.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+    for field in fields {
+        out.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        out.extend_from_slice(&field);
+    }
+    if framing {
+        out.push(1);
+    }
+    Ok(out)
+}
+
+fn read<'a>(buf: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], Error> {
+    let out = buf
+        .get(*pos..*pos + len)
+        .ok_or(Error::Malformed("truncated comment header"))?;
+    *pos += len;
+    Ok(out)
+}
+
+/// Read a little-endian length field.
+fn read_len(buf: &[u8], pos: &mut usize) -> Result<usize, Error> {
+    let b = read(buf, pos, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+}
+
+/// An owned page, as produced by [paginate].
+struct OwnedPage {
+    flags: u8,
+    granule: u64,
+    serial: u32,
+    lacing: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl OwnedPage {
+    fn write(&self, out: &mut Vec<u8>, sequence: u32) {
+        Page {
+            raw: &[],
+            flags: self.flags,
+            granule: self.granule,
+            serial: self.serial,
+            lacing: &self.lacing,
+            data: &self.data,
+        }
+        .write(out, sequence)
+    }
+}
+
+/// Lay header packets out over as many pages as they need. Header pages
+/// carry a granule position of zero, or -1 when no packet ends on them.
+fn paginate(packets: &[Vec<u8>], serial: u32, flags: u8) -> Vec<OwnedPage> {
+    let mut pages = Vec::new();
+    let mut page = OwnedPage {
+        flags,
+        granule: u64::MAX,
+        serial,
+        lacing: Vec::new(),
+        data: Vec::new(),
+    };
+
+    for packet in packets {
+        let mut rest: &[u8] = packet;
+        loop {
+            if page.lacing.len() == MAX_SEGMENTS {
+                let continued = page.lacing.last() == Some(&255);
+                pages.push(page);
+                page = OwnedPage {
+                    flags: if continued { CONTINUED } else { 0 },
+                    granule: u64::MAX,
+                    serial,
+                    lacing: Vec::new(),
+                    data: Vec::new(),
+                };
+            }
+            let len = rest.len().min(255);
+            page.lacing.push(len as u8);
+            page.data.extend_from_slice(&rest[..len]);
+            rest = &rest[len..];
+            if len < 255 {
+                page.granule = 0;
+                break;
+            }
+        }
+    }
+    pages.push(page);
+    pages
+}
+
+/// Standard base64 encoding, with padding.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// CRC-32 as used by Ogg: polynomial 0x04C11DB7, unreflected, no final XOR.
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut r = (i as u32) << 24;
+            let mut bit = 0;
+            while bit < 8 {
+                r = if r & 0x8000_0000 != 0 {
+                    (r << 1) ^ 0x04C1_1DB7
+                } else {
+                    r << 1
+                };
+                bit += 1;
+            }
+            table[i] = r;
+            i += 1;
+        }
+        table
+    };
+
+    data.iter().fold(0, |crc, b| {
+        (crc << 8) ^ TABLE[((crc >> 24) as u8 ^ b) as usize]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(packets: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut sequence = 0;
+        for (i, packet) in packets.iter().enumerate() {
+            let pages = paginate(&[packet.to_vec()], 7, if i == 0 { FIRST } else { 0 });
+            for page in pages {
+                page.write(&mut out, sequence);
+                sequence += 1;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn replaces_opus_tags() {
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&6u32.to_le_bytes());
+        tags.extend_from_slice(b"vendor");
+        tags.extend_from_slice(&2u32.to_le_bytes());
+        for field in &[&b"title=Old"[..], &b"ENCODER=x"[..]] {
+            tags.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            tags.extend_from_slice(field);
+        }
+        let audio = vec![0xAB; 600];
+        let input = stream(&[b"OpusHead\x01\x02", &tags, &audio]);
+
+        let metadata = Metadata {
+            title: Some("New".into()),
+            cover: Some(super::super::tags::Cover {
+                kind: super::super::tags::ImageType::Png,
+                width: 1,
+                height: 1,
+                data: vec![0x42; 70_000],
+            }),
+            ..Default::default()
+        };
+        let out = tag(&input, &metadata).unwrap();
+
+        let mut pos = 0;
+        let mut sequence = 0;
+        let mut data = Vec::new();
+        while pos < out.len() {
+            let (page, len) = Page::parse(&out[pos..]).unwrap();
+            let mut check = out[pos..pos + len].to_vec();
+            check[22..26].copy_from_slice(&[0; 4]);
+            assert_eq!(
+                crc32(&check).to_le_bytes(),
+                [out[pos + 22], out[pos + 23], out[pos + 24], out[pos + 25]]
+            );
+            assert_eq!(
+                u32::from_le_bytes([out[pos + 18], out[pos + 19], out[pos + 20], out[pos + 21]]),
+                sequence
+            );
+            data.extend_from_slice(page.data);
+            pos += len;
+            sequence += 1;
+        }
+        assert!(sequence > 3);
+        let text = String::from_utf8_lossy(&data);
+        assert!(text.contains("ENCODER=x"));
+        assert!(text.contains("TITLE=New"));
+        assert!(!text.contains("title=Old"));
+        assert!(data.ends_with(&audio));
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
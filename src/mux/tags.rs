@@ -0,0 +1,163 @@
+//! Descriptive metadata embedded into output files.
+//!
+//! A [Metadata] is built from a video's [InfoResponse], and written into
+//! whichever container a file turns out to be: `ilst` atoms for MP4, Tags
+//! and Attachments for Matroska, and Vorbis comments for Ogg.
+//!
+//! WebM allows no Attachments, so WebM files, which is what merged VP9 and
+//! Opus downloads are, get their text tags but no cover art.
+
+use crate::InfoResponse;
+
+use super::{mp4, ogg, webm, Error};
+
+/// Image formats accepted as cover art.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageType {
+    Jpeg,
+    Png,
+    WebP,
+}
+
+impl ImageType {
+    /// Identify an image from its leading bytes.
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageType::Jpeg)
+        } else if data.starts_with(b"\x89PNG") {
+            Some(ImageType::Png)
+        } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(ImageType::WebP)
+        } else {
+            None
+        }
+    }
+
+    /// MIME type of the image format.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ImageType::Jpeg => "image/jpeg",
+            ImageType::Png => "image/png",
+            ImageType::WebP => "image/webp",
+        }
+    }
+
+    /// Conventional file extension for the image format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageType::Jpeg => "jpg",
+            ImageType::Png => "png",
+            ImageType::WebP => "webp",
+        }
+    }
+}
+
+/// Cover art to embed.
+#[derive(Debug, Clone)]
+pub struct Cover {
+    pub kind: ImageType,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Descriptive tags for a file.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+
+    /// Release date, as `YYYY-MM-DD`.
+    pub date: Option<String>,
+
+    pub description: Option<String>,
+
+    /// Where the media came from.
+    pub url: Option<String>,
+
+    pub cover: Option<Cover>,
+}
+
+impl Metadata {
+    /// (Vorbis comment / Matroska tag name, value) pairs for each text field
+    /// that is present.
+    pub(crate) fn fields(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("TITLE", &self.title),
+            ("ARTIST", &self.artist),
+            ("DATE", &self.date),
+            ("DESCRIPTION", &self.description),
+            ("URL", &self.url),
+        ]
+        .iter()
+        .filter_map(|(k, v)| v.as_deref().map(|v| (*k, v)))
+        .collect()
+    }
+}
+
+impl From<&InfoResponse> for Metadata {
+    /// Metadata describing a video, without any cover art.
+    fn from(info: &InfoResponse) -> Self {
+        let details = info.details();
+        let description = details.description();
+        Self {
+            title: Some(details.title()),
+            artist: Some(details.author()),
+            date: info.publish_date(),
+            description: if description.is_empty() {
+                None
+            } else {
+                Some(description)
+            },
+            url: Some(format!("https://www.youtube.com/watch?v={}", details.id())),
+            cover: None,
+        }
+    }
+}
+
+/// Embed `metadata` into an MP4, Matroska/WebM or Ogg file, replacing any
+/// tags it already holds.
+pub fn write(data: &[u8], metadata: &Metadata) -> Result<Vec<u8>, Error> {
+    if data.get(4..8) == Some(b"ftyp") {
+        mp4::tag(data, metadata)
+    } else if data.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        webm::tag(data, metadata)
+    } else if data.starts_with(b"OggS") {
+        ogg::tag(data, metadata)
+    } else {
+        Err(Error::Unsupported("unrecognized container".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_images() {
+        assert_eq!(ImageType::sniff(&[0xFF, 0xD8, 0xFF, 0xE0]), Some(ImageType::Jpeg));
+        assert_eq!(ImageType::sniff(b"\x89PNG\r\n\x1a\n"), Some(ImageType::Png));
+        assert_eq!(ImageType::sniff(b"RIFF\x24\0\0\0WEBPVP8 "), Some(ImageType::WebP));
+        assert_eq!(ImageType::sniff(b"RIFF\x24\0\0\0WAVE"), None);
+        assert_eq!(ImageType::sniff(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn lists_present_fields() {
+        let metadata = Metadata {
+            title: Some("Title".into()),
+            date: Some("2021-05-02".into()),
+            url: Some("https://www.youtube.com/watch?v=VfWgE7D1pYY".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            metadata.fields(),
+            vec![
+                ("TITLE", "Title"),
+                ("DATE", "2021-05-02"),
+                ("URL", "https://www.youtube.com/watch?v=VfWgE7D1pYY"),
+            ]
+        );
+        assert!(write(b"not a media file", &metadata).is_err());
+    }
+}
@@ -0,0 +1,1039 @@
+//! Demultiplexing MPEG transport streams, and remuxing them into MP4.
+//!
+//! HLS serves most of its media as MPEG-TS, which few editors or browsers
+//! open. [demux] reassembles the PES packets of each elementary stream
+//! listed by a stream's PAT and PMT, and [to_mp4] rewrites its H.264 video
+//! and ADTS AAC audio as a progressive MP4 without re-encoding.
+
+use std::borrow::Cow;
+
+use super::{
+    mp4::{write_box, write_full_box, Chunk, Handler, Movie, Sample, Track},
+    Error,
+};
+
+/// Length of every transport stream packet.
+pub const PACKET_LEN: usize = 188;
+
+/// Ticks per second of PTS and DTS timestamps.
+pub const CLOCK: u64 = 90_000;
+
+const SYNC: u8 = 0x47;
+
+/// Timestamps are 33 bits long, and wrap around about every 26 hours.
+const WRAP: u64 = 1 << 33;
+
+/// Sample rates by the index stored in ADTS headers.
+const SAMPLE_RATES: [u32; 13] = [
+    96_000, 88_200, 64_000, 48_000, 44_100, 32_000, 24_000, 22_050, 16_000, 12_000, 11_025, 8_000,
+    7_350,
+];
+
+/// Samples in every AAC frame.
+const AAC_FRAME_LEN: u32 = 1024;
+
+/// Packed ISO 639-2/T code for an undetermined language.
+const UNDETERMINED: u16 = 0x55C4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Codec of an elementary stream, by the `stream_type` its PMT gives.
+pub enum StreamType {
+    H264,
+    Aac,
+    Other(u8),
+}
+
+impl From<u8> for StreamType {
+    fn from(stream_type: u8) -> Self {
+        match stream_type {
+            0x1B => StreamType::H264,
+            0x0F => StreamType::Aac,
+            other => StreamType::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A reassembled PES packet, usually holding one video access unit or a
+/// run of audio frames.
+pub struct Pes {
+    /// Presentation timestamp, in [CLOCK] ticks.
+    pub pts: Option<u64>,
+
+    /// Decode timestamp, in [CLOCK] ticks, if it differs from the PTS.
+    pub dts: Option<u64>,
+
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+/// An elementary stream of a transport stream.
+pub struct Stream {
+    pub pid: u16,
+    pub stream_type: StreamType,
+    pub packets: Vec<Pes>,
+}
+
+/// Whether `data` begins like a transport stream.
+pub fn is_ts(data: &[u8]) -> bool {
+    data.len() >= PACKET_LEN && data.iter().step_by(PACKET_LEN).take(3).all(|&b| b == SYNC)
+}
+
+/// Split a transport stream into its elementary streams, in the order
+/// their programs list them. A packet cut short at the end of the input is
+/// ignored.
+pub fn demux(input: &[u8]) -> Result<Vec<Stream>, Error> {
+    let mut pmt_pids = Vec::new();
+    let mut streams: Vec<Stream> = Vec::new();
+    // Payload of the PES packet being reassembled for each stream.
+    let mut partial: Vec<Option<Vec<u8>>> = Vec::new();
+
+    for packet in input.chunks_exact(PACKET_LEN) {
+        if packet[0] != SYNC {
+            return Err(Error::Malformed("lost transport stream sync"));
+        }
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1F, packet[2]]);
+        let control = packet[3] >> 4 & 0x3;
+        let offset = match control {
+            0x1 => 4,
+            0x3 => 5 + packet[4] as usize,
+            _ => continue,
+        };
+        let payload = match packet.get(offset..) {
+            Some(payload) if !payload.is_empty() => payload,
+            _ => continue,
+        };
+
+        if pid == 0 {
+            if unit_start {
+                for pmt in pat(section(payload)?)? {
+                    if !pmt_pids.contains(&pmt) {
+                        pmt_pids.push(pmt);
+                    }
+                }
+            }
+        } else if pmt_pids.contains(&pid) {
+            if unit_start {
+                for (pid, stream_type) in pmt(section(payload)?)? {
+                    if !streams.iter().any(|s| s.pid == pid) {
+                        streams.push(Stream {
+                            pid,
+                            stream_type,
+                            packets: Vec::new(),
+                        });
+                        partial.push(None);
+                    }
+                }
+            }
+        } else if let Some(i) = streams.iter().position(|s| s.pid == pid) {
+            if unit_start {
+                if let Some(data) = partial[i].take() {
+                    streams[i].packets.push(pes(&data)?);
+                }
+                partial[i] = Some(payload.to_vec());
+            } else if let Some(data) = partial[i].as_mut() {
+                data.extend_from_slice(payload);
+            }
+        }
+    }
+
+    for (stream, data) in streams.iter_mut().zip(partial) {
+        if let Some(data) = data {
+            stream.packets.push(pes(&data)?);
+        }
+    }
+    Ok(streams)
+}
+
+/// The PSI section starting in `payload`, from its table ID up to and
+/// including its CRC.
+fn section(payload: &[u8]) -> Result<&[u8], Error> {
+    let pointer = *payload
+        .first()
+        .ok_or(Error::Malformed("empty PSI payload"))? as usize;
+    let section = payload
+        .get(1 + pointer..)
+        .filter(|s| s.len() >= 3)
+        .ok_or(Error::Malformed("truncated PSI section"))?;
+    let len = u16::from_be_bytes([section[1] & 0x0F, section[2]]) as usize;
+    section
+        .get(..3 + len)
+        .filter(|s| s.len() >= 12)
+        .ok_or(Error::Malformed("truncated PSI section"))
+}
+
+/// PIDs of the program map tables listed by a program association table.
+fn pat(section: &[u8]) -> Result<Vec<u16>, Error> {
+    if section[0] != 0x00 {
+        return Err(Error::Malformed("PAT with the wrong table ID"));
+    }
+    Ok(section[8..section.len() - 4]
+        .chunks_exact(4)
+        // Program 0 points at the network information table instead.
+        .filter(|e| e[0] != 0 || e[1] != 0)
+        .map(|e| u16::from_be_bytes([e[2] & 0x1F, e[3]]))
+        .collect())
+}
+
+/// PIDs and types of the elementary streams listed by a program map table.
+fn pmt(section: &[u8]) -> Result<Vec<(u16, StreamType)>, Error> {
+    if section[0] != 0x02 {
+        return Err(Error::Malformed("PMT with the wrong table ID"));
+    }
+    let end = section.len() - 4;
+    let mut pos = 12 + u16::from_be_bytes([section[10] & 0x0F, section[11]]) as usize;
+    let mut streams = Vec::new();
+    while pos + 5 <= end {
+        let pid = u16::from_be_bytes([section[pos + 1] & 0x1F, section[pos + 2]]);
+        streams.push((pid, StreamType::from(section[pos])));
+        pos += 5 + u16::from_be_bytes([section[pos + 3] & 0x0F, section[pos + 4]]) as usize;
+    }
+    Ok(streams)
+}
+
+/// Parse a reassembled PES packet.
+fn pes(data: &[u8]) -> Result<Pes, Error> {
+    if data.len() < 6 || data[..3] != [0, 0, 1] {
+        return Err(Error::Malformed("missing PES start code"));
+    }
+    let len = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let data = match len {
+        0 => data,
+        len => &data[..(6 + len).min(data.len())],
+    };
+
+    // Padding and private streams carry no optional header.
+    if matches!(
+        data[3],
+        0xBC | 0xBE | 0xBF | 0xF0 | 0xF1 | 0xF2 | 0xF8 | 0xFF
+    ) {
+        return Ok(Pes {
+            pts: None,
+            dts: None,
+            data: data[6..].to_vec(),
+        });
+    }
+
+    let header = data
+        .get(6..9)
+        .ok_or(Error::Malformed("truncated PES header"))?;
+    let start = 9 + header[2] as usize;
+    let (pts, dts) = match header[1] >> 6 {
+        0b10 => (Some(timestamp(data.get(9..14))?), None),
+        0b11 => (
+            Some(timestamp(data.get(9..14))?),
+            Some(timestamp(data.get(14..19))?),
+        ),
+        _ => (None, None),
+    };
+    Ok(Pes {
+        pts,
+        dts,
+        data: data
+            .get(start..)
+            .ok_or(Error::Malformed("truncated PES header"))?
+            .to_vec(),
+    })
+}
+
+/// Read a 33-bit PTS or DTS, spread over five bytes between marker bits.
+fn timestamp(b: Option<&[u8]>) -> Result<u64, Error> {
+    let b = b.ok_or(Error::Malformed("truncated PES timestamp"))?;
+    Ok(u64::from(b[0] >> 1 & 0x07) << 30
+        | u64::from(b[1]) << 22
+        | u64::from(b[2] >> 1) << 15
+        | u64::from(b[3]) << 7
+        | u64::from(b[4] >> 1))
+}
+
+/// Place `ts` in whichever 33-bit cycle brings it closest to `reference`,
+/// so that timestamps keep increasing across a wraparound.
+fn unwrap(ts: u64, reference: Option<u64>) -> u64 {
+    let reference = match reference {
+        Some(r) => r,
+        None => return ts,
+    };
+    let candidate = reference - reference % WRAP + ts;
+    [
+        candidate.checked_sub(WRAP),
+        Some(candidate),
+        Some(candidate + WRAP),
+    ]
+    .iter()
+    .flatten()
+    .copied()
+    .min_by_key(|c| (*c as i128 - reference as i128).abs())
+    .unwrap_or(ts)
+}
+
+/// Remux a transport stream into a progressive MP4, keeping its H.264 and
+/// AAC streams and dropping any others.
+///
+/// Parameter sets are taken from the first SPS and PPS of each video
+/// stream, so streams whose encoding changes part-way, as across some HLS
+/// discontinuities, are not supported. Tracks are aligned by their first
+/// presentation timestamps.
+pub fn to_mp4(input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut tracks = Vec::new();
+    for stream in demux(input)? {
+        let id = tracks.len() as u32 + 1;
+        let track = match stream.stream_type {
+            StreamType::H264 => h264_track(id, &stream.packets)?,
+            StreamType::Aac => aac_track(id, &stream.packets)?,
+            StreamType::Other(_) => None,
+        };
+        tracks.extend(track);
+    }
+
+    let start = tracks.iter().map(|(_, pts)| *pts).min().ok_or_else(|| {
+        Error::Unsupported("no H.264 video or AAC audio in the transport stream".into())
+    })?;
+    Ok(Movie {
+        tracks: tracks
+            .into_iter()
+            .map(|(mut track, pts)| {
+                track.delay = (pts - start) * u64::from(track.timescale) / CLOCK;
+                track
+            })
+            .collect(),
+        extra: Vec::new(),
+    }
+    .to_vec())
+}
+
+/// A video [Track] for an H.264 stream, along with its first presentation
+/// timestamp, or [None] if it holds no pictures.
+fn h264_track(id: u32, packets: &[Pes]) -> Result<Option<(Track<'static>, u64)>, Error> {
+    struct Unit {
+        dts: u64,
+        pts: u64,
+        data: Vec<u8>,
+        sync: bool,
+    }
+
+    let mut sps = None;
+    let mut pps = None;
+    let mut units: Vec<Unit> = Vec::new();
+    for pes in packets {
+        let mut data = Vec::new();
+        let mut sync = false;
+        for nal in nal_units(&pes.data) {
+            match nal[0] & 0x1F {
+                // Parameter sets move to the avcC, and delimiters are not
+                // carried in MP4.
+                7 => {
+                    sps.get_or_insert_with(|| nal.to_vec());
+                    continue;
+                }
+                8 => {
+                    pps.get_or_insert_with(|| nal.to_vec());
+                    continue;
+                }
+                9 => continue,
+                5 => sync = true,
+                _ => {}
+            }
+            data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+            data.extend_from_slice(nal);
+        }
+
+        match (pes.pts, units.last_mut()) {
+            (Some(pts), last) => {
+                let dts = unwrap(pes.dts.unwrap_or(pts), last.map(|u| u.dts));
+                units.push(Unit {
+                    dts,
+                    pts: unwrap(pts, Some(dts)),
+                    data,
+                    sync,
+                });
+            }
+            // A PES without timestamps continues the access unit before.
+            (None, Some(unit)) => {
+                unit.data.extend_from_slice(&data);
+                unit.sync |= sync;
+            }
+            (None, None) => {}
+        }
+    }
+    units.retain(|u| !u.data.is_empty());
+    let (first_dts, first_pts) = match (units.first(), units.iter().map(|u| u.pts).min()) {
+        (Some(first), Some(pts)) => (first.dts, pts),
+        _ => return Ok(None),
+    };
+    let sps = sps.ok_or(Error::Malformed("H.264 stream without an SPS"))?;
+    let pps = pps.ok_or(Error::Malformed("H.264 stream without a PPS"))?;
+    let (width, height) = dimensions(&sps)?;
+
+    // Each picture lasts until the next is decoded. Where decode times
+    // jump, as at a discontinuity, the previous duration is kept.
+    let mut durations = Vec::with_capacity(units.len());
+    let mut previous = CLOCK / 30;
+    for pair in units.windows(2) {
+        let gap = pair[1].dts.wrapping_sub(pair[0].dts);
+        if gap > 0 && gap <= 10 * CLOCK {
+            previous = gap;
+        }
+        durations.push(previous);
+    }
+    durations.push(previous);
+
+    let mut samples = Vec::with_capacity(units.len());
+    let mut data = Vec::with_capacity(units.len());
+    for (unit, duration) in units.into_iter().zip(durations) {
+        samples.push(Sample {
+            size: unit.data.len() as u32,
+            duration: duration as u32,
+            composition_offset: (unit.pts as i64 - unit.dts as i64) as i32,
+            sync: unit.sync,
+        });
+        data.push(unit.data);
+    }
+
+    let timescale = CLOCK as u32;
+    Ok(Some((
+        Track {
+            id,
+            handler: Handler::Video { width, height },
+            timescale,
+            language: UNDETERMINED,
+            stsd: avc1(&sps, &pps, width, height),
+            chunks: chunks(&samples, data, timescale),
+            samples,
+            delay: 0,
+            media_start: first_pts.saturating_sub(first_dts),
+        },
+        first_pts,
+    )))
+}
+
+/// An audio [Track] for an ADTS AAC stream, along with its first
+/// presentation timestamp, or [None] if it holds no frames.
+fn aac_track(id: u32, packets: &[Pes]) -> Result<Option<(Track<'static>, u64)>, Error> {
+    let first_pts = match packets.iter().find_map(|p| p.pts) {
+        Some(pts) => pts,
+        None => return Ok(None),
+    };
+    // Frames may be split between PES packets.
+    let stream: Vec<u8> = packets
+        .iter()
+        .flat_map(|p| p.data.iter().copied())
+        .collect();
+
+    let mut config = None;
+    let mut samples = Vec::new();
+    let mut data = Vec::new();
+    let mut pos = 0;
+    while let Some(h) = stream.get(pos..pos + 7) {
+        if h[0] != 0xFF || h[1] & 0xF0 != 0xF0 {
+            return Err(Error::Malformed("lost ADTS sync"));
+        }
+        let header_len = if h[1] & 0x01 == 1 { 7 } else { 9 };
+        let frame_len =
+            usize::from(h[3] & 0x03) << 11 | usize::from(h[4]) << 3 | usize::from(h[5] >> 5);
+        if frame_len < header_len {
+            return Err(Error::Malformed("ADTS frame shorter than its header"));
+        }
+        if h[6] & 0x03 != 0 {
+            return Err(Error::Unsupported(
+                "ADTS frames holding several raw data blocks".into(),
+            ));
+        }
+
+        let this = (
+            (h[2] >> 6) + 1,
+            h[2] >> 2 & 0x0F,
+            (h[2] & 0x01) << 2 | h[3] >> 6,
+        );
+        match config {
+            None => config = Some(this),
+            Some(config) if config != this => {
+                return Err(Error::Unsupported(
+                    "AAC configuration changing mid-stream".into(),
+                ))
+            }
+            Some(_) => {}
+        }
+
+        // A frame cut short ends the stream.
+        let frame = match stream.get(pos + header_len..pos + frame_len) {
+            Some(frame) => frame,
+            None => break,
+        };
+        samples.push(Sample {
+            size: frame.len() as u32,
+            duration: AAC_FRAME_LEN,
+            composition_offset: 0,
+            sync: true,
+        });
+        data.push(frame.to_vec());
+        pos += frame_len;
+    }
+
+    let (object_type, rate_index, channels) = match config {
+        Some(config) if !samples.is_empty() => config,
+        _ => return Ok(None),
+    };
+    let rate = *SAMPLE_RATES
+        .get(rate_index as usize)
+        .ok_or(Error::Malformed("invalid ADTS sample rate"))?;
+    if channels == 0 {
+        return Err(Error::Unsupported(
+            "AAC channel layouts given in-band".into(),
+        ));
+    }
+
+    Ok(Some((
+        Track {
+            id,
+            handler: Handler::Audio,
+            timescale: rate,
+            language: UNDETERMINED,
+            stsd: mp4a(object_type, rate_index, channels, rate),
+            chunks: chunks(&samples, data, rate),
+            samples,
+            delay: 0,
+            media_start: 0,
+        },
+        first_pts,
+    )))
+}
+
+/// Group the `data` of `samples` into chunks of about a second each.
+fn chunks(samples: &[Sample], data: Vec<Vec<u8>>, timescale: u32) -> Vec<Chunk<'static>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut count = 0;
+    let mut elapsed = 0;
+    for (sample, data) in samples.iter().zip(data) {
+        chunk.extend_from_slice(&data);
+        count += 1;
+        elapsed += sample.duration;
+        if elapsed >= timescale {
+            chunks.push(Chunk {
+                samples: count,
+                data: Cow::Owned(std::mem::take(&mut chunk)),
+            });
+            count = 0;
+            elapsed = 0;
+        }
+    }
+    if count > 0 {
+        chunks.push(Chunk {
+            samples: count,
+            data: Cow::Owned(chunk),
+        });
+    }
+    chunks
+}
+
+/// The NAL units of an Annex B byte stream, without their start codes.
+fn nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    starts
+        .iter()
+        .enumerate()
+        .map(|(k, &start)| {
+            let end = starts.get(k + 1).map_or(data.len(), |&next| next - 3);
+            let mut nal = &data[start..end];
+            // Trailing zeroes belong to the next start code.
+            while let [rest @ .., 0] = nal {
+                nal = rest;
+            }
+            nal
+        })
+        .filter(|nal| !nal.is_empty())
+        .collect()
+}
+
+/// Reads the bits of a NAL unit payload, with emulation prevention bytes
+/// removed.
+struct Bits {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl Bits {
+    fn new(nal: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(nal.len());
+        let mut zeroes = 0;
+        for &b in nal {
+            if zeroes >= 2 && b == 0x03 {
+                zeroes = 0;
+                continue;
+            }
+            zeroes = if b == 0 { zeroes + 1 } else { 0 };
+            data.push(b);
+        }
+        Self { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> Result<u32, Error> {
+        let byte = self
+            .data
+            .get(self.pos / 8)
+            .ok_or(Error::Malformed("truncated SPS"))?;
+        let bit = byte >> (7 - self.pos % 8) & 1;
+        self.pos += 1;
+        Ok(u32::from(bit))
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, Error> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = value << 1 | self.bit()?;
+        }
+        Ok(value)
+    }
+
+    /// An unsigned Exp-Golomb code.
+    fn ue(&mut self) -> Result<u32, Error> {
+        let mut zeroes = 0;
+        while self.bit()? == 0 {
+            zeroes += 1;
+            if zeroes > 31 {
+                return Err(Error::Malformed("invalid Exp-Golomb code in SPS"));
+            }
+        }
+        Ok((1u64 << zeroes) as u32 - 1 + self.bits(zeroes)?)
+    }
+
+    /// A signed Exp-Golomb code.
+    fn se(&mut self) -> Result<i32, Error> {
+        let k = self.ue()?;
+        Ok(if k % 2 == 1 {
+            (k / 2 + 1) as i32
+        } else {
+            -((k / 2) as i32)
+        })
+    }
+}
+
+/// Width and height in pixels of the pictures described by an SPS.
+fn dimensions(sps: &[u8]) -> Result<(u32, u32), Error> {
+    let mut bits = Bits::new(sps);
+    bits.bits(8)?;
+    let profile = bits.bits(8)?;
+    bits.bits(16)?;
+    bits.ue()?;
+
+    let mut chroma_format = 1;
+    if matches!(
+        profile,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format = bits.ue()?;
+        if chroma_format == 3 {
+            bits.bit()?;
+        }
+        bits.ue()?;
+        bits.ue()?;
+        bits.bit()?;
+        if bits.bit()? == 1 {
+            for i in 0..if chroma_format == 3 { 12 } else { 8 } {
+                if bits.bit()? == 1 {
+                    skip_scaling_list(&mut bits, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    bits.ue()?;
+    match bits.ue()? {
+        0 => {
+            bits.ue()?;
+        }
+        1 => {
+            bits.bit()?;
+            bits.se()?;
+            bits.se()?;
+            for _ in 0..bits.ue()? {
+                bits.se()?;
+            }
+        }
+        _ => {}
+    }
+    bits.ue()?;
+    bits.bit()?;
+
+    let width_mbs = bits.ue()? + 1;
+    let height_units = bits.ue()? + 1;
+    let frame_mbs_only = bits.bit()?;
+    if frame_mbs_only == 0 {
+        bits.bit()?;
+    }
+    bits.bit()?;
+    let (mut left, mut right, mut top, mut bottom) = (0, 0, 0, 0);
+    if bits.bit()? == 1 {
+        left = bits.ue()?;
+        right = bits.ue()?;
+        top = bits.ue()?;
+        bottom = bits.ue()?;
+    }
+
+    // Cropping is counted in chroma samples, and in field pairs for
+    // interlaced video.
+    let (crop_x, crop_y) = match chroma_format {
+        0 => (1, 2 - frame_mbs_only),
+        1 => (2, 2 * (2 - frame_mbs_only)),
+        2 => (2, 2 - frame_mbs_only),
+        _ => (1, 2 - frame_mbs_only),
+    };
+    // A malformed SPS can give sizes and crops of up to 2^32 - 2.
+    let size = |units: u32, unit: u32, start: u32, end: u32, crop: u32| {
+        let full = units.checked_mul(unit)?;
+        let cropped = start.checked_add(end)?.checked_mul(crop)?;
+        full.checked_sub(cropped)
+    };
+    let width = size(width_mbs, 16, left, right, crop_x);
+    let height = size(height_units, (2 - frame_mbs_only) * 16, top, bottom, crop_y);
+    match (width, height) {
+        (Some(width), Some(height)) => Ok((width, height)),
+        _ => Err(Error::Malformed("SPS picture size out of range")),
+    }
+}
+
+fn skip_scaling_list(bits: &mut Bits, len: usize) -> Result<(), Error> {
+    let mut last = 8;
+    let mut next = 8;
+    for _ in 0..len {
+        if next != 0 {
+            next = (last + bits.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Ok(())
+}
+
+/// An `stsd` holding a single sample entry.
+fn stsd(kind: &[u8; 4], entry: &[u8]) -> Vec<u8> {
+    let mut body = 1u32.to_be_bytes().to_vec();
+    write_box(&mut body, kind, entry);
+    let mut out = Vec::new();
+    write_full_box(&mut out, b"stsd", 0, 0, &body);
+    out
+}
+
+/// An `stsd` describing H.264 video with the given parameter sets.
+fn avc1(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut avcc = vec![1, sps[1], sps[2], sps[3], 0xFF, 0xE1];
+    avcc.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    avcc.extend_from_slice(sps);
+    avcc.push(1);
+    avcc.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    avcc.extend_from_slice(pps);
+
+    let mut entry = vec![0; 6];
+    entry.extend_from_slice(&1u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 16]);
+    entry.extend_from_slice(&(width as u16).to_be_bytes());
+    entry.extend_from_slice(&(height as u16).to_be_bytes());
+    entry.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+    entry.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+    entry.extend_from_slice(&[0; 4]);
+    entry.extend_from_slice(&1u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 32]);
+    entry.extend_from_slice(&0x0018u16.to_be_bytes());
+    entry.extend_from_slice(&(-1i16).to_be_bytes());
+    write_box(&mut entry, b"avcC", &avcc);
+    stsd(b"avc1", &entry)
+}
+
+/// An `stsd` describing AAC audio as configured by an ADTS header.
+fn mp4a(object_type: u8, rate_index: u8, channels: u8, rate: u32) -> Vec<u8> {
+    let config = [
+        object_type << 3 | rate_index >> 1,
+        (rate_index & 1) << 7 | channels << 3,
+    ];
+    let mut decoder = vec![0x40, 0x15, 0, 0, 0];
+    decoder.extend_from_slice(&[0; 8]);
+    decoder.extend_from_slice(&[0x05, config.len() as u8]);
+    decoder.extend_from_slice(&config);
+
+    let mut es = vec![0, 0, 0, 0x04, decoder.len() as u8];
+    es.extend_from_slice(&decoder);
+    es.extend_from_slice(&[0x06, 1, 0x02]);
+    let mut esds = vec![0x03, es.len() as u8];
+    esds.extend_from_slice(&es);
+
+    let mut entry = vec![0; 6];
+    entry.extend_from_slice(&1u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 8]);
+    entry.extend_from_slice(&u16::from(channels).to_be_bytes());
+    entry.extend_from_slice(&16u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 4]);
+    entry.extend_from_slice(&(rate.min(0xFFFF) << 16).to_be_bytes());
+    write_full_box(&mut entry, b"esds", 0, 0, &esds);
+    stsd(b"mp4a", &entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mux::mp4::{Atom, Atoms};
+    use std::convert::TryInto;
+
+    /// Writes bits MSB first, as an SPS is read.
+    #[derive(Default)]
+    struct Writer {
+        bits: Vec<bool>,
+    }
+
+    impl Writer {
+        fn bits(&mut self, value: u32, n: u32) {
+            for i in (0..n).rev() {
+                self.bits.push(value >> i & 1 == 1);
+            }
+        }
+
+        fn ue(&mut self, value: u32) {
+            let n = 32 - (value + 1).leading_zeros();
+            self.bits(0, n - 1);
+            self.bits(value + 1, n);
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            self.bits.push(true);
+            self.bits
+                .chunks(8)
+                .map(|c| {
+                    c.iter()
+                        .enumerate()
+                        .fold(0, |b, (i, &bit)| b | (bit as u8) << (7 - i))
+                })
+                .collect()
+        }
+    }
+
+    /// A baseline SPS for 1920x1080 pictures, cropped from 1088 lines.
+    fn sps() -> Vec<u8> {
+        sized_sps(119, 67, [0, 0, 0, 4])
+    }
+
+    /// A baseline SPS with the given width in macroblocks and height in
+    /// map units, less one each, and frame cropping.
+    fn sized_sps(width: u32, height: u32, crops: [u32; 4]) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bits(0x67, 8);
+        w.bits(66, 8);
+        w.bits(0, 8);
+        w.bits(40, 8);
+        w.ue(0);
+        w.ue(0);
+        w.ue(0);
+        w.ue(0);
+        w.ue(1);
+        w.bits(0, 1);
+        w.ue(width);
+        w.ue(height);
+        w.bits(1, 1);
+        w.bits(1, 1);
+        w.bits(1, 1);
+        for crop in &crops {
+            w.ue(*crop);
+        }
+        w.bits(0, 1);
+        w.finish()
+    }
+
+    fn packetize(out: &mut Vec<u8>, pid: u16, payload: &[u8]) {
+        for (i, chunk) in payload.chunks(184).enumerate() {
+            let unit_start = if i == 0 { 0x40 } else { 0 };
+            let mut packet = vec![SYNC, unit_start | (pid >> 8) as u8, pid as u8];
+            if chunk.len() == 184 {
+                packet.push(0x10);
+            } else {
+                let stuffing = 183 - chunk.len();
+                packet.extend_from_slice(&[0x30, stuffing as u8]);
+                if stuffing > 0 {
+                    packet.push(0);
+                    packet.extend(vec![0xFF; stuffing - 1]);
+                }
+            }
+            packet.extend_from_slice(chunk);
+            out.extend_from_slice(&packet);
+        }
+    }
+
+    fn encode_timestamp(prefix: u8, t: u64) -> Vec<u8> {
+        vec![
+            prefix << 4 | ((t >> 30 & 0x07) as u8) << 1 | 1,
+            (t >> 22) as u8,
+            ((t >> 15 & 0x7F) as u8) << 1 | 1,
+            (t >> 7) as u8,
+            ((t & 0x7F) as u8) << 1 | 1,
+        ]
+    }
+
+    fn pes_packet(stream_id: u8, pts: u64, dts: Option<u64>, payload: &[u8]) -> Vec<u8> {
+        let mut header = encode_timestamp(if dts.is_some() { 3 } else { 2 }, pts);
+        if let Some(dts) = dts {
+            header.extend(encode_timestamp(1, dts));
+        }
+        let mut out = vec![0, 0, 1, stream_id, 0, 0, 0x80];
+        out.push(if dts.is_some() { 0xC0 } else { 0x80 });
+        out.push(header.len() as u8);
+        out.extend(header);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn adts(payload: &[u8]) -> Vec<u8> {
+        // AAC LC, 48 kHz, stereo.
+        let len = payload.len() + 7;
+        let mut out = vec![
+            0xFF,
+            0xF1,
+            1 << 6 | 3 << 2,
+            2 << 6 | (len >> 11) as u8,
+            (len >> 3) as u8,
+            (len as u8 & 0x07) << 5 | 0x1F,
+            0xFC,
+        ];
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn transport_stream() -> Vec<u8> {
+        let mut out = Vec::new();
+        packetize(
+            &mut out,
+            0,
+            &[
+                0, 0x00, 0xB0, 13, 0, 1, 0xC1, 0, 0, 0, 1, 0xF0, 0x00, 0, 0, 0, 0,
+            ],
+        );
+        packetize(
+            &mut out,
+            0x1000,
+            &[
+                0, 0x02, 0xB0, 23, 0, 1, 0xC1, 0, 0, 0xE1, 0x00, 0xF0, 0, 0x1B, 0xE1, 0x00, 0xF0,
+                0, 0x0F, 0xE1, 0x01, 0xF0, 0, 0, 0, 0, 0,
+            ],
+        );
+
+        let mut idr = vec![0, 0, 0, 1, 0x09, 0xF0, 0, 0, 0, 1];
+        idr.extend(sps());
+        idr.extend(&[0, 0, 1, 0x68, 0xCE, 0x38, 0x80, 0, 0, 1, 0x65]);
+        idr.extend(vec![0xAA; 300]);
+        packetize(
+            &mut out,
+            0x100,
+            &pes_packet(0xE0, 12_000, Some(9_000), &idr),
+        );
+        packetize(
+            &mut out,
+            0x101,
+            &pes_packet(
+                0xC0,
+                11_000,
+                None,
+                &[adts(&[1; 20]), adts(&[2; 30])].concat(),
+            ),
+        );
+        packetize(
+            &mut out,
+            0x100,
+            &pes_packet(0xE0, 18_000, Some(12_000), &[0, 0, 1, 0x41, 0xBB]),
+        );
+        packetize(
+            &mut out,
+            0x100,
+            &pes_packet(0xE0, 15_000, None, &[0, 0, 1, 0x01, 0xCC]),
+        );
+        out
+    }
+
+    #[test]
+    fn demuxes() {
+        let input = transport_stream();
+        assert!(is_ts(&input));
+
+        let streams = demux(&input).unwrap();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].stream_type, StreamType::H264);
+        assert_eq!(streams[1].stream_type, StreamType::Aac);
+
+        let video = &streams[0].packets;
+        assert_eq!(video.len(), 3);
+        assert_eq!((video[0].pts, video[0].dts), (Some(12_000), Some(9_000)));
+        assert_eq!((video[2].pts, video[2].dts), (Some(15_000), None));
+        assert_eq!(nal_units(&video[0].data).len(), 4);
+        assert_eq!(streams[1].packets[0].data.len(), 64);
+
+        assert_eq!(dimensions(&sps()).unwrap(), (1920, 1080));
+        // Sizes and crops too large for the picture are rejected.
+        assert!(dimensions(&sized_sps(u32::MAX / 8, 67, [0; 4])).is_err());
+        assert!(dimensions(&sized_sps(119, 67, [u32::MAX - 1, 1, 0, 0])).is_err());
+        assert!(dimensions(&sized_sps(119, 67, [0, 0, 600, 0])).is_err());
+        assert_eq!(unwrap(5, Some(WRAP - 5)), WRAP + 5);
+        assert_eq!(unwrap(WRAP - 5, Some(WRAP + 5)), WRAP - 5);
+    }
+
+    #[test]
+    fn remuxes_to_mp4() {
+        let output = to_mp4(&transport_stream()).unwrap();
+        let moov = Atoms::new(&output).find(b"moov").unwrap().unwrap();
+        let traks: Vec<_> = moov
+            .children()
+            .filter_map(|a| a.ok())
+            .filter(|a| &a.kind == b"trak")
+            .collect();
+        assert_eq!(traks.len(), 2);
+
+        let entry = |trak: &Atom| {
+            let stsd = trak
+                .children()
+                .find(b"mdia")
+                .unwrap()
+                .unwrap()
+                .children()
+                .find(b"minf")
+                .unwrap()
+                .unwrap()
+                .children()
+                .find(b"stbl")
+                .unwrap()
+                .unwrap()
+                .children()
+                .find(b"stsd")
+                .unwrap()
+                .unwrap();
+            stsd.body[8..].to_vec()
+        };
+        let avc1 = entry(&traks[0]);
+        assert_eq!(&avc1[4..8], b"avc1");
+        assert_eq!(&avc1[32..36], &[0x07, 0x80, 0x04, 0x38]);
+        let mp4a = entry(&traks[1]);
+        assert_eq!(&mp4a[4..8], b"mp4a");
+
+        // Video starts 1000 ticks after audio, and presents from its first
+        // composition time.
+        let edts = traks[0].children().find(b"edts").unwrap().unwrap();
+        let elst = edts.children().find(b"elst").unwrap().unwrap();
+        let (_, _, body) = elst.full().unwrap();
+        assert_eq!(u32::from_be_bytes(body[..4].try_into().unwrap()), 2);
+        assert_eq!(i64::from_be_bytes(body[32..40].try_into().unwrap()), 3_000);
+        assert!(traks[1].children().find(b"edts").unwrap().is_none());
+
+        let mdat = Atoms::new(&output).find(b"mdat").unwrap().unwrap();
+        // Three pictures of 4-byte lengths and NAL units, and 50 bytes of
+        // audio.
+        assert_eq!(mdat.body.len(), (4 + 301) + (4 + 2) + (4 + 2) + 50);
+        assert!(matches!(
+            to_mp4(&transport_stream()[..188 * 2]),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
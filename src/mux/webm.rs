@@ -0,0 +1,907 @@
+//! Reading and merging WebM and Matroska files.
+//!
+//! YouTube's VP9, AV1 and Opus adaptive formats are each served as a WebM
+//! file holding a single track. [merge] interleaves any number of these
+//! into one file with a fresh set of cues, so that the result is seekable.
+
+use super::{ebml, tags::Metadata, Error};
+
+pub(crate) mod id {
+    //! Matroska element IDs used by the muxer.
+
+    pub const EBML: u32 = 0x1A45_DFA3;
+    pub const EBML_VERSION: u32 = 0x4286;
+    pub const EBML_READ_VERSION: u32 = 0x42F7;
+    pub const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+    pub const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+    pub const DOC_TYPE: u32 = 0x4282;
+    pub const DOC_TYPE_VERSION: u32 = 0x4287;
+    pub const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+
+    pub const SEGMENT: u32 = 0x1853_8067;
+
+    pub const SEEK_HEAD: u32 = 0x114D_9B74;
+    pub const SEEK: u32 = 0x4DBB;
+    pub const SEEK_ID: u32 = 0x53AB;
+    pub const SEEK_POSITION: u32 = 0x53AC;
+
+    pub const INFO: u32 = 0x1549_A966;
+    pub const TIMECODE_SCALE: u32 = 0x2AD7B1;
+    pub const DURATION: u32 = 0x4489;
+    pub const MUXING_APP: u32 = 0x4D80;
+    pub const WRITING_APP: u32 = 0x5741;
+    pub const TITLE: u32 = 0x7BA9;
+
+    pub const TRACKS: u32 = 0x1654_AE6B;
+    pub const TRACK_ENTRY: u32 = 0xAE;
+    pub const TRACK_NUMBER: u32 = 0xD7;
+    pub const TRACK_UID: u32 = 0x73C5;
+    pub const TRACK_TYPE: u32 = 0x83;
+    pub const CODEC_ID: u32 = 0x86;
+
+    pub const CLUSTER: u32 = 0x1F43_B675;
+    pub const TIMECODE: u32 = 0xE7;
+    pub const SIMPLE_BLOCK: u32 = 0xA3;
+    pub const BLOCK_GROUP: u32 = 0xA0;
+    pub const BLOCK: u32 = 0xA1;
+    pub const REFERENCE_BLOCK: u32 = 0xFB;
+
+    pub const CUES: u32 = 0x1C53_BB6B;
+    pub const CUE_POINT: u32 = 0xBB;
+    pub const CUE_TIME: u32 = 0xB3;
+    pub const CUE_TRACK_POSITIONS: u32 = 0xB7;
+    pub const CUE_TRACK: u32 = 0xF7;
+    pub const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+    pub const ATTACHMENTS: u32 = 0x1941_A469;
+    pub const ATTACHED_FILE: u32 = 0x61A7;
+    pub const FILE_DESCRIPTION: u32 = 0x467E;
+    pub const FILE_NAME: u32 = 0x466E;
+    pub const FILE_MIME_TYPE: u32 = 0x4660;
+    pub const FILE_DATA: u32 = 0x465C;
+    pub const FILE_UID: u32 = 0x46AE;
+
+    pub const TAGS: u32 = 0x1254_C367;
+    pub const TAG: u32 = 0x7373;
+    pub const TARGETS: u32 = 0x63C0;
+    pub const TARGET_TYPE_VALUE: u32 = 0x68CA;
+    pub const SIMPLE_TAG: u32 = 0x67C8;
+    pub const TAG_NAME: u32 = 0x45A3;
+    pub const TAG_STRING: u32 = 0x4487;
+
+    pub const VOID: u32 = 0xEC;
+    pub const CRC_32: u32 = 0xBF;
+}
+
+/// Timecode scale of merged output, in nanoseconds per tick.
+const TIMECODE_SCALE: u64 = 1_000_000;
+
+/// Audio-only output starts a new cluster at least this often, in ticks.
+const MAX_CLUSTER_SPAN: i64 = 5_000;
+
+/// Matroska track type for video.
+const TRACK_TYPE_VIDEO: u64 = 1;
+
+/// Codecs permitted in the WebM subset of Matroska.
+const WEBM_CODECS: [&str; 5] = ["V_VP8", "V_VP9", "V_AV1", "A_VORBIS", "A_OPUS"];
+
+/// The flavor of Matroska to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocType {
+    /// WebM, restricted to VP8, VP9, AV1, Vorbis and Opus.
+    WebM,
+
+    /// Unrestricted Matroska.
+    Matroska,
+}
+
+impl DocType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DocType::WebM => "webm",
+            DocType::Matroska => "matroska",
+        }
+    }
+}
+
+/// A track read from an input file.
+#[derive(Debug, Clone)]
+pub struct Track {
+    number: u64,
+    kind: u64,
+    codec_id: String,
+
+    // Children of the TrackEntry, minus its number and UID, which are
+    // reassigned on output.
+    rest: Vec<u8>,
+}
+
+impl Track {
+    /// Number of the track within its file.
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// Matroska codec ID, such as `V_VP9` or `A_OPUS`.
+    pub fn codec_id(&self) -> &str {
+        &self.codec_id
+    }
+
+    /// Whether the track holds video.
+    pub fn is_video(&self) -> bool {
+        self.kind == TRACK_TYPE_VIDEO
+    }
+}
+
+/// A single (Simple)Block with its absolute timestamp.
+#[derive(Debug, Clone)]
+struct Block<'a> {
+    /// Timestamp in nanoseconds.
+    time: i64,
+    track: u64,
+    keyframe: bool,
+
+    /// Set for blocks stored in a BlockGroup.
+    group: Option<ebml::Element<'a>>,
+
+    /// Flags and frame data following the block header.
+    payload: &'a [u8],
+}
+
+/// A parsed WebM or Matroska file.
+#[derive(Debug, Clone)]
+pub struct Document<'a> {
+    timecode_scale: u64,
+    duration: Option<f64>,
+    tracks: Vec<Track>,
+    blocks: Vec<Block<'a>>,
+}
+
+impl<'a> Document<'a> {
+    /// Parse a complete WebM or Matroska file.
+    pub fn parse(buf: &'a [u8]) -> Result<Self, Error> {
+        let mut top = ebml::Reader::new(buf);
+        match top.next_element()? {
+            Some(el) if el.id == id::EBML => {}
+            _ => return Err(Error::Malformed("missing EBML header")),
+        }
+        let segment = top
+            .find(id::SEGMENT)?
+            .ok_or(Error::Malformed("missing Segment"))?;
+
+        let mut doc = Document {
+            timecode_scale: TIMECODE_SCALE,
+            duration: None,
+            tracks: Vec::new(),
+            blocks: Vec::new(),
+        };
+
+        for el in segment.children() {
+            let el = el?;
+            match el.id {
+                id::INFO => {
+                    for child in el.children() {
+                        let child = child?;
+                        match child.id {
+                            id::TIMECODE_SCALE => doc.timecode_scale = child.uint()?,
+                            id::DURATION => doc.duration = Some(child.float()?),
+                            _ => {}
+                        }
+                    }
+                }
+                id::TRACKS => {
+                    for entry in el.children() {
+                        let entry = entry?;
+                        if entry.id == id::TRACK_ENTRY {
+                            doc.tracks.push(parse_track(&entry)?);
+                        }
+                    }
+                }
+                id::CLUSTER => doc.parse_cluster(&el)?,
+                _ => {}
+            }
+        }
+
+        if doc.tracks.is_empty() {
+            return Err(Error::Malformed("no tracks"));
+        }
+        Ok(doc)
+    }
+
+    /// Tracks declared by the file.
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// Duration of the file in nanoseconds, as declared or as implied by
+    /// its last block.
+    pub fn duration(&self) -> i64 {
+        match self.duration {
+            Some(d) => (d * self.timecode_scale as f64) as i64,
+            None => self.blocks.iter().map(|b| b.time).max().unwrap_or(0),
+        }
+    }
+
+    fn parse_cluster(&mut self, cluster: &ebml::Element<'a>) -> Result<(), Error> {
+        let scale = self.timecode_scale as i64;
+        let mut base = 0;
+        for el in cluster.children() {
+            let el = el?;
+            match el.id {
+                id::TIMECODE => base = el.uint()? as i64,
+                id::SIMPLE_BLOCK => {
+                    let (track, rel, payload) = parse_block(el.body)?;
+                    self.blocks.push(Block {
+                        time: (base + rel) * scale,
+                        track,
+                        keyframe: payload[0] & 0x80 != 0,
+                        group: None,
+                        payload,
+                    });
+                }
+                id::BLOCK_GROUP => {
+                    let mut block = None;
+                    let mut keyframe = true;
+                    for child in el.children() {
+                        let child = child?;
+                        match child.id {
+                            id::BLOCK => block = Some(parse_block(child.body)?),
+                            id::REFERENCE_BLOCK => keyframe = false,
+                            _ => {}
+                        }
+                    }
+                    let (track, rel, payload) =
+                        block.ok_or(Error::Malformed("BlockGroup without a Block"))?;
+                    self.blocks.push(Block {
+                        time: (base + rel) * scale,
+                        track,
+                        keyframe,
+                        group: Some(el),
+                        payload,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_track(entry: &ebml::Element) -> Result<Track, Error> {
+    let mut track = Track {
+        number: 0,
+        kind: 0,
+        codec_id: String::new(),
+        rest: Vec::new(),
+    };
+    for child in entry.children() {
+        let child = child?;
+        match child.id {
+            id::TRACK_NUMBER => {
+                track.number = child.uint()?;
+                continue;
+            }
+            id::TRACK_UID => continue,
+            id::TRACK_TYPE => track.kind = child.uint()?,
+            id::CODEC_ID => track.codec_id = child.string()?,
+            _ => {}
+        }
+        track.rest.extend_from_slice(raw(entry, &child));
+    }
+    if track.number == 0 {
+        return Err(Error::Malformed("TrackEntry without a TrackNumber"));
+    }
+    Ok(track)
+}
+
+/// The encoded bytes of `child`, header included.
+fn raw<'a>(parent: &ebml::Element<'a>, child: &ebml::Element) -> &'a [u8] {
+    &parent.body[child.offset..child.offset + child.len()]
+}
+
+/// Split a Block or SimpleBlock into its track number, relative timecode
+/// and the flags and frames that follow.
+fn parse_block(body: &[u8]) -> Result<(u64, i64, &[u8]), Error> {
+    let (track, len) = ebml::read_size(body)?;
+    if body.len() < len + 3 {
+        return Err(Error::Malformed("truncated block"));
+    }
+    let rel = i16::from_be_bytes([body[len], body[len + 1]]);
+    Ok((track, i64::from(rel), &body[len + 2..]))
+}
+
+/// Append a Block or SimpleBlock body for the given track and relative
+/// timecode.
+fn write_block_body(out: &mut Vec<u8>, track: u64, rel: i16, payload: &[u8]) {
+    ebml::write_size(out, track);
+    out.extend_from_slice(&rel.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Rewrite a BlockGroup so that its Block refers to a new track and
+/// relative timecode, keeping every other child intact.
+fn write_block_group(
+    out: &mut Vec<u8>,
+    group: &ebml::Element,
+    track: u64,
+    rel: i16,
+    payload: &[u8],
+) -> Result<(), Error> {
+    let mut body = Vec::new();
+    for child in group.children() {
+        let child = child?;
+        if child.id == id::BLOCK {
+            let mut block = Vec::new();
+            write_block_body(&mut block, track, rel, payload);
+            ebml::write_element(&mut body, id::BLOCK, &block);
+        } else {
+            body.extend_from_slice(raw(group, &child));
+        }
+    }
+    ebml::write_element(out, id::BLOCK_GROUP, &body);
+    Ok(())
+}
+
+/// Merge the tracks of several WebM or Matroska files into one file.
+///
+/// Tracks are renumbered in input order, blocks are interleaved by
+/// timestamp, and clusters are started on every keyframe of the first video
+/// track so that the generated cues allow seeking.
+pub fn merge(inputs: &[&[u8]], doc_type: DocType) -> Result<Vec<u8>, Error> {
+    let docs = inputs
+        .iter()
+        .map(|i| Document::parse(i))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Assign output track numbers and collect every block.
+    let mut tracks = Vec::new();
+    let mut blocks = Vec::new();
+    for doc in &docs {
+        for track in &doc.tracks {
+            if doc_type == DocType::WebM && !WEBM_CODECS.contains(&track.codec_id.as_str()) {
+                return Err(Error::Unsupported(format!(
+                    "codec {} cannot be stored in WebM",
+                    track.codec_id
+                )));
+            }
+            let number = tracks.len() as u64 + 1;
+            tracks.push((number, track));
+            blocks.extend(
+                doc.blocks
+                    .iter()
+                    .filter(|b| b.track == track.number)
+                    .map(|b| (number, b)),
+            );
+        }
+    }
+    // Stable, so that blocks sharing a timestamp keep their input order.
+    blocks.sort_by_key(|(_, b)| b.time);
+
+    let cue_track = tracks
+        .iter()
+        .find(|(_, t)| t.is_video())
+        .or_else(|| tracks.first())
+        .map(|(n, _)| *n)
+        .ok_or(Error::Malformed("no tracks"))?;
+    let cue_on_keyframes = tracks.iter().any(|(_, t)| t.is_video());
+
+    let duration = docs.iter().map(|d| d.duration()).max().unwrap_or(0);
+
+    let mut info = Vec::new();
+    ebml::write_uint(&mut info, id::TIMECODE_SCALE, TIMECODE_SCALE);
+    ebml::write_string(&mut info, id::MUXING_APP, "maguro");
+    ebml::write_string(&mut info, id::WRITING_APP, "maguro");
+    ebml::write_float(
+        &mut info,
+        id::DURATION,
+        duration as f64 / TIMECODE_SCALE as f64,
+    );
+    let info = element(id::INFO, &info);
+
+    let mut entries = Vec::new();
+    for (number, track) in &tracks {
+        let mut entry = Vec::new();
+        ebml::write_uint(&mut entry, id::TRACK_NUMBER, *number);
+        ebml::write_uint(&mut entry, id::TRACK_UID, *number);
+        entry.extend_from_slice(&track.rest);
+        ebml::write_element(&mut entries, id::TRACK_ENTRY, &entry);
+    }
+    let tracks = element(id::TRACKS, &entries);
+
+    // Clusters, along with (time, offset into the cluster data) for cues.
+    let mut clusters = Vec::new();
+    let mut cue_points = Vec::new();
+    let mut current: Option<(i64, Vec<u8>)> = None;
+    for (track, block) in blocks {
+        let time = block.time / TIMECODE_SCALE as i64;
+
+        let starts_cue = track == cue_track
+            && if cue_on_keyframes {
+                block.keyframe
+            } else {
+                current
+                    .as_ref()
+                    .is_none_or(|(start, _)| time - start >= MAX_CLUSTER_SPAN)
+            };
+        let overflows = current
+            .as_ref()
+            .is_none_or(|(start, _)| time - start > i64::from(i16::MAX) || time < *start);
+
+        if starts_cue || overflows {
+            if let Some((start, body)) = current.take() {
+                flush_cluster(&mut clusters, start, &body);
+            }
+            if starts_cue {
+                cue_points.push((time, clusters.len() as u64));
+            }
+            current = Some((time, Vec::new()));
+        }
+
+        let (start, body) = current.as_mut().unwrap();
+        let rel = (time - *start) as i16;
+        match &block.group {
+            Some(group) => write_block_group(body, group, track, rel, block.payload)?,
+            None => {
+                let mut simple = Vec::new();
+                write_block_body(&mut simple, track, rel, block.payload);
+                ebml::write_element(body, id::SIMPLE_BLOCK, &simple);
+            }
+        }
+    }
+    if let Some((start, body)) = current.take() {
+        flush_cluster(&mut clusters, start, &body);
+    }
+
+    // The SeekHead is written first with fixed-width positions, so its
+    // length is known before any of the positions it records.
+    let seek_head_len = seek_head(&[(id::INFO, 0), (id::TRACKS, 0), (id::CUES, 0)]).len() as u64;
+    let info_pos = seek_head_len;
+    let tracks_pos = info_pos + info.len() as u64;
+    let clusters_pos = tracks_pos + tracks.len() as u64;
+    let cues_pos = clusters_pos + clusters.len() as u64;
+
+    let mut points = Vec::new();
+    for (time, offset) in cue_points {
+        let mut positions = Vec::new();
+        ebml::write_uint(&mut positions, id::CUE_TRACK, cue_track);
+        ebml::write_uint(&mut positions, id::CUE_CLUSTER_POSITION, clusters_pos + offset);
+
+        let mut point = Vec::new();
+        ebml::write_uint(&mut point, id::CUE_TIME, time as u64);
+        ebml::write_element(&mut point, id::CUE_TRACK_POSITIONS, &positions);
+        ebml::write_element(&mut points, id::CUE_POINT, &point);
+    }
+    let cues = element(id::CUES, &points);
+
+    let mut segment = seek_head(&[
+        (id::INFO, info_pos),
+        (id::TRACKS, tracks_pos),
+        (id::CUES, cues_pos),
+    ]);
+    segment.extend_from_slice(&info);
+    segment.extend_from_slice(&tracks);
+    segment.extend_from_slice(&clusters);
+    segment.extend_from_slice(&cues);
+
+    let mut out = header(doc_type);
+    ebml::write_element(&mut out, id::SEGMENT, &segment);
+    Ok(out)
+}
+
+fn element(id: u32, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    ebml::write_element(&mut out, id, body);
+    out
+}
+
+fn flush_cluster(out: &mut Vec<u8>, start: i64, blocks: &[u8]) {
+    let mut body = Vec::new();
+    ebml::write_uint(&mut body, id::TIMECODE, start.max(0) as u64);
+    body.extend_from_slice(blocks);
+    ebml::write_element(out, id::CLUSTER, &body);
+}
+
+fn seek_head(entries: &[(u32, u64)]) -> Vec<u8> {
+    let mut seeks = Vec::new();
+    for (target, position) in entries {
+        let mut target_id = Vec::new();
+        ebml::write_id(&mut target_id, *target);
+
+        let mut seek = Vec::new();
+        ebml::write_element(&mut seek, id::SEEK_ID, &target_id);
+        ebml::write_uint_fixed(&mut seek, id::SEEK_POSITION, *position);
+        ebml::write_element(&mut seeks, id::SEEK, &seek);
+    }
+    element(id::SEEK_HEAD, &seeks)
+}
+
+#[cfg(feature = "client")]
+/// The headers of `init` followed by the start of a Segment of unknown
+/// size, to which clusters cut from the same file can be appended.
+///
+/// Seek entries, cues and the declared duration are dropped, as they
+/// describe the whole file rather than the clusters that follow.
+pub(crate) fn clip_header(init: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut top = ebml::Reader::new(init);
+    let header = match top.next_element()? {
+        Some(el) if el.id == id::EBML => el,
+        _ => return Err(Error::Malformed("missing EBML header")),
+    };
+    let segment = top
+        .find(id::SEGMENT)?
+        .ok_or(Error::Malformed("missing Segment"))?;
+
+    let mut out = init[header.offset..header.offset + header.len()].to_vec();
+    ebml::write_id(&mut out, id::SEGMENT);
+    ebml::write_size_exact(&mut out, ebml::UNKNOWN_SIZE, 8);
+    for el in segment.children() {
+        let el = el?;
+        match el.id {
+            id::INFO => {
+                let mut info = Vec::new();
+                for child in el.children() {
+                    let child = child?;
+                    if child.id != id::DURATION {
+                        info.extend_from_slice(raw(&el, &child));
+                    }
+                }
+                ebml::write_element(&mut out, id::INFO, &info);
+            }
+            id::TRACKS => out.extend_from_slice(raw(&segment, &el)),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// The EBML header for a file of the given type.
+pub(crate) fn header(doc_type: DocType) -> Vec<u8> {
+    let mut body = Vec::new();
+    ebml::write_uint(&mut body, id::EBML_VERSION, 1);
+    ebml::write_uint(&mut body, id::EBML_READ_VERSION, 1);
+    ebml::write_uint(&mut body, id::EBML_MAX_ID_LENGTH, 4);
+    ebml::write_uint(&mut body, id::EBML_MAX_SIZE_LENGTH, 8);
+    ebml::write_string(&mut body, id::DOC_TYPE, doc_type.as_str());
+    ebml::write_uint(&mut body, id::DOC_TYPE_VERSION, 4);
+    ebml::write_uint(&mut body, id::DOC_TYPE_READ_VERSION, 2);
+    element(id::EBML, &body)
+}
+
+/// A top-level element of a segment being rewritten by [tag].
+enum Piece<'a> {
+    /// An element copied as-is from its old offset in the segment.
+    Moved(usize, &'a [u8]),
+
+    /// A newly written element.
+    New(Vec<u8>),
+
+    /// The Cues, whose cluster positions must follow the clusters.
+    Cues(ebml::Element<'a>),
+}
+
+impl<'a> Piece<'a> {
+    fn id(&self) -> Result<u32, Error> {
+        match self {
+            Piece::Moved(_, bytes) => Ok(ebml::read_id(bytes)?.0),
+            Piece::New(bytes) => Ok(ebml::read_id(bytes)?.0),
+            Piece::Cues(_) => Ok(id::CUES),
+        }
+    }
+
+    /// Encode the piece, given a translation for old cluster positions.
+    fn encode(&self, relocate: &dyn Fn(u64) -> u64) -> Result<Vec<u8>, Error> {
+        match self {
+            Piece::Moved(_, bytes) => Ok(bytes.to_vec()),
+            Piece::New(bytes) => Ok(bytes.clone()),
+            Piece::Cues(cues) => {
+                let mut points = Vec::new();
+                for point in cues.children() {
+                    let point = point?;
+                    let mut body = Vec::new();
+                    for child in point.children() {
+                        let child = child?;
+                        if child.id != id::CUE_TRACK_POSITIONS {
+                            body.extend_from_slice(raw(&point, &child));
+                            continue;
+                        }
+                        let mut positions = Vec::new();
+                        for p in child.children() {
+                            let p = p?;
+                            if p.id == id::CUE_CLUSTER_POSITION {
+                                ebml::write_uint_fixed(
+                                    &mut positions,
+                                    id::CUE_CLUSTER_POSITION,
+                                    relocate(p.uint()?),
+                                );
+                            } else {
+                                positions.extend_from_slice(raw(&child, &p));
+                            }
+                        }
+                        ebml::write_element(&mut body, id::CUE_TRACK_POSITIONS, &positions);
+                    }
+                    ebml::write_element(&mut points, id::CUE_POINT, &body);
+                }
+                Ok(element(id::CUES, &points))
+            }
+        }
+    }
+}
+
+/// Embed `metadata` into a Matroska or WebM file as Tags, with the cover
+/// art as an attachment.
+///
+/// WebM does not allow attachments, so the cover is only written to
+/// Matroska files. Existing tags and attachments are replaced.
+pub fn tag(input: &[u8], metadata: &Metadata) -> Result<Vec<u8>, Error> {
+    let mut top = ebml::Reader::new(input);
+    let header = match top.next_element()? {
+        Some(el) if el.id == id::EBML => el,
+        _ => return Err(Error::Malformed("missing EBML header")),
+    };
+    let doc_type = match header.children().find(id::DOC_TYPE)? {
+        Some(el) => el.string()?,
+        None => DocType::Matroska.as_str().into(),
+    };
+    let segment = top
+        .find(id::SEGMENT)?
+        .ok_or(Error::Malformed("missing Segment"))?;
+
+    // Headers first, then the new tags, then everything else in its
+    // original order.
+    let mut pieces = Vec::new();
+    let mut rest = Vec::new();
+    for el in segment.children() {
+        let el = el?;
+        match el.id {
+            id::INFO => {
+                let mut info = Vec::new();
+                for child in el.children() {
+                    let child = child?;
+                    if child.id != id::TITLE {
+                        info.extend_from_slice(raw(&el, &child));
+                    }
+                }
+                if let Some(title) = &metadata.title {
+                    ebml::write_string(&mut info, id::TITLE, title);
+                }
+                pieces.push(Piece::New(element(id::INFO, &info)));
+            }
+            id::TRACKS => pieces.push(Piece::Moved(el.offset, raw(&segment, &el))),
+            id::SEEK_HEAD | id::TAGS | id::ATTACHMENTS | id::VOID | id::CRC_32 => {}
+            id::CUES => rest.push(Piece::Cues(el)),
+            _ => rest.push(Piece::Moved(el.offset, raw(&segment, &el))),
+        }
+    }
+
+    if let Some(cover) = metadata.cover.as_ref() {
+        if doc_type != DocType::WebM.as_str() {
+            let mut file = Vec::new();
+            ebml::write_string(&mut file, id::FILE_DESCRIPTION, "Cover");
+            ebml::write_string(
+                &mut file,
+                id::FILE_NAME,
+                &format!("cover.{}", cover.kind.extension()),
+            );
+            ebml::write_string(&mut file, id::FILE_MIME_TYPE, cover.kind.mime_type());
+            ebml::write_element(&mut file, id::FILE_DATA, &cover.data);
+            ebml::write_uint(&mut file, id::FILE_UID, 1);
+            pieces.push(Piece::New(element(
+                id::ATTACHMENTS,
+                &element(id::ATTACHED_FILE, &file),
+            )));
+        }
+    }
+
+    let mut targets = Vec::new();
+    // 50 targets the whole movie, album or episode.
+    ebml::write_uint(&mut targets, id::TARGET_TYPE_VALUE, 50);
+    let mut tag = element(id::TARGETS, &targets);
+    for (name, value) in metadata.fields() {
+        let mut simple = Vec::new();
+        ebml::write_string(
+            &mut simple,
+            id::TAG_NAME,
+            if name == "DATE" {
+                "DATE_RELEASED"
+            } else {
+                name
+            },
+        );
+        ebml::write_string(&mut simple, id::TAG_STRING, value);
+        ebml::write_element(&mut tag, id::SIMPLE_TAG, &simple);
+    }
+    pieces.push(Piece::New(element(id::TAGS, &element(id::TAG, &tag))));
+    pieces.extend(rest);
+
+    // Every top-level element other than clusters gets a seek entry. Seek
+    // and cue positions are written with a fixed width, so the layout can be
+    // settled before any of them are known.
+    let mut targets = Vec::new();
+    for piece in &pieces {
+        let target = piece.id()?;
+        if target != id::CLUSTER && !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    let placeholder: Vec<_> = targets.iter().map(|t| (*t, 0)).collect();
+    let mut pos = seek_head(&placeholder).len() as u64;
+
+    let mut positions = Vec::new();
+    for piece in &pieces {
+        positions.push(pos);
+        pos += piece.encode(&|_| 0)?.len() as u64;
+    }
+
+    // Old cluster positions translate to wherever their element moved.
+    let moved: Vec<(u64, u64, u64)> = pieces
+        .iter()
+        .zip(&positions)
+        .filter_map(|(piece, new)| match piece {
+            Piece::Moved(old, bytes) => Some((*old as u64, bytes.len() as u64, *new)),
+            _ => None,
+        })
+        .collect();
+    let relocate = |old: u64| {
+        moved
+            .iter()
+            .find(|(start, len, _)| (*start..start + len).contains(&old))
+            .map_or(old, |(start, _, new)| new + (old - start))
+    };
+
+    let seeks: Vec<_> = targets
+        .iter()
+        .map(|t| {
+            let at = pieces.iter().position(|p| p.id().ok() == Some(*t)).unwrap();
+            (*t, positions[at])
+        })
+        .collect();
+    let mut body = seek_head(&seeks);
+    for piece in &pieces {
+        body.extend_from_slice(&piece.encode(&relocate)?);
+    }
+
+    let end = segment.offset + segment.len();
+    let mut out = input[..segment.offset].to_vec();
+    ebml::write_element(&mut out, id::SEGMENT, &body);
+    out.extend_from_slice(&input[end..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a single-track file with one block every `step` milliseconds.
+    fn sample(codec: &str, kind: u64, step: i64, keyframe_every: usize) -> Vec<u8> {
+        let mut entry = Vec::new();
+        ebml::write_uint(&mut entry, id::TRACK_NUMBER, 1);
+        ebml::write_uint(&mut entry, id::TRACK_UID, 1);
+        ebml::write_uint(&mut entry, id::TRACK_TYPE, kind);
+        ebml::write_string(&mut entry, id::CODEC_ID, codec);
+        let mut tracks = Vec::new();
+        ebml::write_element(&mut tracks, id::TRACK_ENTRY, &entry);
+
+        let mut cluster = Vec::new();
+        ebml::write_uint(&mut cluster, id::TIMECODE, 0);
+        for i in 0..20 {
+            let flags = if i % keyframe_every == 0 { 0x80 } else { 0 };
+            let mut block = Vec::new();
+            write_block_body(&mut block, 1, (i as i64 * step) as i16, &[flags, i as u8]);
+            ebml::write_element(&mut cluster, id::SIMPLE_BLOCK, &block);
+        }
+
+        let mut info = Vec::new();
+        ebml::write_uint(&mut info, id::TIMECODE_SCALE, TIMECODE_SCALE);
+
+        let mut segment = Vec::new();
+        ebml::write_element(&mut segment, id::INFO, &info);
+        ebml::write_element(&mut segment, id::TRACKS, &tracks);
+        ebml::write_element(&mut segment, id::CLUSTER, &cluster);
+
+        let mut out = header(DocType::WebM);
+        ebml::write_element(&mut out, id::SEGMENT, &segment);
+        out
+    }
+
+    #[test]
+    fn merges_video_and_audio() {
+        let video = sample("V_VP9", TRACK_TYPE_VIDEO, 40, 5);
+        let audio = sample("A_OPUS", 2, 20, 1);
+        let merged = merge(&[&video, &audio], DocType::WebM).unwrap();
+
+        let doc = Document::parse(&merged).unwrap();
+        assert_eq!(doc.tracks().len(), 2);
+        assert_eq!(doc.tracks()[0].codec_id(), "V_VP9");
+        assert_eq!(doc.tracks()[1].number(), 2);
+        assert_eq!(doc.blocks.len(), 40);
+        assert!(doc.blocks.windows(2).all(|w| w[0].time <= w[1].time));
+
+        // One cue per video keyframe, each pointing at a cluster.
+        let segment = ebml::Reader::new(&merged).find(id::SEGMENT).unwrap().unwrap();
+        let cues = segment.children().find(id::CUES).unwrap().unwrap();
+        let mut count = 0;
+        for point in cues.children() {
+            let positions = point
+                .unwrap()
+                .children()
+                .find(id::CUE_TRACK_POSITIONS)
+                .unwrap()
+                .unwrap();
+            let position = positions
+                .children()
+                .find(id::CUE_CLUSTER_POSITION)
+                .unwrap()
+                .unwrap()
+                .uint()
+                .unwrap() as usize;
+            let (cluster, _) = ebml::read_id(&segment.body[position..]).unwrap();
+            assert_eq!(cluster, id::CLUSTER);
+            count += 1;
+        }
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn webm_rejects_foreign_codecs() {
+        let video = sample("V_MPEG4/ISO/AVC", TRACK_TYPE_VIDEO, 40, 5);
+        assert!(merge(&[&video], DocType::WebM).is_err());
+        assert!(merge(&[&video], DocType::Matroska).is_ok());
+    }
+
+    #[test]
+    fn tags_keep_cues_valid() {
+        let video = sample("V_VP9", TRACK_TYPE_VIDEO, 40, 5);
+        let merged = merge(&[&video], DocType::Matroska).unwrap();
+        let metadata = Metadata {
+            title: Some("Title".into()),
+            artist: Some("Artist".into()),
+            cover: Some(super::super::tags::Cover {
+                kind: super::super::tags::ImageType::Jpeg,
+                width: 1,
+                height: 1,
+                data: vec![0xFF, 0xD8, 0xFF, 0x00],
+            }),
+            ..Default::default()
+        };
+        let tagged = tag(&merged, &metadata).unwrap();
+        assert_eq!(tag(&tagged, &metadata).unwrap(), tagged);
+
+        let segment = ebml::Reader::new(&tagged)
+            .find(id::SEGMENT)
+            .unwrap()
+            .unwrap();
+        let tags = segment.children().find(id::TAGS).unwrap().unwrap();
+        assert!(tags.body.windows(6).any(|w| w == b"Artist"));
+        assert!(segment.children().find(id::ATTACHMENTS).unwrap().is_some());
+
+        // Seek entries and cues still point at the elements they name.
+        let seek_head = segment.children().find(id::SEEK_HEAD).unwrap().unwrap();
+        for seek in seek_head.children() {
+            let seek = seek.unwrap();
+            let target = seek.children().find(id::SEEK_ID).unwrap().unwrap();
+            let position = seek.children().find(id::SEEK_POSITION).unwrap().unwrap();
+            let (found, _) =
+                ebml::read_id(&segment.body[position.uint().unwrap() as usize..]).unwrap();
+            assert_eq!(ebml::read_id(target.body).unwrap().0, found);
+        }
+        let cues = segment.children().find(id::CUES).unwrap().unwrap();
+        for point in cues.children() {
+            let position = point
+                .unwrap()
+                .children()
+                .find(id::CUE_TRACK_POSITIONS)
+                .unwrap()
+                .unwrap()
+                .children()
+                .find(id::CUE_CLUSTER_POSITION)
+                .unwrap()
+                .unwrap()
+                .uint()
+                .unwrap() as usize;
+            assert_eq!(
+                ebml::read_id(&segment.body[position..]).unwrap().0,
+                id::CLUSTER
+            );
+        }
+    }
+}
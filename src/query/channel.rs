@@ -0,0 +1,407 @@
+//! Listing the videos of YouTube channels.
+//!
+//! However a channel is referred to, by handle or by a legacy custom URL or
+//! username, it is first resolved to its canonical `UC` ID. Its videos,
+//! shorts and past live streams are then listed from the corresponding
+//! [Tabs](Tab) of its page, each paged through with continuation tokens as
+//! a [Playlist](super::Playlist) is.
+
+use serde_json::Value;
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display},
+    time::Duration,
+};
+
+#[cfg(feature = "client")]
+use log::info;
+#[cfg(feature = "client")]
+use std::{collections::HashSet, error};
+
+use super::{innertube, Error};
+use crate::Thumbnail;
+
+#[cfg(feature = "client")]
+use super::ChannelRef;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A tab of a channel's page listing some of its videos.
+pub enum Tab {
+    Videos,
+    Shorts,
+    Live,
+}
+
+impl Tab {
+    /// Every tab, in the order they appear on a channel's page.
+    pub const ALL: [Tab; 3] = [Tab::Videos, Tab::Shorts, Tab::Live];
+
+    #[cfg(feature = "client")]
+    /// The browse parameters selecting the tab.
+    fn params(self) -> &'static str {
+        match self {
+            Tab::Videos => "EgZ2aWRlb3PyBgQKAjoA",
+            Tab::Shorts => "EgZzaG9ydHPyBgUKA5oBAA==",
+            Tab::Live => "EgdzdHJlYW1z8gYECgJ6AA==",
+        }
+    }
+
+    /// The tab with the given English title.
+    fn from_title(title: &str) -> Option<Self> {
+        match title {
+            "Videos" => Some(Tab::Videos),
+            "Shorts" => Some(Tab::Shorts),
+            "Live" => Some(Tab::Live),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Tab {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tab::Videos => write!(f, "Videos"),
+            Tab::Shorts => write!(f, "Shorts"),
+            Tab::Live => write!(f, "Live"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A video listed on a [Channel].
+pub struct ChannelEntry {
+    id: String,
+    title: String,
+    duration: Option<Duration>,
+    tab: Tab,
+}
+
+impl ChannelEntry {
+    /// ID of the video.
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Title of the video.
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    /// Length of the video, if shown. Shorts and live streams still in
+    /// progress have none.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// The [Tab] the video is listed under.
+    pub fn tab(&self) -> Tab {
+        self.tab
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// A YouTube channel and the videos listed on it, newest first within each
+/// [Tab].
+pub struct Channel {
+    id: String,
+    title: String,
+    description: String,
+    avatar: Option<Thumbnail>,
+    banner: Option<Thumbnail>,
+    subscribers: Option<String>,
+    entries: Vec<ChannelEntry>,
+}
+
+impl TryFrom<&str> for Channel {
+    type Error = Error;
+
+    /// Attempt to parse the JSON of a channel's page, as returned by
+    /// InnerTube's `browse`, into a [Channel]. Only the first page of the
+    /// selected [Tab] is listed.
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let response: Value = serde_json::from_str(s)
+            .map_err(|e| Error::Malformed(format!("channel is not JSON: {}", e)))?;
+        let mut channel = Self::from_response(&response)?;
+        if let Some(tab) = selected_tab(&response) {
+            channel.extend(&response, tab);
+        }
+        Ok(channel)
+    }
+}
+
+impl Channel {
+    #[cfg(feature = "client")]
+    /// Fetches the channel, listing every video under each of `tabs`.
+    pub async fn from_ref(
+        channel: &ChannelRef,
+        tabs: &[Tab],
+    ) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        let id = channel.resolve().await?;
+        let mut channel = Self::from_response(&innertube::browse(Some(&id), None, None).await?)?;
+        for &tab in tabs {
+            channel.fetch_tab(tab).await?;
+        }
+        Ok(channel)
+    }
+
+    #[cfg(feature = "client")]
+    /// Adds every video listed under `tab`, paging through them all.
+    async fn fetch_tab(&mut self, tab: Tab) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        let response = innertube::browse(Some(&self.id), Some(tab.params()), None).await?;
+        // Channels without such videos show another tab instead.
+        if selected_tab(&response) != Some(tab) {
+            info!("Channel {} has no {} tab", self.id, tab);
+            return Ok(());
+        }
+
+        let mut next = self.extend(&response, tab);
+        let mut seen = HashSet::new();
+        while let Some(token) = next.filter(|t| seen.insert(t.clone())) {
+            info!(
+                "Fetched {} videos from channel {}",
+                self.entries.len(),
+                self.id
+            );
+            let response = innertube::browse(None, None, Some(&token)).await?;
+            next = self.extend(&response, tab);
+        }
+        Ok(())
+    }
+
+    /// Reads the metadata of a channel from its page.
+    fn from_response(response: &Value) -> Result<Self, Error> {
+        let metadata = match innertube::find(response, "channelMetadataRenderer") {
+            Some(metadata) => metadata,
+            None => {
+                return Err(Error::Unavailable(
+                    innertube::alert(response)
+                        .unwrap_or_else(|| "the channel is unavailable".to_string()),
+                ))
+            }
+        };
+        let header = innertube::find(response, "c4TabbedHeaderRenderer");
+        let string = |v: Option<&Value>| v.and_then(Value::as_str).unwrap_or_default().to_string();
+
+        let banner = header
+            .and_then(|h| h.get("banner"))
+            .and_then(|b| b.get("thumbnails"))
+            .or_else(|| {
+                innertube::find(response, "imageBannerViewModel")
+                    .and_then(|b| innertube::find(b, "sources"))
+            });
+        let subscribers = header
+            .and_then(|h| h.get("subscriberCountText"))
+            .and_then(innertube::text)
+            .or_else(|| {
+                innertube::find_all(response, "content")
+                    .into_iter()
+                    .filter_map(Value::as_str)
+                    .find(|c| c.contains("subscriber"))
+                    .map(String::from)
+            });
+
+        Ok(Self {
+            id: string(metadata.get("externalId")),
+            title: string(metadata.get("title")),
+            description: string(metadata.get("description")),
+            avatar: metadata
+                .get("avatar")
+                .and_then(|a| a.get("thumbnails"))
+                .and_then(largest),
+            banner: banner.and_then(largest),
+            subscribers,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Adds the videos of a page of `tab`, returning the token for the next
+    /// page, if there is one.
+    fn extend(&mut self, response: &Value, tab: Tab) -> Option<String> {
+        let key = match tab {
+            Tab::Shorts => "reelItemRenderer",
+            Tab::Videos | Tab::Live => "videoRenderer",
+        };
+        for video in innertube::find_all(response, key) {
+            let id = match video.get("videoId").and_then(Value::as_str) {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+            self.entries.push(ChannelEntry {
+                id,
+                title: video
+                    .get("title")
+                    .or_else(|| video.get("headline"))
+                    .and_then(innertube::text)
+                    .unwrap_or_default(),
+                duration: video.get("lengthText").and_then(innertube::duration),
+                tab,
+            });
+        }
+
+        // Shorts are more often listed through a view model.
+        if tab == Tab::Shorts {
+            for short in innertube::find_all(response, "shortsLockupViewModel") {
+                let id = match innertube::find(short, "reelWatchEndpoint")
+                    .and_then(|e| e.get("videoId"))
+                    .and_then(Value::as_str)
+                {
+                    Some(id) => id.to_string(),
+                    None => continue,
+                };
+                self.entries.push(ChannelEntry {
+                    id,
+                    title: innertube::find(short, "primaryText")
+                        .and_then(innertube::text)
+                        .unwrap_or_default(),
+                    duration: None,
+                    tab,
+                });
+            }
+        }
+
+        innertube::continuation(response)
+    }
+
+    /// Canonical ID of the channel, beginning with `UC`.
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Name of the channel.
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    /// Description of the channel.
+    pub fn description(&self) -> String {
+        self.description.clone()
+    }
+
+    /// The channel's largest avatar image.
+    pub fn avatar(&self) -> Option<Thumbnail> {
+        self.avatar.clone()
+    }
+
+    /// The channel's largest banner image, if it has one.
+    pub fn banner(&self) -> Option<Thumbnail> {
+        self.banner.clone()
+    }
+
+    /// Subscriber count as YouTube shows it, such as `2.41M subscribers`.
+    pub fn subscribers(&self) -> Option<String> {
+        self.subscribers.clone()
+    }
+
+    /// The videos listed on the channel, in the order of the tabs fetched.
+    pub fn entries(&self) -> Vec<ChannelEntry> {
+        self.entries.clone()
+    }
+}
+
+#[cfg(feature = "client")]
+impl ChannelRef {
+    /// Resolves the channel to its canonical ID, beginning with `UC`.
+    pub async fn resolve(&self) -> Result<String, Box<dyn error::Error + Send + Sync>> {
+        if let ChannelRef::Id(id) = self {
+            return Ok(id.clone());
+        }
+        match innertube::resolve_url(&self.to_string()).await? {
+            Some(id) if id.starts_with("UC") => Ok(id),
+            _ => Err(Error::Unavailable(format!("no channel found at {}", self)).into()),
+        }
+    }
+}
+
+/// The [Tab] shown on a channel's page.
+fn selected_tab(response: &Value) -> Option<Tab> {
+    innertube::find_all(response, "tabRenderer")
+        .into_iter()
+        .find(|t| t.get("selected").and_then(Value::as_bool) == Some(true))
+        .and_then(|t| t.get("title"))
+        .and_then(Value::as_str)
+        .and_then(Tab::from_title)
+}
+
+/// The largest of a list of images.
+fn largest(images: &Value) -> Option<Thumbnail> {
+    let mut images: Vec<Thumbnail> = serde_json::from_value(images.clone()).ok()?;
+    for image in &mut images {
+        // Some are given without a scheme.
+        if image.url.starts_with("//") {
+            image.url = format!("https:{}", image.url);
+        }
+    }
+    images.into_iter().max_by_key(|t| t.width * t.height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn selects_tabs() {
+        // Sent as JSON, so unescaped.
+        assert_eq!(Tab::Videos.params(), "EgZ2aWRlb3PyBgQKAjoA");
+        assert_eq!(Tab::Shorts.params(), "EgZzaG9ydHPyBgUKA5oBAA==");
+        assert_eq!(Tab::Live.params(), "EgdzdHJlYW1z8gYECgJ6AA==");
+    }
+
+    #[test]
+    fn parses_tabs() {
+        let channel = Channel::try_from(
+            r#"{
+  "contents": {"twoColumnBrowseResultsRenderer": {"tabs": [
+    {"tabRenderer": {"title": "Home", "selected": false}},
+    {"tabRenderer": {"title": "Videos", "selected": true, "content": {"richGridRenderer": {"contents": [
+      {"richItemRenderer": {"content": {"videoRenderer": {"videoId": "VfWgE7D1pYY",
+        "title": {"runs": [{"text": "Newest"}]}, "lengthText": {"simpleText": "4:05"}}}}},
+      {"richItemRenderer": {"content": {"videoRenderer": {"videoId": "dQw4w9WgXcQ",
+        "title": {"runs": [{"text": "Older"}]}}}}},
+      {"continuationItemRenderer": {"continuationEndpoint": {"continuationCommand": {"token": "4qmFsgJ"}}}}
+    ]}}}}
+  ]}},
+  "header": {"c4TabbedHeaderRenderer": {
+    "banner": {"thumbnails": [
+      {"url": "https://yt3.example.com/banner=w1060", "width": 1060, "height": 175},
+      {"url": "https://yt3.example.com/banner=w2560", "width": 2560, "height": 424}]},
+    "subscriberCountText": {"simpleText": "2.41M subscribers"}}},
+  "metadata": {"channelMetadataRenderer": {"title": "Google for Developers",
+    "externalId": "UC_x5XG1OV2P6uZZ5FSM9Ttw", "description": "Talks.",
+    "avatar": {"thumbnails": [{"url": "//yt3.example.com/avatar=s900", "width": 900, "height": 900}]}}}
+}"#,
+        )
+        .unwrap();
+        assert_eq!(channel.id(), "UC_x5XG1OV2P6uZZ5FSM9Ttw");
+        assert_eq!(channel.title(), "Google for Developers");
+        assert_eq!(channel.subscribers().as_deref(), Some("2.41M subscribers"));
+        assert_eq!(channel.banner().unwrap().width(), 2560);
+        assert_eq!(
+            channel.avatar().unwrap().url(),
+            "https://yt3.example.com/avatar=s900"
+        );
+
+        let entries = channel.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].tab(), Tab::Videos);
+        assert_eq!(entries[0].duration(), Some(Duration::from_secs(245)));
+        assert_eq!(entries[1].title(), "Older");
+
+        let mut channel = channel;
+        let shorts: Value = serde_json::from_str(
+            r#"{"onResponseReceivedActions": [{"appendContinuationItemsAction": {"continuationItems": [
+  {"richItemRenderer": {"content": {"reelItemRenderer": {"videoId": "jNQXAC9IVRw",
+    "headline": {"simpleText": "A short"}}}}},
+  {"richItemRenderer": {"content": {"shortsLockupViewModel": {
+    "onTap": {"innertubeCommand": {"reelWatchEndpoint": {"videoId": "9bZkp7q19f0"}}},
+    "overlayMetadata": {"primaryText": {"content": "Another"}}}}}}
+]}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(channel.extend(&shorts, Tab::Shorts), None);
+        let entries = channel.entries();
+        assert_eq!(entries[2].title(), "A short");
+        assert_eq!(entries[3].id(), "9bZkp7q19f0");
+        assert_eq!(entries[3].title(), "Another");
+    }
+}
@@ -0,0 +1,209 @@
+//! Reading the Atom feeds YouTube publishes of each channel's uploads.
+//!
+//! A channel's feed lists only its fifteen most recent videos, but is a
+//! single small request, so it is a cheap way of noticing new uploads
+//! before listing a whole [Channel](super::Channel).
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+#[cfg(feature = "client")]
+use std::error;
+
+#[cfg(feature = "client")]
+use super::ChannelRef;
+use crate::serde::date_time;
+
+#[derive(Deserialize, Debug, Clone)]
+/// A video listed in a [Feed].
+pub struct FeedEntry {
+    #[serde(rename = "videoId")]
+    video_id: String,
+
+    title: String,
+
+    #[serde(default, deserialize_with = "date_time::from_str_option")]
+    published: Option<DateTime<Utc>>,
+
+    // When the video's title, description or thumbnail last changed.
+    #[serde(default, deserialize_with = "date_time::from_str_option")]
+    updated: Option<DateTime<Utc>>,
+}
+
+impl FeedEntry {
+    /// ID of the video.
+    pub fn video_id(&self) -> String {
+        self.video_id.clone()
+    }
+
+    /// Title of the video.
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    /// When the video was published.
+    pub fn published(&self) -> Option<DateTime<Utc>> {
+        self.published
+    }
+
+    /// When the video's details were last changed.
+    pub fn updated(&self) -> Option<DateTime<Utc>> {
+        self.updated
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+struct Author {
+    name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// The Atom feed of a channel's most recent uploads.
+pub struct Feed {
+    #[serde(rename = "channelId")]
+    channel_id: String,
+
+    title: String,
+
+    #[serde(default)]
+    author: Author,
+
+    #[serde(default, rename = "entry")]
+    entries: Vec<FeedEntry>,
+}
+
+impl TryFrom<&str> for Feed {
+    type Error = serde_xml_rs::Error;
+
+    /// Attempt to parse the XML of a channel's `feeds/videos.xml` into a
+    /// [Feed].
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        serde_xml_rs::from_str(s.trim_start_matches('\u{feff}'))
+    }
+}
+
+impl Feed {
+    #[cfg(feature = "client")]
+    /// Fetches the [Feed] of the channel with the given canonical ID.
+    pub async fn from_channel_id(id: &str) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        let https = hyper_tls::HttpsConnector::new();
+        let client = hyper::Client::builder().build::<_, hyper::Body>(https);
+
+        let url = format!("https://www.youtube.com/feeds/videos.xml?channel_id={}", id);
+        let mut res = client.get(url.parse()?).await?;
+        if !res.status().is_success() {
+            return Err(format!("Feed request failed with {}", res.status()).into());
+        }
+        let body = String::from_utf8(hyper::body::to_bytes(res.body_mut()).await?.to_vec())?;
+        Ok(Self::try_from(body.as_str())?)
+    }
+
+    #[cfg(feature = "client")]
+    /// Fetches the [Feed] of a channel however it is referred to,
+    /// resolving it to its canonical ID first if need be.
+    pub async fn from_ref(
+        channel: &ChannelRef,
+    ) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        Self::from_channel_id(&channel.resolve().await?).await
+    }
+
+    /// ID of the channel.
+    pub fn channel_id(&self) -> String {
+        self.channel_id.clone()
+    }
+
+    /// Name of the channel.
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    /// Name of the channel's owner, as the feed's author.
+    pub fn author(&self) -> String {
+        self.author.name.clone()
+    }
+
+    /// The videos listed, newest first.
+    pub fn entries(&self) -> Vec<FeedEntry> {
+        self.entries.clone()
+    }
+
+    /// The videos published after `since`, newest first.
+    pub fn published_since(&self, since: DateTime<Utc>) -> Vec<FeedEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.published.is_some_and(|p| p > since))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_feed() {
+        let feed = Feed::try_from(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns:yt="http://www.youtube.com/xml/schemas/2015" xmlns:media="http://search.yahoo.com/mrss/" xmlns="http://www.w3.org/2005/Atom">
+ <link rel="self" href="http://www.youtube.com/feeds/videos.xml?channel_id=UC_x5XG1OV2P6uZZ5FSM9Ttw"/>
+ <id>yt:channel:UC_x5XG1OV2P6uZZ5FSM9Ttw</id>
+ <yt:channelId>UC_x5XG1OV2P6uZZ5FSM9Ttw</yt:channelId>
+ <title>Google for Developers</title>
+ <link rel="alternate" href="https://www.youtube.com/channel/UC_x5XG1OV2P6uZZ5FSM9Ttw"/>
+ <author>
+  <name>Google for Developers</name>
+  <uri>https://www.youtube.com/channel/UC_x5XG1OV2P6uZZ5FSM9Ttw</uri>
+ </author>
+ <published>2007-08-23T00:34:43+00:00</published>
+ <entry>
+  <id>yt:video:VfWgE7D1pYY</id>
+  <yt:videoId>VfWgE7D1pYY</yt:videoId>
+  <yt:channelId>UC_x5XG1OV2P6uZZ5FSM9Ttw</yt:channelId>
+  <title>Newest &amp; best</title>
+  <link rel="alternate" href="https://www.youtube.com/watch?v=VfWgE7D1pYY"/>
+  <author>
+   <name>Google for Developers</name>
+  </author>
+  <published>2021-05-02T16:00:10+00:00</published>
+  <updated>2021-05-03T08:12:45+00:00</updated>
+  <media:group>
+   <media:title>Newest &amp; best</media:title>
+   <media:thumbnail url="https://i1.ytimg.com/vi/VfWgE7D1pYY/hqdefault.jpg" width="480" height="360"/>
+   <media:description>Talks.</media:description>
+  </media:group>
+ </entry>
+ <entry>
+  <id>yt:video:dQw4w9WgXcQ</id>
+  <yt:videoId>dQw4w9WgXcQ</yt:videoId>
+  <yt:channelId>UC_x5XG1OV2P6uZZ5FSM9Ttw</yt:channelId>
+  <title>Older</title>
+  <published>2021-04-30T16:00:00+00:00</published>
+  <updated>2021-04-30T16:00:00+00:00</updated>
+ </entry>
+</feed>"#,
+        )
+        .unwrap();
+        assert_eq!(feed.channel_id(), "UC_x5XG1OV2P6uZZ5FSM9Ttw");
+        assert_eq!(feed.title(), "Google for Developers");
+        assert_eq!(feed.author(), "Google for Developers");
+
+        let entries = feed.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].video_id(), "VfWgE7D1pYY");
+        assert_eq!(entries[0].title(), "Newest & best");
+        assert_eq!(
+            entries[0].updated(),
+            date_time::parse("2021-05-03T08:12:45Z")
+        );
+
+        let since = date_time::parse("2021-05-01T00:00:00Z").unwrap();
+        let new: Vec<_> = feed
+            .published_since(since)
+            .iter()
+            .map(|e| e.video_id())
+            .collect();
+        assert_eq!(new, vec!["VfWgE7D1pYY"]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Reading responses of InnerTube, the API behind YouTube's web pages.
+//!
+//! Its responses nest the same renderers at depths that vary between pages
+//! and over time, so they are searched for by key rather than walked along
+//! a fixed path.
+
+use serde_json::Value;
+use std::time::Duration;
+
+#[cfg(feature = "client")]
+use hyper::{body, header::CONTENT_TYPE, Body, Client, Request};
+#[cfg(feature = "client")]
+use hyper_tls::HttpsConnector;
+#[cfg(feature = "client")]
+use log::warn;
+#[cfg(feature = "client")]
+use serde_json::json;
+#[cfg(feature = "client")]
+use std::error;
+
+#[cfg(feature = "client")]
+const API: &str = "https://www.youtube.com/youtubei/v1";
+
+#[cfg(feature = "client")]
+// Version of the web client requests claim to come from.
+const CLIENT_VERSION: &str = "2.20240101.00.00";
+
+/// The first value found under `key`, searching depth-first.
+pub(super) fn find<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(map) => map
+            .get(key)
+            .or_else(|| map.values().find_map(|v| find(v, key))),
+        Value::Array(values) => values.iter().find_map(|v| find(v, key)),
+        _ => None,
+    }
+}
+
+/// Every value found under `key`, in document order. Values nested within
+/// one found are not searched.
+pub(super) fn find_all<'a>(value: &'a Value, key: &str) -> Vec<&'a Value> {
+    let mut found = Vec::new();
+    collect(value, key, &mut found);
+    found
+}
+
+fn collect<'a>(value: &'a Value, key: &str, found: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                if k == key {
+                    found.push(v);
+                } else {
+                    collect(v, key, found);
+                }
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|v| collect(v, key, found)),
+        _ => (),
+    }
+}
+
+/// The text of a formatted string, given either as `simpleText` or as
+/// `runs` of text to be joined, or as `content`.
+pub(super) fn text(value: &Value) -> Option<String> {
+    if let Some(s) = value.as_str() {
+        return Some(s.to_string());
+    }
+    if let Some(s) = value
+        .get("simpleText")
+        .or_else(|| value.get("content"))
+        .and_then(Value::as_str)
+    {
+        return Some(s.to_string());
+    }
+    let runs = value.get("runs")?.as_array()?;
+    Some(
+        runs.iter()
+            .filter_map(|r| r.get("text").and_then(Value::as_str))
+            .collect(),
+    )
+}
+
+/// The number within a text such as `1,234 videos`.
+pub(super) fn number(text: &str) -> Option<u64> {
+    let digits: String = text
+        .split_whitespace()
+        .next()?
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+/// A length given as seconds, or as a timestamp such as `1:02:03`.
+pub(super) fn duration(value: &Value) -> Option<Duration> {
+    if let Some(secs) = value
+        .as_str()
+        .and_then(|s| s.parse().ok())
+        .or_else(|| value.as_u64())
+    {
+        return Some(Duration::from_secs(secs));
+    }
+    let mut secs = 0;
+    for part in text(value)?.split(':') {
+        secs = secs * 60 + part.trim().parse::<u64>().ok()?;
+    }
+    Some(Duration::from_secs(secs))
+}
+
+/// The token to request the page following that in `response`, if any.
+pub(super) fn continuation(response: &Value) -> Option<String> {
+    find_all(response, "continuationItemRenderer")
+        .into_iter()
+        .find_map(|r| find(r, "token"))
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+/// The text of the first alert in `response`, such as why a page is
+/// unavailable.
+pub(super) fn alert(response: &Value) -> Option<String> {
+    find(response, "alertRenderer")
+        .or_else(|| find(response, "alertWithButtonRenderer"))
+        .and_then(|a| a.get("text"))
+        .and_then(text)
+}
+
+#[cfg(feature = "client")]
+/// Requests the page `browse_id`, such as `VL` followed by a playlist ID,
+/// optionally narrowed to one of its tabs by `params`; or the page
+/// following another if given its `continuation` token instead.
+pub(super) async fn browse(
+    browse_id: Option<&str>,
+    params: Option<&str>,
+    continuation: Option<&str>,
+) -> Result<Value, Box<dyn error::Error + Send + Sync>> {
+    let mut fields = json!({});
+    if let Some(id) = browse_id {
+        fields["browseId"] = json!(id);
+    }
+    if let Some(params) = params {
+        fields["params"] = json!(params);
+    }
+    if let Some(token) = continuation {
+        fields["continuation"] = json!(token);
+    }
+    request("browse", fields).await
+}
+
+#[cfg(feature = "client")]
+/// The browse ID of the page at `url`, such as the canonical ID of a
+/// channel given its handle.
+pub(super) async fn resolve_url(
+    url: &str,
+) -> Result<Option<String>, Box<dyn error::Error + Send + Sync>> {
+    let response = request("navigation/resolve_url", json!({ "url": url })).await?;
+    Ok(find(&response, "browseEndpoint")
+        .and_then(|e| e.get("browseId"))
+        .and_then(Value::as_str)
+        .map(String::from))
+}
+
+#[cfg(feature = "client")]
+/// Posts `fields` to the InnerTube `endpoint` as the web client. Requests
+/// are retried a few times before failing.
+async fn request(
+    endpoint: &str,
+    mut fields: Value,
+) -> Result<Value, Box<dyn error::Error + Send + Sync>> {
+    fields["context"] = json!({
+        "client": {
+            "clientName": "WEB",
+            "clientVersion": CLIENT_VERSION,
+            "hl": "en",
+            "gl": "US",
+        }
+    });
+    let body = fields.to_string();
+    let url = format!("{}/{}?prettyPrint=false", API, endpoint);
+
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    let mut attempt = 0;
+    loop {
+        let req = Request::post(url.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.clone()))?;
+        let result = match client.request(req).await {
+            Ok(mut res) if res.status().is_success() => {
+                let body = body::to_bytes(res.body_mut()).await?;
+                return Ok(serde_json::from_slice(&body)?);
+            }
+            Ok(res) if res.status().is_client_error() => {
+                return Err(format!("{} request failed with {}", endpoint, res.status()).into())
+            }
+            Ok(res) => format!("{} request failed with {}", endpoint, res.status()),
+            Err(e) => e.to_string(),
+        };
+        if attempt >= 3 {
+            return Err(result.into());
+        }
+        warn!("{}; retrying", result);
+        tokio::time::sleep(Duration::from_millis(500 << attempt)).await;
+        attempt += 1;
+    }
+}
@@ -2,40 +2,312 @@
 //!
 //! Handles parsing channel, video, playlist URLs and IDs into maguro-managed
 //! entities.
+//!
+//! Each whitespace-separated part of a [Query] is classified into a
+//! [Target]. Bare IDs are recognized by their shape: eleven characters for a
+//! video, `UC` and 22 more for a channel, `@` for a handle, and a known
+//! prefix such as `PL` for a playlist. URLs may point to any of YouTube's
+//! hosts, including `youtu.be`, `m.youtube.com` and `music.youtube.com`.
+//!
+//! A [Playlist] or [Channel] is expanded into the videos it lists through
+//! InnerTube, the API behind YouTube's own pages. A channel's [Feed] lists
+//! just its latest uploads, far more cheaply.
 
-use std::{error, str::FromStr};
+use std::{
+    error,
+    fmt::{self, Display},
+    str::FromStr,
+};
 
 use lazy_static::lazy_static;
 use regex::Regex;
+use url::Url;
+
+mod channel;
+mod feed;
+mod innertube;
+mod playlist;
+
+pub use channel::{Channel, ChannelEntry, Tab};
+pub use feed::{Feed, FeedEntry};
+pub use playlist::{Playlist, PlaylistEntry};
+
+lazy_static! {
+    static ref VIDEO: Regex = Regex::new("^[A-Za-z0-9_-]{11}$").unwrap();
+    static ref PLAYLIST: Regex =
+        Regex::new("^(?:PL|UU|UUSH|UULV|FL|LL|RD|OL|UL|PU)[A-Za-z0-9_-]{10,}$").unwrap();
+    static ref CHANNEL: Regex = Regex::new("^UC[A-Za-z0-9_-]{22}$").unwrap();
+    static ref HANDLE: Regex = Regex::new("^@[A-Za-z0-9_.-]{3,30}$").unwrap();
+    static ref NAME: Regex = Regex::new("^[A-Za-z0-9_.-]+$").unwrap();
+}
+
+#[derive(Debug)]
+/// Error while parsing a [Query].
+pub enum Error {
+    /// The query names a YouTube entity, but its ID is invalid.
+    Malformed(String),
+
+    /// The query is not a YouTube URL or ID maguro recognizes.
+    Unsupported(String),
+
+    /// YouTube refuses to show what the query names, for the reason given.
+    Unavailable(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed(reason) => write!(f, "Malformed query: {}", reason),
+            Error::Unsupported(what) => write!(f, "Unsupported query: {}", what),
+            Error::Unavailable(reason) => write!(f, "Unavailable: {}", reason),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The ways a YouTube channel may be referred to.
+pub enum ChannelRef {
+    /// A canonical channel ID, such as `UC_x5XG1OV2P6uZZ5FSM9Ttw`.
+    Id(String),
+
+    /// A handle, such as `@GoogleDevelopers`.
+    Handle(String),
+
+    /// A legacy custom URL name, as in `/c/name`.
+    Custom(String),
+
+    /// A legacy username, as in `/user/name`.
+    User(String),
+}
+
+impl Display for ChannelRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelRef::Id(id) => write!(f, "https://www.youtube.com/channel/{}", id),
+            ChannelRef::Handle(handle) => write!(f, "https://www.youtube.com/{}", handle),
+            ChannelRef::Custom(name) => write!(f, "https://www.youtube.com/c/{}", name),
+            ChannelRef::User(name) => write!(f, "https://www.youtube.com/user/{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single video, playlist or channel named by a [Query].
+pub enum Target {
+    Video(String),
+    Playlist(String),
+    Channel(ChannelRef),
+}
+
+impl Display for Target {
+    /// Writes the canonical URL of the [Target].
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Video(id) => write!(f, "https://www.youtube.com/watch?v={}", id),
+            Target::Playlist(id) => write!(f, "https://www.youtube.com/playlist?list={}", id),
+            Target::Channel(channel) => write!(f, "{}", channel),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    /// Classifies a YouTube URL or bare ID. A watch URL naming both a video
+    /// and a playlist is taken to be the video.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if VIDEO.is_match(s) {
+            return Ok(Target::Video(s.to_string()));
+        }
+        if CHANNEL.is_match(s) {
+            return Ok(Target::Channel(ChannelRef::Id(s.to_string())));
+        }
+        if HANDLE.is_match(s) {
+            return Ok(Target::Channel(ChannelRef::Handle(s.to_string())));
+        }
+        if PLAYLIST.is_match(s) {
+            return Ok(Target::Playlist(s.to_string()));
+        }
+
+        // URLs are often pasted without their scheme.
+        let url = match Url::parse(s) {
+            Ok(url) => url,
+            Err(url::ParseError::RelativeUrlWithoutBase) if s.contains('/') => {
+                Url::parse(&format!("https://{}", s))
+                    .map_err(|_| Error::Unsupported(format!("{:?} is not a URL or ID", s)))?
+            }
+            Err(_) => {
+                return Err(Error::Malformed(format!(
+                    "{:?} is not a video, playlist or channel ID",
+                    s
+                )))
+            }
+        };
+        Self::from_url(&url)
+    }
+}
+
+impl Target {
+    /// Classifies a YouTube URL.
+    fn from_url(url: &Url) -> Result<Self, Error> {
+        let host = url.host_str().unwrap_or_default();
+        let host = host.strip_prefix("www.").unwrap_or(host);
+        let path: Vec<_> = url
+            .path_segments()
+            .map(|p| p.filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.into_owned())
+        };
+
+        match host {
+            "youtu.be" => match path.as_slice() {
+                [id, ..] => video(id),
+                [] => Err(Error::Malformed(format!("{} names no video", url))),
+            },
+            "youtube.com" | "m.youtube.com" | "music.youtube.com" | "youtube-nocookie.com" => {
+                match path.as_slice() {
+                    ["watch"] => match (param("v"), param("list")) {
+                        (Some(id), _) => video(&id),
+                        (None, Some(list)) => playlist(&list),
+                        (None, None) => Err(Error::Malformed(format!("{} names no video", url))),
+                    },
+                    ["playlist"] | ["embed", "videoseries"] => match param("list") {
+                        Some(list) => playlist(&list),
+                        None => Err(Error::Malformed(format!("{} names no playlist", url))),
+                    },
+                    ["shorts", id, ..]
+                    | ["embed", id, ..]
+                    | ["live", id, ..]
+                    | ["v", id, ..]
+                    | ["e", id, ..] => video(id),
+                    ["channel", id, ..] | ["browse", id, ..] => {
+                        if CHANNEL.is_match(id) {
+                            Ok(Target::Channel(ChannelRef::Id(id.to_string())))
+                        } else {
+                            Err(Error::Malformed(format!("{:?} is not a channel ID", id)))
+                        }
+                    }
+                    ["c", name, ..] => {
+                        channel_name(name).map(|n| Target::Channel(ChannelRef::Custom(n)))
+                    }
+                    ["user", name, ..] => {
+                        channel_name(name).map(|n| Target::Channel(ChannelRef::User(n)))
+                    }
+                    [handle, ..] if handle.starts_with('@') => {
+                        if HANDLE.is_match(handle) {
+                            Ok(Target::Channel(ChannelRef::Handle(handle.to_string())))
+                        } else {
+                            Err(Error::Malformed(format!("{:?} is not a handle", handle)))
+                        }
+                    }
+                    _ => Err(Error::Unsupported(format!(
+                        "{} is not a video, playlist or channel",
+                        url
+                    ))),
+                }
+            }
+            _ => Err(Error::Unsupported(format!("{} is not a YouTube URL", url))),
+        }
+    }
+}
+
+/// A [Target::Video], if `id` is a valid video ID.
+fn video(id: &str) -> Result<Target, Error> {
+    if VIDEO.is_match(id) {
+        Ok(Target::Video(id.to_string()))
+    } else {
+        Err(Error::Malformed(format!("{:?} is not a video ID", id)))
+    }
+}
+
+/// A [Target::Playlist], if `id` is a valid playlist ID.
+fn playlist(id: &str) -> Result<Target, Error> {
+    if PLAYLIST.is_match(id) {
+        Ok(Target::Playlist(id.to_string()))
+    } else {
+        Err(Error::Malformed(format!("{:?} is not a playlist ID", id)))
+    }
+}
+
+/// `name`, if valid as a legacy custom URL name or username.
+fn channel_name(name: &str) -> Result<String, Error> {
+    if NAME.is_match(name) {
+        Ok(name.to_string())
+    } else {
+        Err(Error::Malformed(format!(
+            "{:?} is not a channel name",
+            name
+        )))
+    }
+}
 
 /// Collection of video IDs that will be downloaded, as parsed from
 /// a list of YouTube channels, playlists, video URLs.
-pub struct Query(String);
+pub struct Query(Vec<Target>);
 
 impl Query {
-    /// Video URLs parsed from a given query.
+    /// The [Targets](Target) named by the query, in order.
+    pub fn targets(&self) -> Vec<Target> {
+        self.0.clone()
+    }
+
+    /// Video URLs parsed from a given query. Playlists are expanded into
+    /// every video in them, in order, and channels into every video on
+    /// each of their [Tabs](Tab).
     pub async fn urls(&self) -> Result<Vec<String>, Box<dyn error::Error>> {
-        lazy_static! {
-          // First capture group is always our video ID.
-          static ref VIDEO: Regex = Regex::new("").unwrap();
-        }
+        let info = |id: &str| format!("https://www.youtube.com/get_video_info?video_id={}", id);
 
         let mut videos = Vec::new();
-        for pattern in self.0.split(" ") {
-            videos.push(format!(
-                "https://www.youtube.com/get_video_info?video_id={}",
-                pattern
-            ));
+        for target in &self.0 {
+            match target {
+                Target::Video(id) => videos.push(info(id)),
+                #[cfg(feature = "client")]
+                Target::Playlist(id) => videos.extend(
+                    Playlist::from_id(id)
+                        .await
+                        .map_err(|e| e as Box<dyn error::Error>)?
+                        .entries()
+                        .iter()
+                        .map(|e| info(&e.id())),
+                ),
+                #[cfg(feature = "client")]
+                Target::Channel(channel) => {
+                    let channel = Channel::from_ref(channel, &Tab::ALL)
+                        .await
+                        .map_err(|e| e as Box<dyn error::Error>)?;
+                    videos.extend(channel.entries().iter().map(|e| info(&e.id())));
+                }
+                #[cfg(not(feature = "client"))]
+                other => {
+                    return Err(Error::Unsupported(format!(
+                        "cannot expand {} without the client feature",
+                        other
+                    ))
+                    .into())
+                }
+            }
         }
         Ok(videos)
     }
 }
 
 impl FromStr for Query {
-    type Err = &'static str;
+    type Err = Error;
 
+    /// Parses whitespace-separated URLs and IDs, failing on the first that
+    /// is not recognized.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self(s.to_string()))
+        Ok(Self(
+            s.split_whitespace()
+                .map(Target::from_str)
+                .collect::<Result<_, _>>()?,
+        ))
     }
 }
 
@@ -49,4 +321,74 @@ mod test {
             assert!(false);
         }
     }
+
+    #[test]
+    fn classifies_urls() {
+        let video = Target::Video("VfWgE7D1pYY".to_string());
+        for url in &[
+            "https://www.youtube.com/watch?v=VfWgE7D1pYY&t=42s",
+            "youtube.com/watch?feature=share&v=VfWgE7D1pYY",
+            "https://m.youtube.com/watch?v=VfWgE7D1pYY",
+            "https://music.youtube.com/watch?v=VfWgE7D1pYY&list=RDAMVMVfWgE7D1pYY",
+            "https://youtu.be/VfWgE7D1pYY?si=abc",
+            "https://www.youtube.com/shorts/VfWgE7D1pYY",
+            "https://www.youtube-nocookie.com/embed/VfWgE7D1pYY",
+            "https://www.youtube.com/live/VfWgE7D1pYY?feature=share",
+        ] {
+            assert_eq!(Target::from_str(url).unwrap(), video, "{}", url);
+        }
+
+        let list = "PLBCF2DAC6FFB574DE";
+        assert_eq!(
+            Target::from_str(&format!("https://www.youtube.com/playlist?list={}", list)).unwrap(),
+            Target::Playlist(list.to_string())
+        );
+        assert_eq!(
+            Target::from_str(&format!("https://music.youtube.com/watch?list={}", list)).unwrap(),
+            Target::Playlist(list.to_string())
+        );
+
+        let id = "UC_x5XG1OV2P6uZZ5FSM9Ttw";
+        assert_eq!(
+            Target::from_str(&format!("https://www.youtube.com/channel/{}/videos", id)).unwrap(),
+            Target::Channel(ChannelRef::Id(id.to_string()))
+        );
+        assert_eq!(
+            Target::from_str("https://www.youtube.com/@GoogleDevelopers/streams").unwrap(),
+            Target::Channel(ChannelRef::Handle("@GoogleDevelopers".to_string()))
+        );
+        assert_eq!(
+            Target::from_str("https://www.youtube.com/c/GoogleDevelopers").unwrap(),
+            Target::Channel(ChannelRef::Custom("GoogleDevelopers".to_string()))
+        );
+        assert_eq!(
+            Target::from_str("https://www.youtube.com/user/GoogleDevelopers").unwrap(),
+            Target::Channel(ChannelRef::User("GoogleDevelopers".to_string()))
+        );
+        assert_eq!(
+            Target::Channel(ChannelRef::Id(id.to_string())).to_string(),
+            format!("https://www.youtube.com/channel/{}", id)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        for query in &[
+            "https://www.youtube.com/watch?v=VfWgE7D1p",
+            "https://youtu.be/",
+            "https://www.youtube.com/playlist?list=XX123",
+            "https://www.youtube.com/channel/UCtooshort",
+            "https://vimeo.com/123456",
+            "https://www.youtube.com/feed/trending",
+            "not an id",
+        ] {
+            assert!(Target::from_str(query).is_err(), "{}", query);
+        }
+
+        let targets = Query::from_str("VfWgE7D1pYY  @GoogleDevelopers")
+            .unwrap()
+            .targets();
+        assert_eq!(targets.len(), 2);
+        assert!(Query::from_str("VfWgE7D1pYY VfWgE7D1").is_err());
+    }
 }
@@ -0,0 +1,266 @@
+//! Expanding YouTube playlists into their videos.
+//!
+//! A playlist's page lists its first hundred or so videos, along with a
+//! continuation token for the next page, and so on until the end. A
+//! [Playlist] pages through them all.
+
+use serde_json::Value;
+use std::{convert::TryFrom, time::Duration};
+
+#[cfg(feature = "client")]
+use log::info;
+#[cfg(feature = "client")]
+use std::{collections::HashSet, error};
+
+use super::{innertube, Error};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A video in a [Playlist].
+pub struct PlaylistEntry {
+    id: String,
+    title: String,
+    duration: Option<Duration>,
+    index: u64,
+}
+
+impl PlaylistEntry {
+    /// ID of the video.
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Title of the video.
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    /// Length of the video, unless it is live or unavailable.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// Position of the video in the [Playlist], counting from one.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// A YouTube playlist and the videos in it, in order.
+pub struct Playlist {
+    id: String,
+    title: String,
+    owner: Option<String>,
+    count: Option<u64>,
+    entries: Vec<PlaylistEntry>,
+
+    // Token for the next page of entries, if there is one.
+    continuation: Option<String>,
+}
+
+impl TryFrom<&str> for Playlist {
+    type Error = Error;
+
+    /// Attempt to parse the JSON of the first page of a playlist, as
+    /// returned by InnerTube's `browse`, into a [Playlist].
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let response: Value = serde_json::from_str(s)
+            .map_err(|e| Error::Malformed(format!("playlist is not JSON: {}", e)))?;
+        Self::from_response(&response)
+    }
+}
+
+impl Playlist {
+    #[cfg(feature = "client")]
+    /// Fetches the playlist with the given ID, paging through every video
+    /// in it.
+    pub async fn from_id(id: &str) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        let response = innertube::browse(Some(&format!("VL{}", id)), None, None).await?;
+        let mut playlist = Self::from_response(&response)?;
+
+        // A token seen before would only lead round in circles.
+        let mut seen = HashSet::new();
+        while let Some(token) = playlist.continuation.take() {
+            if !seen.insert(token.clone()) {
+                break;
+            }
+            info!(
+                "Fetched {} of {} videos in playlist {}",
+                playlist.entries.len(),
+                playlist
+                    .count
+                    .map_or_else(|| "?".to_string(), |c| c.to_string()),
+                id
+            );
+            let response = innertube::browse(None, None, Some(&token)).await?;
+            playlist.extend(&response);
+        }
+        Ok(playlist)
+    }
+
+    /// Reads the metadata and first page of entries of a playlist.
+    fn from_response(response: &Value) -> Result<Self, Error> {
+        let metadata = innertube::find(response, "playlistMetadataRenderer");
+        let header = innertube::find(response, "playlistHeaderRenderer");
+        let sidebar = innertube::find(response, "playlistSidebarPrimaryInfoRenderer");
+
+        let title = match metadata
+            .or(header)
+            .and_then(|m| m.get("title"))
+            .and_then(innertube::text)
+        {
+            Some(title) => title,
+            None => {
+                return Err(Error::Unavailable(
+                    innertube::alert(response)
+                        .unwrap_or_else(|| "the playlist is unavailable".to_string()),
+                ))
+            }
+        };
+        let id = header
+            .and_then(|h| h.get("playlistId"))
+            .or_else(|| innertube::find(response, "playlistId"))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let owner = header
+            .and_then(|h| h.get("ownerText"))
+            .or_else(|| {
+                innertube::find(response, "videoOwnerRenderer").and_then(|o| o.get("title"))
+            })
+            .and_then(innertube::text);
+        let count = header
+            .and_then(|h| h.get("numVideosText"))
+            .or_else(|| sidebar.and_then(|s| s.get("stats")).and_then(|s| s.get(0)))
+            .and_then(innertube::text)
+            .and_then(|t| innertube::number(&t));
+
+        let mut playlist = Self {
+            id,
+            title,
+            owner,
+            count,
+            ..Self::default()
+        };
+        playlist.extend(response);
+        Ok(playlist)
+    }
+
+    /// Adds the entries of a page of the playlist, and notes the token for
+    /// the next.
+    fn extend(&mut self, response: &Value) {
+        for video in innertube::find_all(response, "playlistVideoRenderer") {
+            let id = match video.get("videoId").and_then(Value::as_str) {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+            let index = video
+                .get("index")
+                .and_then(innertube::text)
+                .and_then(|i| innertube::number(&i))
+                .unwrap_or(self.entries.len() as u64 + 1);
+            self.entries.push(PlaylistEntry {
+                id,
+                title: video
+                    .get("title")
+                    .and_then(innertube::text)
+                    .unwrap_or_default(),
+                duration: video
+                    .get("lengthSeconds")
+                    .or_else(|| video.get("lengthText"))
+                    .and_then(innertube::duration),
+                index,
+            });
+        }
+        self.continuation = innertube::continuation(response);
+    }
+
+    /// ID of the playlist.
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Title of the playlist.
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    /// Name of the channel that owns the playlist, if shown.
+    pub fn owner(&self) -> Option<String> {
+        self.owner.clone()
+    }
+
+    /// Number of videos in the playlist, as YouTube reports it. Hidden and
+    /// unavailable videos are counted, but not listed.
+    pub fn count(&self) -> Option<u64> {
+        self.count
+    }
+
+    /// The videos of the playlist, in order.
+    pub fn entries(&self) -> Vec<PlaylistEntry> {
+        self.entries.clone()
+    }
+
+    /// Whether there are more entries than have been fetched.
+    pub fn is_partial(&self) -> bool {
+        self.continuation.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pages() {
+        let mut playlist = Playlist::try_from(
+            r#"{
+  "contents": {"twoColumnBrowseResultsRenderer": {"tabs": [{"tabRenderer": {"content": {
+    "sectionListRenderer": {"contents": [{"itemSectionRenderer": {"contents": [{
+      "playlistVideoListRenderer": {"contents": [
+        {"playlistVideoRenderer": {"videoId": "VfWgE7D1pYY", "index": {"simpleText": "1"},
+          "title": {"runs": [{"text": "First"}]}, "lengthSeconds": "212"}},
+        {"playlistVideoRenderer": {"videoId": "dQw4w9WgXcQ", "index": {"simpleText": "2"},
+          "title": {"runs": [{"text": "Sec"}, {"text": "ond"}]}, "lengthText": {"simpleText": "1:02:03"}}},
+        {"continuationItemRenderer": {"continuationEndpoint": {"continuationCommand": {"token": "4qmFsgI"}}}}
+      ]}
+    }]}}]}
+  }}}]}},
+  "header": {"playlistHeaderRenderer": {"playlistId": "PLBCF2DAC6FFB574DE",
+    "title": {"simpleText": "Mixtape"}, "ownerText": {"runs": [{"text": "Someone"}]},
+    "numVideosText": {"runs": [{"text": "1,203"}, {"text": " videos"}]}}}
+}"#,
+        )
+        .unwrap();
+        assert_eq!(playlist.id(), "PLBCF2DAC6FFB574DE");
+        assert_eq!(playlist.title(), "Mixtape");
+        assert_eq!(playlist.owner().as_deref(), Some("Someone"));
+        assert_eq!(playlist.count(), Some(1203));
+        assert!(playlist.is_partial());
+
+        let entries = playlist.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].duration(), Some(Duration::from_secs(212)));
+        assert_eq!(entries[1].title(), "Second");
+        assert_eq!(entries[1].duration(), Some(Duration::from_secs(3723)));
+
+        let next: Value = serde_json::from_str(
+            r#"{"onResponseReceivedActions": [{"appendContinuationItemsAction": {"continuationItems": [
+  {"playlistVideoRenderer": {"videoId": "jNQXAC9IVRw", "index": {"simpleText": "3"},
+    "title": {"simpleText": "Third"}}}
+]}}]}"#,
+        )
+        .unwrap();
+        playlist.extend(&next);
+        assert!(!playlist.is_partial());
+        assert_eq!(playlist.entries()[2].index(), 3);
+        assert_eq!(playlist.entries()[2].duration(), None);
+
+        let missing = r#"{"alerts": [{"alertRenderer": {"type": "ERROR",
+  "text": {"runs": [{"text": "The playlist does not exist."}]}}}]}"#;
+        match Playlist::try_from(missing) {
+            Err(Error::Unavailable(reason)) => assert_eq!(reason, "The playlist does not exist."),
+            other => panic!("{:?}", other),
+        }
+    }
+}
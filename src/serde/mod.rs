@@ -1,7 +1,8 @@
 //! Extensions to serde for deserializing foreign types.
 //!
-//! Provides deserializers for [Durations](std::time::Duration),
-//! and for converting types such as [&str] to [u32](std::u32).
+//! Provides deserializers for [Durations](std::time::Duration), for
+//! ISO 8601 dates and times, and for converting types such as [&str] to
+//! [u32](std::u32) and [u64](std::u64).
 
 use serde::{
     de::{Error, Visitor},
@@ -92,6 +93,7 @@ pub mod duration {
     //! [Options](Option<T>) from strings.
 
     use super::*;
+    use serde::Serializer;
     use std::{
         fmt::{self, Display},
         time::Duration,
@@ -188,6 +190,150 @@ pub mod duration {
     {
         Ok(deserializer.deserialize_option(DurationOptionVisitor::new(Unit::Seconds))?)
     }
+
+    /// Deserialize an `Option<Duration>` from an `xs:duration` such as
+    /// `PT1H2M3.5S`.
+    pub fn from_iso8601_option<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: Option<String> = Deserialize::deserialize(deserializer)?;
+        s.map(|s| {
+            parse_iso8601(&s)
+                .ok_or_else(|| D::Error::custom(format!("invalid xs:duration {:?}", s)))
+        })
+        .transpose()
+    }
+
+    /// Serialize an `Option<Duration>` as an `xs:duration`.
+    pub fn to_iso8601_option<S>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match d {
+            Some(d) => s.serialize_some(&to_iso8601(*d)),
+            None => s.serialize_none(),
+        }
+    }
+
+    /// Parse an `xs:duration` such as `PT1H2M3.5S`. Years and months are
+    /// taken to be 365 and 30 days long. Durations too long to represent
+    /// are rejected, as are those without any components.
+    pub(crate) fn parse_iso8601(s: &str) -> Option<Duration> {
+        let s = s.trim().strip_prefix('P')?;
+        let mut secs = 0.0;
+        let mut in_time = false;
+        // Whether any component has been read, in either part.
+        let (mut date_part, mut time_part) = (false, false);
+        let mut number = String::new();
+        for c in s.chars() {
+            let unit = match (c, in_time) {
+                ('T', false) => {
+                    in_time = true;
+                    continue;
+                }
+                ('0'..='9', _) | ('.', _) => {
+                    number.push(c);
+                    continue;
+                }
+                ('Y', false) => 365.0 * 86_400.0,
+                ('M', false) => 30.0 * 86_400.0,
+                ('W', false) => 7.0 * 86_400.0,
+                ('D', false) => 86_400.0,
+                ('H', true) => 3_600.0,
+                ('M', true) => 60.0,
+                ('S', true) => 1.0,
+                _ => return None,
+            };
+            secs += number.parse::<f64>().ok()? * unit;
+            number.clear();
+            if in_time {
+                time_part = true;
+            } else {
+                date_part = true;
+            }
+        }
+        // A `T` must be followed by at least one component.
+        if !number.is_empty() || in_time != time_part || !(date_part || time_part) {
+            return None;
+        }
+        Duration::try_from_secs_f64(secs).ok()
+    }
+
+    /// Format a [Duration] as an `xs:duration` in seconds, to millisecond
+    /// precision.
+    pub(crate) fn to_iso8601(d: Duration) -> String {
+        match d.subsec_millis() {
+            0 => format!("PT{}S", d.as_secs()),
+            millis => format!("PT{}.{:03}S", d.as_secs(), millis),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn iso8601() {
+            assert_eq!(
+                parse_iso8601("PT1H2M3.5S"),
+                Some(Duration::from_secs_f64(3723.5))
+            );
+            assert_eq!(parse_iso8601("P1DT1S"), Some(Duration::from_secs(86_401)));
+            assert_eq!(parse_iso8601("PT1"), None);
+            assert_eq!(parse_iso8601("P"), None);
+            assert_eq!(parse_iso8601("PT"), None);
+            assert_eq!(parse_iso8601("P1DT"), None);
+            assert_eq!(parse_iso8601("PT0S"), Some(Duration::from_secs(0)));
+            assert_eq!(parse_iso8601(&format!("PT{}S", "9".repeat(400))), None);
+            assert_eq!(parse_iso8601("P99999999999999999999Y"), None);
+            assert_eq!(to_iso8601(Duration::from_millis(3_723_500)), "PT3723.500S");
+            assert_eq!(to_iso8601(Duration::from_secs(9)), "PT9S");
+        }
+    }
+}
+
+pub mod date_time {
+    //! Extensions for parsing ISO 8601 `xs:dateTime` strings into
+    //! [DateTime<Utc>](DateTime).
+
+    use super::*;
+    use chrono::{DateTime, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+    use serde::Serializer;
+
+    /// Deserialize an `Option<DateTime<Utc>>` from an `xs:dateTime` such as
+    /// `2021-05-01T12:00:00Z`.
+    pub fn from_str_option<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: Option<String> = Deserialize::deserialize(deserializer)?;
+        s.map(|s| parse(&s).ok_or_else(|| D::Error::custom(format!("invalid xs:dateTime {:?}", s))))
+            .transpose()
+    }
+
+    /// Serialize an `Option<DateTime<Utc>>` as an `xs:dateTime`.
+    pub fn to_str_option<S>(t: &Option<DateTime<Utc>>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match t {
+            Some(t) => s.serialize_some(&t.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            None => s.serialize_none(),
+        }
+    }
+
+    /// Parse an `xs:dateTime`. Times without a zone are taken to be in UTC.
+    pub(crate) fn parse(s: &str) -> Option<DateTime<Utc>> {
+        let s = s.trim();
+        DateTime::parse_from_rfc3339(s)
+            .map(|t| t.with_timezone(&Utc))
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+                    .map(|t| Utc.from_utc_datetime(&t))
+            })
+            .ok()
+    }
 }
 
 pub mod u32 {
@@ -246,3 +392,17 @@ pub mod u32 {
         Ok(d.deserialize_option(U32OptionVisitor)?)
     }
 }
+
+pub mod u64 {
+    //! Extensions for parsing [u64] from string types.
+
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    pub fn from_str<'de, D>(d: D) -> Result<u64, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: &str = Deserialize::deserialize(d)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}